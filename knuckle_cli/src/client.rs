@@ -0,0 +1,566 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use futures::{SinkExt, StreamExt};
+use lib_knuckle::{
+    api_interfaces::GameBody,
+    dice::DiceChain,
+    game::HistoryItem,
+    protocol::{negotiate, ClientMessage, ServerMessage, PROTOCOL_VERSION},
+    rules::RuleSet,
+    seed::{self, SeedCommitments, SeedReveal},
+    signing_key_from_string, verifying_key_from_string,
+};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream,
+};
+use uuid::Uuid;
+
+use crate::ClientError;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Talks to one knuckle server over http and websockets
+#[derive(Clone)]
+pub struct Client {
+    server: String,
+    http: reqwest::Client,
+    /// How long to wait for the server before giving up
+    pub timeout: Duration,
+}
+
+/// An account created through `/signup`
+pub struct Player {
+    pub signing_key: SigningKey,
+    pub pub_key: String,
+}
+
+/// Match parameters the server signed for one player
+#[derive(Debug, Clone)]
+pub struct Pairing {
+    pub public_key: String,
+    pub partner_key: String,
+    pub initiator: bool,
+    pub seed: u64,
+    pub signature: String,
+    pub time: u64,
+    pub rules: RuleSet,
+    pub bot: bool,
+    pub commitments: Option<SeedCommitments>,
+    /// Filled in once the seed of a commit-reveal is known
+    pub seed_reveal: Option<SeedReveal>,
+    /// Chain behind our contribution, rolls the dice in hidden dice games
+    pub dice_chain: Option<DiceChain>,
+}
+
+// one frame at a time is read, boxing would only get in the way of matching
+#[allow(clippy::large_enum_variant)]
+pub enum Incoming {
+    Message(ServerMessage),
+    Move(HistoryItem),
+}
+
+/// A websocket that already answered the verify challenge
+pub struct Connection {
+    socket: Socket,
+    verify_time: String,
+    timeout: Duration,
+    /// Dice chain whose anchor we committed to on join
+    dice_chain: Option<DiceChain>,
+}
+
+impl Client {
+    pub fn new(server: &str, timeout: Duration) -> Self {
+        Self {
+            server: server.trim_end_matches('/').to_owned(),
+            http: reqwest::Client::new(),
+            timeout,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.server)
+    }
+
+    pub async fn signup(&self) -> Result<Player, ClientError> {
+        #[derive(Deserialize)]
+        struct Keys {
+            pub_key: String,
+            priv_key: String,
+        }
+        let keys: Keys = self
+            .http
+            .get(self.url("/signup"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(Player {
+            signing_key: signing_key_from_string(&keys.priv_key)
+                .ok_or_else(|| ClientError::Protocol("Invalid private key".into()))?,
+            pub_key: keys.pub_key,
+        })
+    }
+
+    pub async fn connect(&self) -> Result<Connection, ClientError> {
+        let url = match self.server.split_once("://") {
+            Some(("https", rest)) => format!("wss://{rest}/ws"),
+            Some((_, rest)) => format!("ws://{rest}/ws"),
+            None => format!("ws://{}/ws", self.server),
+        };
+        let (socket, _) = connect_async(url).await?;
+        let mut connection = Connection {
+            socket,
+            verify_time: String::new(),
+            timeout: self.timeout,
+            dice_chain: None,
+        };
+        connection.verify_time = match connection.receive().await? {
+            Incoming::Message(ServerMessage::Verify { verify_time }) => verify_time,
+            _ => return Err(ClientError::Protocol("Expected verify".into())),
+        };
+        connection
+            .send(&ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+            })
+            .await?;
+        match connection.receive().await? {
+            Incoming::Message(ServerMessage::Hello { version }) => {
+                negotiate(version).map_err(|e| ClientError::Protocol(e.to_string()))?;
+            }
+            Incoming::Message(message) => {
+                check(message)?;
+                return Err(ClientError::Protocol("Expected hello".into()));
+            }
+            Incoming::Move(_) => {
+                return Err(ClientError::Protocol("Expected hello".into()))
+            }
+        }
+        Ok(connection)
+    }
+
+    pub async fn submit_game(&self, body: &GameBody) -> Result<(), ClientError> {
+        let response = self
+            .http
+            .post(self.url("/submit_game"))
+            .json(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ClientError::Rejected(response.text().await?));
+        }
+        Ok(())
+    }
+}
+
+impl Player {
+    pub fn sign(&self, message: &str) -> String {
+        STANDARD_NO_PAD.encode(self.signing_key.sign(message.as_bytes()).to_bytes())
+    }
+}
+
+impl Pairing {
+    pub fn partner_key(&self) -> Result<VerifyingKey, ClientError> {
+        verifying_key_from_string(&self.partner_key)
+            .ok_or_else(|| ClientError::Protocol("Invalid partner key".into()))
+    }
+
+    /// The body the server expects once this player finished the game
+    pub fn game_body(&self, moves: Vec<HistoryItem>) -> GameBody {
+        GameBody {
+            seed: self.seed,
+            time: self.time,
+            your_key: self.public_key.clone(),
+            opponent_key: self.partner_key.clone(),
+            starting: self.initiator,
+            rules: self.rules,
+            signature: self.signature.clone(),
+            moves,
+            seed_reveal: self.seed_reveal.clone(),
+        }
+    }
+}
+
+impl Connection {
+    /// Joins a queue, the public one when `queue` is unset. Always commits to
+    /// a seed contribution so human opponents share in picking the dice
+    pub async fn join(
+        &mut self,
+        player: &Player,
+        queue: Option<Uuid>,
+        rules: Option<RuleSet>,
+    ) -> Result<(), ClientError> {
+        let dice_chain = DiceChain::new();
+        let message = ClientMessage::Join {
+            pub_key: player.pub_key.clone(),
+            signature: player.sign(&self.verify_time),
+            queue: queue.map(|queue| queue.to_string()),
+            tournament: None,
+            lobby: None,
+            password: None,
+            rules,
+            commitment: Some(seed::commitment(&dice_chain.anchor())?),
+        };
+        self.dice_chain = Some(dice_chain);
+        self.send(&message).await
+    }
+
+    /// Waits until the matchmaker pairs this connection, `wait` replaces the
+    /// usual timeout since queues can take a while
+    pub async fn wait_for_pairing(
+        &mut self,
+        wait: Duration,
+    ) -> Result<Pairing, ClientError> {
+        let pairing: Result<Pairing, ClientError> = tokio::time::timeout(wait, async {
+            loop {
+                match self.next().await? {
+                    Incoming::Message(ServerMessage::Paired {
+                        public_key,
+                        partner_key,
+                        initiator,
+                        seed,
+                        signature,
+                        time,
+                        rules,
+                        bot,
+                        commitments,
+                        ..
+                    }) => {
+                        return Ok(Pairing {
+                            public_key,
+                            partner_key,
+                            initiator,
+                            seed,
+                            signature,
+                            time,
+                            rules,
+                            bot,
+                            commitments,
+                            seed_reveal: None,
+                            dice_chain: None,
+                        })
+                    }
+                    Incoming::Message(message) => check(message)?,
+                    Incoming::Move(_) => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| ClientError::Timeout)?;
+        let pairing = pairing?;
+        match pairing.commitments.clone() {
+            Some(commitments) => self.reveal_seed(pairing, commitments).await,
+            None => Ok(pairing),
+        }
+    }
+
+    /// Reveals the contribution and checks the seed the server derived from
+    /// all three of them
+    async fn reveal_seed(
+        &mut self,
+        mut pairing: Pairing,
+        commitments: SeedCommitments,
+    ) -> Result<Pairing, ClientError> {
+        let dice_chain = self
+            .dice_chain
+            .take()
+            .ok_or_else(|| ClientError::Protocol("Paired without committing".into()))?;
+        let contribution = dice_chain.anchor();
+        let mine = match pairing.initiator {
+            true => &commitments.first,
+            false => &commitments.second,
+        };
+        if seed::commitment(&contribution)? != *mine {
+            return Err(ClientError::Protocol(
+                "Server changed our commitment".into(),
+            ));
+        }
+        self.send(&ClientMessage::Reveal {
+            contribution: contribution.clone(),
+        })
+        .await?;
+        loop {
+            match self.receive().await? {
+                Incoming::Message(ServerMessage::SeedRevealed { seed, reveal }) => {
+                    let ours = match pairing.initiator {
+                        true => &reveal.first,
+                        false => &reveal.second,
+                    };
+                    if *ours != contribution || reveal.verify(&commitments)? != seed {
+                        return Err(ClientError::Protocol("Seed reveal mismatch".into()));
+                    }
+                    pairing.seed = seed;
+                    pairing.seed_reveal = Some(reveal);
+                    pairing.dice_chain = Some(dice_chain);
+                    return Ok(pairing);
+                }
+                Incoming::Message(message) => check(message)?,
+                Incoming::Move(_) => {}
+            }
+        }
+    }
+
+    pub async fn send(&mut self, message: &ClientMessage) -> Result<(), ClientError> {
+        let text = serde_json::to_string(message)?;
+        Ok(self.socket.send(Message::Text(text)).await?)
+    }
+
+    pub async fn send_move(&mut self, item: &HistoryItem) -> Result<(), ClientError> {
+        let data =
+            bincode::serialize(item).map_err(|e| ClientError::Protocol(e.to_string()))?;
+        Ok(self.socket.send(Message::Binary(data)).await?)
+    }
+
+    pub async fn receive(&mut self) -> Result<Incoming, ClientError> {
+        tokio::time::timeout(self.timeout, self.next())
+            .await
+            .map_err(|_| ClientError::Timeout)?
+    }
+
+    async fn next(&mut self) -> Result<Incoming, ClientError> {
+        loop {
+            let message = self.socket.next().await.ok_or(ClientError::Closed)??;
+            match message {
+                Message::Text(text) => {
+                    return Ok(Incoming::Message(serde_json::from_str(&text)?))
+                }
+                Message::Binary(data) => {
+                    let item = bincode::deserialize(&data)
+                        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+                    return Ok(Incoming::Move(item));
+                }
+                Message::Close(_) => return Err(ClientError::Closed),
+                _ => {}
+            }
+        }
+    }
+
+    pub async fn close(mut self) {
+        self.socket.close(None).await.ok();
+    }
+}
+
+/// Turns messages that end the game into errors
+pub fn check(message: ServerMessage) -> Result<(), ClientError> {
+    match message {
+        ServerMessage::PartnerLeft => Err(ClientError::PartnerLeft),
+        ServerMessage::Disconnected { reason, name } => {
+            Err(ClientError::Disconnected(format!("{name}: {reason}")))
+        }
+        ServerMessage::Error { reason, name } => {
+            tracing::warn!("Server ignored a message, {name}: {reason}");
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use lib_knuckle::api_interfaces::IceServers;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+
+    type ServerSocket = WebSocketStream<TcpStream>;
+
+    /// Client for a fake server that accepts one websocket and plays `script`
+    /// on it
+    async fn fake_server<F, Fut>(script: F) -> Client
+    where
+        F: FnOnce(ServerSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            script(accept_async(stream).await.unwrap()).await;
+        });
+        Client::new(&format!("http://{addr}"), Duration::from_secs(5))
+    }
+
+    async fn send(socket: &mut ServerSocket, message: ServerMessage) {
+        let text = serde_json::to_string(&message).unwrap();
+        socket.send(Message::Text(text)).await.unwrap();
+    }
+
+    async fn receive(socket: &mut ServerSocket) -> ClientMessage {
+        loop {
+            if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                return ClientMessage::parse(&text).unwrap();
+            }
+        }
+    }
+
+    async fn greet(socket: &mut ServerSocket, version: u32) {
+        send(
+            socket,
+            ServerMessage::Verify {
+                verify_time: "1".to_owned(),
+            },
+        )
+        .await;
+        assert_eq!(
+            receive(socket).await,
+            ClientMessage::Hello {
+                version: PROTOCOL_VERSION
+            }
+        );
+        send(socket, ServerMessage::Hello { version }).await;
+    }
+
+    fn player() -> Player {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        Player {
+            pub_key: STANDARD_NO_PAD.encode(signing_key.verifying_key().to_bytes()),
+            signing_key,
+        }
+    }
+
+    fn paired(commitments: Option<SeedCommitments>) -> ServerMessage {
+        ServerMessage::Paired {
+            public_key: player().pub_key,
+            partner_key: player().pub_key,
+            initiator: true,
+            seed: 0,
+            signature: String::new(),
+            time: 0,
+            ice_servers: IceServers {
+                urls: Vec::new(),
+                username: None,
+                credential: None,
+            },
+            rules: RuleSet::default(),
+            bot: false,
+            commitments,
+        }
+    }
+
+    /// Pairs the client with commitments, answers the reveal with the seed
+    /// `tamper` makes of the real one
+    async fn commit_reveal(mut socket: ServerSocket, tamper: fn(u64) -> u64) {
+        greet(&mut socket, PROTOCOL_VERSION).await;
+        let ClientMessage::Join {
+            commitment: Some(first),
+            ..
+        } = receive(&mut socket).await
+        else {
+            panic!("Expected a join with a commitment");
+        };
+        let (server, second) = (seed::new_contribution(), seed::new_contribution());
+        let commitments = SeedCommitments {
+            server: seed::commitment(&server).unwrap(),
+            first,
+            second: seed::commitment(&second).unwrap(),
+        };
+        send(&mut socket, paired(Some(commitments.clone()))).await;
+        let ClientMessage::Reveal { contribution } = receive(&mut socket).await else {
+            panic!("Expected a reveal");
+        };
+        // rejected messages do not end the wait
+        send(
+            &mut socket,
+            ServerMessage::Error {
+                reason: "Not now".to_owned(),
+                name: "BadRequest".to_owned(),
+            },
+        )
+        .await;
+        let reveal = SeedReveal {
+            server,
+            first: contribution,
+            second,
+        };
+        let seed = tamper(reveal.verify(&commitments).unwrap());
+        send(&mut socket, ServerMessage::SeedRevealed { seed, reveal }).await;
+        socket.next().await;
+    }
+
+    #[tokio::test]
+    async fn test_connect_negotiates_version() {
+        let client = fake_server(|mut socket| async move {
+            greet(&mut socket, PROTOCOL_VERSION).await;
+            socket.next().await;
+        })
+        .await;
+        assert!(client.connect().await.is_ok());
+
+        let client = fake_server(|mut socket| async move {
+            greet(&mut socket, 0).await;
+            socket.next().await;
+        })
+        .await;
+        assert!(matches!(
+            client.connect().await,
+            Err(ClientError::Protocol(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_commit_reveal_pairing() {
+        let client = fake_server(|socket| commit_reveal(socket, |seed| seed)).await;
+        let mut connection = client.connect().await.unwrap();
+        connection.join(&player(), None, None).await.unwrap();
+        let pairing = connection
+            .wait_for_pairing(Duration::from_secs(5))
+            .await
+            .unwrap();
+        let reveal = pairing.seed_reveal.as_ref().unwrap();
+        assert_eq!(reveal.seed(), Ok(pairing.seed));
+        assert!(pairing.dice_chain.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tampered_seed_rejected() {
+        let client = fake_server(|socket| commit_reveal(socket, |seed| seed + 1)).await;
+        let mut connection = client.connect().await.unwrap();
+        connection.join(&player(), None, None).await.unwrap();
+        assert!(matches!(
+            connection.wait_for_pairing(Duration::from_secs(5)).await,
+            Err(ClientError::Protocol(reason)) if reason == "Seed reveal mismatch"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ending_messages() {
+        let client = fake_server(|mut socket| async move {
+            greet(&mut socket, PROTOCOL_VERSION).await;
+            send(&mut socket, ServerMessage::PartnerLeft).await;
+            socket.next().await;
+        })
+        .await;
+        let mut connection = client.connect().await.unwrap();
+        assert!(matches!(
+            connection.wait_for_pairing(Duration::from_secs(5)).await,
+            Err(ClientError::PartnerLeft)
+        ));
+
+        let client = fake_server(|mut socket| async move {
+            greet(&mut socket, PROTOCOL_VERSION).await;
+            send(
+                &mut socket,
+                ServerMessage::Disconnected {
+                    reason: "Bye".to_owned(),
+                    name: "Kicked".to_owned(),
+                },
+            )
+            .await;
+            socket.next().await;
+        })
+        .await;
+        let mut connection = client.connect().await.unwrap();
+        assert!(matches!(
+            connection.wait_for_pairing(Duration::from_secs(5)).await,
+            Err(ClientError::Disconnected(reason)) if reason == "Kicked: Bye"
+        ));
+        connection.close().await;
+    }
+}
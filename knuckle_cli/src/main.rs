@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use client::Client;
+use lib_knuckle::{ai::Strategy, error::GameError, rules::RuleSet};
+use play::{play_loopback, play_relayed, GameReport, PlayOptions};
+use swarm::Transport;
+use thiserror::Error;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use uuid::Uuid;
+
+pub mod client;
+pub mod play;
+pub mod swarm;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Websocket failed: {0}")]
+    Socket(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("Invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Game(#[from] GameError),
+    #[error("Unexpected message: {0}")]
+    Protocol(String),
+    #[error("Server rejected the game: {0}")]
+    Rejected(String),
+    #[error("Server disconnected us: {0}")]
+    Disconnected(String),
+    #[error("Partner left")]
+    PartnerLeft,
+    #[error("Connection closed")]
+    Closed,
+    #[error("Timed out waiting for the server")]
+    Timeout,
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for ClientError {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::Socket(Box::new(error))
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum StrategyArg {
+    Random,
+    Greedy,
+    Expectimax,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Headless knucklebones client for playing and load testing")]
+struct Args {
+    #[clap(long, env = "KNUCKLE_SERVER", default_value = "http://localhost:8083")]
+    server: String,
+    #[clap(long, value_enum, default_value_t = StrategyArg::Random)]
+    strategy: StrategyArg,
+    /// Moves the expectimax strategy looks ahead
+    #[clap(long, default_value_t = 2)]
+    depth: u8,
+    /// Milliseconds to wait before every move
+    #[clap(long, default_value_t = 0)]
+    think_ms: u64,
+    /// Seconds to stay in the queue before giving up
+    #[clap(long, default_value_t = 60)]
+    queue_wait_secs: u64,
+    /// Seconds to wait for any other message from the server
+    #[clap(long, default_value_t = 60)]
+    timeout_secs: u64,
+    /// Rules to ask for in the `3x3d6:knucklebones:same_column` form, the queue
+    /// decides when unset
+    #[clap(long, value_parser = RuleSet::decode)]
+    rules: Option<RuleSet>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Plays against whoever the matchmaker pairs us with, moves go through
+    /// the server relay
+    Play {
+        #[clap(long, default_value_t = 1)]
+        games: u32,
+        /// Private queue to join instead of the public one
+        #[clap(long)]
+        queue: Option<Uuid>,
+    },
+    /// Pairs two local bots in a private queue and submits their game
+    Loopback {
+        #[clap(long, default_value_t = 1)]
+        games: u32,
+    },
+    /// Runs many bots at once to load test matchmaking and game submission
+    Swarm {
+        #[clap(long, default_value_t = 10)]
+        bots: u32,
+        /// Games every bot plays one after another
+        #[clap(long, default_value_t = 10)]
+        games: u32,
+        #[clap(long, value_enum, default_value_t = Transport::Loopback)]
+        transport: Transport,
+        /// Queue the relay transport joins instead of the public one
+        #[clap(long)]
+        queue: Option<Uuid>,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                format!("error,{}=info", env!("CARGO_CRATE_NAME")).into()
+            }),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let args = Args::parse();
+
+    let client = Client::new(&args.server, Duration::from_secs(args.timeout_secs));
+    let options = PlayOptions {
+        strategy: match args.strategy {
+            StrategyArg::Random => Strategy::Random,
+            StrategyArg::Greedy => Strategy::Greedy,
+            StrategyArg::Expectimax => Strategy::Expectimax { depth: args.depth },
+        },
+        think_time: Duration::from_millis(args.think_ms),
+        queue_wait: Duration::from_secs(args.queue_wait_secs),
+        rules: args.rules,
+    };
+
+    let result = match args.command {
+        Command::Play { games, queue } => play(&client, games, queue, &options).await,
+        Command::Loopback { games } => loopback(&client, games, &options).await,
+        Command::Swarm {
+            bots,
+            games,
+            transport,
+            queue,
+        } => {
+            let summary =
+                swarm::run(&client, bots, games, transport, queue, &options).await;
+            println!("{summary}");
+            match summary.failed() {
+                0 => Ok(()),
+                _ => std::process::exit(1),
+            }
+        }
+    };
+    if let Err(e) = result {
+        tracing::error!("{e}");
+        std::process::exit(1);
+    }
+}
+
+async fn play(
+    client: &Client,
+    games: u32,
+    queue: Option<Uuid>,
+    options: &PlayOptions,
+) -> Result<(), ClientError> {
+    let player = client.signup().await?;
+    tracing::info!("Signed up as {}", player.pub_key);
+    for _ in 0..games {
+        let report = play_relayed(client, &player, queue, options).await?;
+        log_report(&report);
+    }
+    Ok(())
+}
+
+async fn loopback(
+    client: &Client,
+    games: u32,
+    options: &PlayOptions,
+) -> Result<(), ClientError> {
+    let first = client.signup().await?;
+    let second = client.signup().await?;
+    for _ in 0..games {
+        let report = play_loopback(client, (&first, &second), options).await?;
+        log_report(&report);
+    }
+    Ok(())
+}
+
+fn log_report(report: &GameReport) {
+    let result = match &report.end {
+        end if end.win_by_tie => "tie",
+        end if end.winner => "win",
+        _ => "loss",
+    };
+    tracing::info!(
+        "{result} after {} moves, queued {:?}, played {:?}, submitted in {:?}",
+        report.moves,
+        report.queue_time,
+        report.game_time,
+        report.submit_time
+    );
+}
@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use lib_knuckle::{
+    ai::{Ai, Strategy},
+    game::{Game, GameEnd, ServerGameInfo},
+    keys::Keys,
+    protocol::ClientMessage,
+    rules::RuleSet,
+};
+use uuid::Uuid;
+
+use crate::{
+    client::{check, Client, Incoming, Pairing, Player},
+    ClientError,
+};
+
+/// How a bot picks and plays its games
+#[derive(Debug, Clone, Copy)]
+pub struct PlayOptions {
+    pub strategy: Strategy,
+    /// Pause before every move, keeps games against people watchable
+    pub think_time: Duration,
+    /// How long to stay in the queue before giving up
+    pub queue_wait: Duration,
+    pub rules: Option<RuleSet>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameReport {
+    pub queue_time: Duration,
+    pub game_time: Duration,
+    /// Only set when the client had to submit the game itself
+    pub submit_time: Option<Duration>,
+    pub moves: usize,
+    pub end: GameEnd,
+}
+
+/// Queues up and plays one game with moves sent through the server relay,
+/// works against other bots, the server bot and the web client
+pub async fn play_relayed(
+    client: &Client,
+    player: &Player,
+    queue: Option<Uuid>,
+    options: &PlayOptions,
+) -> Result<GameReport, ClientError> {
+    let mut connection = client.connect().await?;
+    connection.join(player, queue, options.rules).await?;
+    let queued = Instant::now();
+    let pairing = connection.wait_for_pairing(options.queue_wait).await?;
+    let queue_time = queued.elapsed();
+    tracing::debug!("Paired with {}", pairing.partner_key);
+
+    if !pairing.bot {
+        // tells a web client on the other side to stop waiting for WebRTC
+        connection.send(&ClientMessage::Relay).await?;
+    }
+
+    let started = Instant::now();
+    let mut game = new_game(player, &pairing)?;
+    let mut ai = Ai::new(options.strategy);
+    loop {
+        while let Some(x) = ai.choose_column(&game.get_board_data()) {
+            if !options.think_time.is_zero() {
+                tokio::time::sleep(options.think_time).await;
+            }
+            let item = game.place(x)?;
+            connection.send_move(&item).await?;
+        }
+        if game.is_completed() {
+            break;
+        }
+        match connection.receive().await? {
+            Incoming::Move(item) => game.add_opponent_move(item)?,
+            Incoming::Message(message) => check(message)?,
+        }
+    }
+    let game_time = started.elapsed();
+
+    // the server stores relayed and bot games itself
+    connection.close().await;
+
+    Ok(GameReport {
+        queue_time,
+        game_time,
+        submit_time: None,
+        moves: game.history().len(),
+        end: game.get_board_data().winner,
+    })
+}
+
+/// Pairs two local players through a private queue and plays their game in
+/// memory, the initiator submits it like a WebRTC game would be
+pub async fn play_loopback(
+    client: &Client,
+    (first, second): (&Player, &Player),
+    options: &PlayOptions,
+) -> Result<GameReport, ClientError> {
+    let queue = Uuid::new_v4();
+    let mut first_connection = client.connect().await?;
+    let mut second_connection = client.connect().await?;
+    first_connection
+        .join(first, Some(queue), options.rules)
+        .await?;
+    second_connection
+        .join(second, Some(queue), options.rules)
+        .await?;
+    let queued = Instant::now();
+    let first_pairing = first_connection
+        .wait_for_pairing(options.queue_wait)
+        .await?;
+    let second_pairing = second_connection
+        .wait_for_pairing(options.queue_wait)
+        .await?;
+    let queue_time = queued.elapsed();
+    if first_pairing.partner_key != second.pub_key {
+        return Err(ClientError::Protocol(
+            "Paired with someone outside the private queue".into(),
+        ));
+    }
+
+    let started = Instant::now();
+    let ((initiator, initiator_pairing), (other, other_pairing)) =
+        match first_pairing.initiator {
+            true => ((first, first_pairing), (second, second_pairing)),
+            false => ((second, second_pairing), (first, first_pairing)),
+        };
+    let mut players = [
+        new_game(initiator, &initiator_pairing)?,
+        new_game(other, &other_pairing)?,
+    ];
+    let mut ais = [Ai::new(options.strategy), Ai::new(options.strategy)];
+    let mut turn = 0;
+    while !players[0].is_completed() {
+        let (current, next) = (turn % 2, (turn + 1) % 2);
+        match ais[current].choose_column(&players[current].get_board_data()) {
+            Some(x) => {
+                let item = players[current].place(x)?;
+                players[next].add_opponent_move(item)?;
+            }
+            None => turn += 1,
+        }
+    }
+    let game_time = started.elapsed();
+
+    let submitted = Instant::now();
+    client
+        .submit_game(&initiator_pairing.game_body(players[0].history().to_vec()))
+        .await?;
+    let submit_time = submitted.elapsed();
+    first_connection.close().await;
+    second_connection.close().await;
+
+    Ok(GameReport {
+        queue_time,
+        game_time,
+        submit_time: Some(submit_time),
+        moves: players[0].history().len(),
+        end: players[0].get_board_data().winner,
+    })
+}
+
+fn new_game(player: &Player, pairing: &Pairing) -> Result<Game, ClientError> {
+    let mut game = Game::new(
+        Keys::Sign {
+            my_keys: player.signing_key.clone(),
+            other_keys: pairing.partner_key()?,
+        },
+        pairing.rules,
+        ServerGameInfo::new(pairing.seed, pairing.initiator)
+            .with_time(pairing.time)
+            .with_reveal(pairing.seed_reveal.as_ref()),
+    )?;
+    if let Some(dice_chain) = pairing.dice_chain.clone() {
+        game.set_dice_chain(dice_chain);
+    }
+    Ok(game)
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::{
+    client::Client,
+    play::{play_loopback, play_relayed, GameReport, PlayOptions},
+    ClientError,
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Transport {
+    /// Every bot queues on its own and sends its moves through the server
+    /// relay, the server stores the games
+    Relay,
+    /// Bots are paired up locally and submit their games, so there are half
+    /// as many games at once as bots
+    Loopback,
+}
+
+/// Timings and errors of a whole swarm run
+#[derive(Debug, Default)]
+pub struct Summary {
+    elapsed: Duration,
+    reports: Vec<GameReport>,
+    errors: BTreeMap<String, u32>,
+}
+
+/// Runs `bots` bots at once until each of them played `games` games, a
+/// failed game does not stop the bot
+pub async fn run(
+    client: &Client,
+    bots: u32,
+    games: u32,
+    transport: Transport,
+    queue: Option<Uuid>,
+    options: &PlayOptions,
+) -> Summary {
+    let started = Instant::now();
+    let tasks = match transport {
+        Transport::Relay => bots,
+        Transport::Loopback => bots.div_ceil(2),
+    };
+    let mut set = JoinSet::new();
+    for _ in 0..tasks {
+        let (client, options) = (client.clone(), *options);
+        set.spawn(async move {
+            let mut results = Vec::new();
+            let players = match transport {
+                Transport::Relay => client.signup().await.map(|player| (player, None)),
+                Transport::Loopback => {
+                    match (client.signup().await, client.signup().await) {
+                        (Ok(first), Ok(second)) => Ok((first, Some(second))),
+                        (Err(e), _) | (_, Err(e)) => Err(e),
+                    }
+                }
+            };
+            let (first, second) = match players {
+                Ok(players) => players,
+                Err(e) => return vec![Err(e)],
+            };
+            for _ in 0..games {
+                let result = match &second {
+                    Some(second) => {
+                        play_loopback(&client, (&first, second), &options).await
+                    }
+                    None => play_relayed(&client, &first, queue, &options).await,
+                };
+                if let Err(e) = &result {
+                    tracing::warn!("Game failed: {e}");
+                }
+                results.push(result);
+            }
+            results
+        });
+    }
+
+    let mut summary = Summary::default();
+    while let Some(results) = set.join_next().await {
+        for result in results.unwrap_or_default() {
+            summary.add(result);
+        }
+    }
+    summary.elapsed = started.elapsed();
+    summary
+}
+
+impl Summary {
+    fn add(&mut self, result: Result<GameReport, ClientError>) {
+        match result {
+            Ok(report) => self.reports.push(report),
+            Err(e) => *self.errors.entry(e.to_string()).or_default() += 1,
+        }
+    }
+
+    pub fn failed(&self) -> u32 {
+        self.errors.values().sum()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let finished = self.reports.len();
+        writeln!(
+            f,
+            "{finished} games finished, {} failed in {:.1?} ({:.2} games/s)",
+            self.failed(),
+            self.elapsed,
+            finished as f64 / self.elapsed.as_secs_f64()
+        )?;
+        let timings: [(_, Vec<_>); 3] = [
+            ("queue", self.reports.iter().map(|r| r.queue_time).collect()),
+            ("game", self.reports.iter().map(|r| r.game_time).collect()),
+            (
+                "submit",
+                self.reports.iter().filter_map(|r| r.submit_time).collect(),
+            ),
+        ];
+        for (name, mut times) in timings {
+            if let Some(line) = percentiles(&mut times) {
+                writeln!(f, "{name:>8}: {line}")?;
+            }
+        }
+        for (error, count) in &self.errors {
+            writeln!(f, "{count:>8}x {error}")?;
+        }
+        Ok(())
+    }
+}
+
+fn percentiles(times: &mut [Duration]) -> Option<String> {
+    if times.is_empty() {
+        return None;
+    }
+    times.sort();
+    let at = |p: usize| times[(times.len() - 1) * p / 100];
+    Some(format!(
+        "p50 {:.1?}, p95 {:.1?}, max {:.1?}",
+        at(50),
+        at(95),
+        at(100)
+    ))
+}
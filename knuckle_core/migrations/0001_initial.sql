@@ -0,0 +1,67 @@
+-- Schema from before migrations existed, everything is IF NOT EXISTS so
+-- databases set up by the old init_db are adopted as is
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+CREATE TABLE IF NOT EXISTS players (
+    player_id UUID PRIMARY KEY,
+    public_key BYTEA NOT NULL,
+    secret_key BYTEA NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (public_key)
+);
+
+CREATE TABLE IF NOT EXISTS started_matches (
+    match_id UUID PRIMARY KEY,
+    seed BIGINT NOT NULL,
+    time BIGINT NOT NULL,
+    player1 UUID NOT NULL,
+    player2 UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    FOREIGN KEY (player1) REFERENCES players(player_id),
+    FOREIGN KEY (player2) REFERENCES players(player_id),
+    UNIQUE (seed, time)
+);
+
+CREATE TABLE IF NOT EXISTS matches (
+    match_id UUID PRIMARY KEY,
+    seed BIGINT NOT NULL,
+    time BIGINT NOT NULL,
+    player1 UUID NOT NULL,
+    player2 UUID NOT NULL,
+    winner UUID,
+    result TEXT NOT NULL,
+    points_p1 SMALLINT NOT NULL,
+    points_p2 SMALLINT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    FOREIGN KEY (player1) REFERENCES players(player_id),
+    FOREIGN KEY (player2) REFERENCES players(player_id),
+    FOREIGN KEY (winner) REFERENCES players(player_id),
+    UNIQUE (seed, time)
+);
+
+CREATE TABLE IF NOT EXISTS moves (
+    match_id UUID NOT NULL,
+    player_id UUID NOT NULL,
+    number SMALLINT NOT NULL,
+    x SMALLINT NOT NULL,
+    seq INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    UNIQUE (match_id, seq),
+    FOREIGN KEY (match_id) REFERENCES matches(match_id),
+    FOREIGN KEY (player_id) REFERENCES players(player_id)
+);
+
+CREATE TABLE IF NOT EXISTS queue_times (
+    queue_time INTEGER NOT NULL,
+    queue_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- the other tables have unique keys without their time column so they can not
+-- be hypertables
+SELECT create_hypertable('queue_times', by_range('created_at'), if_not_exists => TRUE, migrate_data => TRUE);
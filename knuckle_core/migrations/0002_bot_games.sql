@@ -0,0 +1,3 @@
+ALTER TABLE players ADD COLUMN IF NOT EXISTS is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE started_matches ADD COLUMN IF NOT EXISTS bot_game BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE matches ADD COLUMN IF NOT EXISTS bot_game BOOLEAN NOT NULL DEFAULT FALSE;
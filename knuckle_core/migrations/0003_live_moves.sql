@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS live_moves (
+    match_id UUID NOT NULL,
+    seq INT NOT NULL,
+    item BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    UNIQUE (match_id, seq)
+);
@@ -0,0 +1 @@
+ALTER TABLE matches ADD COLUMN IF NOT EXISTS rules TEXT NOT NULL DEFAULT '3x3d6:knucklebones:same_column';
@@ -0,0 +1,18 @@
+-- one row per player and match, the leaderboard aggregates read from here
+CREATE TABLE IF NOT EXISTS match_players (
+    match_id UUID NOT NULL,
+    player_id UUID NOT NULL,
+    points SMALLINT NOT NULL,
+    won BOOLEAN NOT NULL,
+    bot_game BOOLEAN NOT NULL DEFAULT FALSE,
+    completed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+SELECT create_hypertable('match_players', by_range('completed_at'), if_not_exists => TRUE, migrate_data => TRUE);
+
+INSERT INTO match_players (match_id, player_id, points, won, bot_game, completed_at)
+SELECT match_id, player1, points_p1, winner IS NOT DISTINCT FROM player1, bot_game, completed_at FROM matches
+WHERE NOT EXISTS (SELECT 1 FROM match_players)
+UNION ALL
+SELECT match_id, player2, points_p2, winner IS NOT DISTINCT FROM player2, bot_game, completed_at FROM matches
+WHERE NOT EXISTS (SELECT 1 FROM match_players);
@@ -0,0 +1,16 @@
+-- no-transaction
+-- continuous aggregates can not be created inside a transaction
+CREATE MATERIALIZED VIEW IF NOT EXISTS player_daily_stats
+WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+SELECT
+    time_bucket('1 day', completed_at) AS day,
+    player_id,
+    SUM(points) AS points,
+    COUNT(*) AS games,
+    SUM(CASE WHEN won THEN 1 ELSE 0 END) AS wins
+FROM match_players
+WHERE NOT bot_game
+GROUP BY day, player_id
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('player_daily_stats', start_offset => NULL, end_offset => INTERVAL '1 hour', schedule_interval => INTERVAL '1 hour', if_not_exists => TRUE);
@@ -0,0 +1,7 @@
+-- rematches share the series of the match they followed, a series is named
+-- after its first match
+ALTER TABLE started_matches ADD COLUMN IF NOT EXISTS series_id UUID;
+ALTER TABLE matches ADD COLUMN IF NOT EXISTS series_id UUID;
+UPDATE started_matches SET series_id = match_id WHERE series_id IS NULL;
+UPDATE matches SET series_id = match_id WHERE series_id IS NULL;
+CREATE INDEX IF NOT EXISTS matches_series_id_idx ON matches (series_id);
@@ -0,0 +1,91 @@
+-- Same tables as the Postgres schema, uuids are stored as blobs and every
+-- timestamp as unix millis
+CREATE TABLE players (
+    player_id BLOB PRIMARY KEY,
+    public_key BLOB NOT NULL UNIQUE,
+    secret_key BLOB NOT NULL,
+    name TEXT NOT NULL,
+    is_bot INTEGER NOT NULL DEFAULT 0,
+    rating REAL NOT NULL DEFAULT 1500,
+    rating_deviation REAL NOT NULL DEFAULT 350,
+    rating_volatility REAL NOT NULL DEFAULT 0.06,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE started_matches (
+    match_id BLOB PRIMARY KEY,
+    seed INTEGER NOT NULL,
+    time INTEGER NOT NULL,
+    player1 BLOB NOT NULL REFERENCES players(player_id),
+    player2 BLOB NOT NULL REFERENCES players(player_id),
+    bot_game INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+
+    UNIQUE (seed, time)
+);
+
+CREATE TABLE matches (
+    match_id BLOB PRIMARY KEY,
+    seed INTEGER NOT NULL,
+    time INTEGER NOT NULL,
+    player1 BLOB NOT NULL REFERENCES players(player_id),
+    player2 BLOB NOT NULL REFERENCES players(player_id),
+    winner BLOB REFERENCES players(player_id),
+    result TEXT NOT NULL,
+    points_p1 INTEGER NOT NULL,
+    points_p2 INTEGER NOT NULL,
+    bot_game INTEGER NOT NULL DEFAULT 0,
+    rules TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    completed_at INTEGER NOT NULL,
+
+    UNIQUE (seed, time)
+);
+
+CREATE TABLE moves (
+    match_id BLOB NOT NULL REFERENCES matches(match_id),
+    player_id BLOB NOT NULL REFERENCES players(player_id),
+    number INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+
+    UNIQUE (match_id, seq)
+);
+
+CREATE TABLE live_moves (
+    match_id BLOB NOT NULL,
+    seq INTEGER NOT NULL,
+    item BLOB NOT NULL,
+
+    UNIQUE (match_id, seq)
+);
+
+CREATE TABLE match_players (
+    match_id BLOB NOT NULL REFERENCES matches(match_id),
+    player_id BLOB NOT NULL REFERENCES players(player_id),
+    points INTEGER NOT NULL,
+    won INTEGER NOT NULL,
+    bot_game INTEGER NOT NULL DEFAULT 0,
+    completed_at INTEGER NOT NULL
+);
+
+CREATE INDEX match_players_completed_at ON match_players (completed_at);
+
+CREATE TABLE rating_history (
+    match_id BLOB NOT NULL,
+    player_id BLOB NOT NULL REFERENCES players(player_id),
+    rating_before REAL NOT NULL,
+    rating_after REAL NOT NULL,
+    deviation_before REAL NOT NULL,
+    deviation_after REAL NOT NULL,
+    volatility REAL NOT NULL,
+
+    UNIQUE (match_id, player_id)
+);
+
+CREATE TABLE queue_times (
+    queue_time INTEGER NOT NULL,
+    queue_id BLOB NOT NULL,
+    created_at INTEGER NOT NULL
+);
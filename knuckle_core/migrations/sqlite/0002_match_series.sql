@@ -0,0 +1,7 @@
+-- rematches share the series of the match they followed, a series is named
+-- after its first match
+ALTER TABLE started_matches ADD COLUMN series_id BLOB;
+ALTER TABLE matches ADD COLUMN series_id BLOB;
+UPDATE started_matches SET series_id = match_id WHERE series_id IS NULL;
+UPDATE matches SET series_id = match_id WHERE series_id IS NULL;
+CREATE INDEX matches_series_id_idx ON matches (series_id);
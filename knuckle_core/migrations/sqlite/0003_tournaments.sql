@@ -0,0 +1,7 @@
+-- brackets are kept in memory while they are played, the stored state is
+-- only read back on startup
+CREATE TABLE IF NOT EXISTS tournaments (
+    tournament_id BLOB PRIMARY KEY,
+    state TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
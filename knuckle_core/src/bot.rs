@@ -0,0 +1,244 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::extract::ws::Message;
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use ed25519_dalek::{Signer, SigningKey};
+use lib_knuckle::{
+    ai::{Ai, Strategy},
+    api_interfaces::{GameBody, IceServers},
+    error::GameError,
+    game::{Game, HistoryItem, ServerGameInfo},
+    keys::Keys,
+    protocol::ServerMessage,
+    verifying_key_from_string,
+};
+use rand_core::{OsRng, RngCore};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::{
+    routes::{save_abandoned, save_game, send_error, ToTextMessage},
+    store::{SharedStore, StartedMatch},
+    AppState, UserCreateError,
+};
+
+/// Delay before the bot answers a move, instant replies feel off
+const THINK_TIME: Duration = Duration::from_millis(600);
+
+#[derive(Debug)]
+pub struct BotPlayer {
+    player_id: Uuid,
+    signing_key: SigningKey,
+    wait: Duration,
+    strategy: Strategy,
+}
+
+pub type BotData = Option<Arc<BotPlayer>>;
+
+impl BotPlayer {
+    /// Loads the bot from the players table, registering it on first start
+    pub async fn load_or_create(
+        store: &SharedStore,
+        wait: Duration,
+    ) -> Result<Self, UserCreateError> {
+        let (player_id, signing_key) = match store.bot_player().await? {
+            Some((player_id, secret_key)) => {
+                let secret_key = secret_key.try_into().map_err(|_| {
+                    UserCreateError::Internal("Invalid bot key".to_owned())
+                })?;
+                (player_id, SigningKey::from_bytes(&secret_key))
+            }
+            None => {
+                let player_id = Uuid::new_v7(Timestamp::now(NoContext));
+                let signing_key = SigningKey::generate(&mut OsRng);
+                store
+                    .create_player(
+                        player_id,
+                        signing_key.verifying_key().to_bytes().to_vec(),
+                        signing_key.to_bytes().to_vec(),
+                        String::from("Bot"),
+                        true,
+                    )
+                    .await?;
+                (player_id, signing_key)
+            }
+        };
+
+        Ok(Self {
+            player_id,
+            signing_key,
+            wait,
+            strategy: Strategy::default(),
+        })
+    }
+
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    fn pub_key(&self) -> String {
+        STANDARD_NO_PAD.encode(self.signing_key.verifying_key().to_bytes())
+    }
+
+    /// Pairs a player that waited too long in the queue with the bot, the
+    /// player always starts and moves are relayed over their websocket
+    pub async fn start_game(
+        &self,
+        state: &AppState,
+        user_id: Uuid,
+    ) -> Result<(), UserCreateError> {
+        let user = state
+            .get_user_clone(&user_id)
+            .ok_or_else(|| UserCreateError::Internal("User left the queue".to_owned()))?;
+        let user_pub_key = user.pub_key.clone().ok_or_else(|| {
+            UserCreateError::Internal("User pub_key not set".to_owned())
+        })?;
+        let user_verify = verifying_key_from_string(&user_pub_key)
+            .ok_or(UserCreateError::InvalidSignature)?;
+        let player_id = user.player_id.ok_or_else(|| {
+            UserCreateError::Internal("User player_id not set".to_owned())
+        })?;
+        let bot_pub_key = self.pub_key();
+
+        let seed = OsRng.next_u32() as u64;
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let game_signature = STANDARD_NO_PAD.encode(
+            state
+                .dice_seed_signing_keys
+                .lock()
+                .await
+                .sign(
+                    format!(
+                        "{seed}:{time}:{}:{}:{}",
+                        user_pub_key,
+                        bot_pub_key,
+                        user.rules.encode()
+                    )
+                    .as_bytes(),
+                )
+                .to_bytes(),
+        );
+
+        let match_id = Uuid::new_v7(Timestamp::now(NoContext));
+        state
+            .store
+            .start_match(StartedMatch {
+                match_id,
+                seed,
+                time,
+                players: (player_id, self.player_id),
+                bot_game: true,
+                created_at: SystemTime::now(),
+                series_id: match_id,
+            })
+            .await?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        state
+            .all_users
+            .update_async(&user_id, |_, item| {
+                item.set_bot_sender(tx);
+            })
+            .await;
+
+        user.sender.send(
+            ServerMessage::Paired {
+                public_key: user_pub_key.clone(),
+                partner_key: bot_pub_key.clone(),
+                initiator: true,
+                seed,
+                signature: game_signature.clone(),
+                time,
+                ice_servers: IceServers::default(),
+                rules: user.rules,
+                bot: true,
+                commitments: None,
+            }
+            .to_text_message()?,
+        )?;
+        state
+            .metrics
+            .paired("bot", &[user.in_queue_since.elapsed()]);
+
+        let mut game = Game::new(
+            Keys::Sign {
+                my_keys: self.signing_key.clone(),
+                other_keys: user_verify,
+            },
+            user.rules,
+            ServerGameInfo::new(seed, false).with_time(time),
+        )?;
+        let ai = Ai::new(self.strategy);
+        // the bot stores the game itself, a losing player could just never
+        // submit it
+        let body = GameBody {
+            seed,
+            time,
+            your_key: bot_pub_key,
+            opponent_key: user_pub_key.clone(),
+            starting: false,
+            rules: user.rules,
+            signature: game_signature,
+            moves: Vec::new(),
+            seed_reveal: None,
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = play_game(&mut game, ai, user.sender, rx).await {
+                tracing::debug!("Bot game ended early: {e:?}");
+            }
+            let body = GameBody {
+                moves: game.history().to_vec(),
+                ..body
+            };
+            // leaving a game that is going badly still counts as a loss
+            let saved = match game.is_completed() {
+                true => save_game(&state, body).await,
+                false => save_abandoned(&state, body, &user_pub_key).await,
+            };
+            if let Err(e) = saved {
+                tracing::error!("Failed saving bot game: {e:?}");
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Plays until the game is over or the player left
+async fn play_game(
+    game: &mut Game,
+    mut ai: Ai,
+    sender: UnboundedSender<Message>,
+    mut moves: UnboundedReceiver<Vec<u8>>,
+) -> Result<(), UserCreateError> {
+    loop {
+        while let Some(x) = ai.choose_column(&game.get_board_data()) {
+            tokio::time::sleep(THINK_TIME).await;
+            let item = game.place(x)?;
+            let data = bincode::serialize(&item)
+                .map_err(|e| UserCreateError::Internal(e.to_string()))?;
+            sender.send(Message::Binary(data))?;
+        }
+        if game.is_completed() {
+            return Ok(());
+        }
+
+        // the channel closes once the player disconnects
+        let Some(data) = moves.recv().await else {
+            return Ok(());
+        };
+        let played = bincode::deserialize::<HistoryItem>(&data)
+            .map_err(|e| GameError::Decode {
+                reason: e.to_string(),
+            })
+            .and_then(|item| game.add_opponent_move(item));
+        // the bot keeps waiting for a valid move
+        if let Err(e) = played {
+            send_error(&sender, &e.into())?;
+        }
+    }
+}
@@ -1,112 +1,194 @@
-use crate::{pool_extractor::Conn, UserCreateError};
+use sha2::{Digest, Sha256};
+use tokio_postgres::Client;
 
-pub async fn init_db(conn: Conn) -> Result<(), UserCreateError> {
-    conn.simple_query(
-        /* language=postgresql */
-        "
-      CREATE TABLE players (
-            player_id UUID PRIMARY KEY,
-            public_key BYTEA NOT NULL,
-            secret_key BYTEA NOT NULL,
-            name TEXT NOT NULL,
-            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-            UNIQUE (public_key)
-        );
+use crate::UserCreateError;
 
+/// First line of a migration that has to run outside of a transaction, its
+/// statements then run one by one
+const NO_TRANSACTION: &str = "-- no-transaction";
+/// Keeps two servers starting at the same time from migrating concurrently
+const MIGRATION_LOCK: i64 = 0x6b6e75636b6c65;
 
-    ",
-    )
-    .await
-    .ok();
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub(crate) sql: &'static str,
+}
 
-    conn.simple_query(
-        /* language=postgresql */
-        "
-  CREATE TABLE started_matches (
-            match_id UUID PRIMARY KEY,
-            seed BIGINT NOT NULL,
-            time BIGINT NOT NULL,
-            player1 UUID NOT NULL,
-            player2 UUID NOT NULL,
-            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+    (sqlite $version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/sqlite/", $name, ".sql")),
+        }
+    };
+}
 
-            FOREIGN KEY (player1) REFERENCES players(player_id),
-            FOREIGN KEY (player2) REFERENCES players(player_id),
-            UNIQUE (seed, time)
-        );
-    ",
-    )
-    .await
-    .ok();
-    conn.simple_query(
-        /* language=postgresql */
-        "
-  CREATE TABLE matches (
-            match_id UUID PRIMARY KEY,
-            seed BIGINT NOT NULL,
-            time BIGINT NOT NULL,
-            player1 UUID NOT NULL,
-            player2 UUID NOT NULL,
-            winner UUID,
-            result TEXT NOT NULL,
-            points_p1 SMALLINT NOT NULL,
-            points_p2 SMALLINT NOT NULL,
-            started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-            completed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+/// Every migration in the order it is applied, never edit one that already
+/// shipped, add a new one instead
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial"),
+    migration!(2, "0002_bot_games"),
+    migration!(3, "0003_live_moves"),
+    migration!(4, "0004_match_rules"),
+    migration!(5, "0005_ratings"),
+    migration!(6, "0006_match_players"),
+    migration!(7, "0007_player_daily_stats"),
+    migration!(8, "0008_match_series"),
+    migration!(9, "0009_tournaments"),
+];
 
-            FOREIGN KEY (player1) REFERENCES players(player_id),
-            FOREIGN KEY (player2) REFERENCES players(player_id),
-            FOREIGN KEY (winner) REFERENCES players(player_id),
-            UNIQUE (seed, time)
-        );
-    ",
-    )
-    .await
-    .ok();
+/// The same for the embedded SQLite store, which has its own schema
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    migration!(sqlite 1, "0001_initial"),
+    migration!(sqlite 2, "0002_match_series"),
+    migration!(sqlite 3, "0003_tournaments"),
+];
+
+impl Migration {
+    pub(crate) fn checksum(&self) -> Vec<u8> {
+        Sha256::digest(self.sql.as_bytes()).to_vec()
+    }
 
-    conn.simple_query(
+    fn transactional(&self) -> bool {
+        !self.sql.starts_with(NO_TRANSACTION)
+    }
+
+    fn statements(&self) -> impl Iterator<Item = &'static str> {
+        self.sql
+            .split_inclusive(";\n")
+            .map(str::trim)
+            .filter(|statement| {
+                statement
+                    .lines()
+                    .any(|line| !line.trim().is_empty() && !line.starts_with("--"))
+            })
+    }
+}
+
+/// Checks the applied migrations against the embedded ones and returns the
+/// ones that still have to run
+pub async fn verify_migrations(
+    conn: &Client,
+) -> Result<Vec<&'static Migration>, UserCreateError> {
+    let exists: bool = conn
+        .query_one(
+            /* language=postgresql */
+            "SELECT to_regclass('schema_migrations') IS NOT NULL",
+            &[],
+        )
+        .await?
+        .get(0);
+    let applied = match exists {
+        true => {
+            conn.query(
+                /* language=postgresql */
+                "SELECT version, checksum FROM schema_migrations ORDER BY version",
+                &[],
+            )
+            .await?
+        }
+        false => Vec::new(),
+    };
+    let applied = applied
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect::<Vec<_>>();
+    pending_migrations(MIGRATIONS, &applied)
+}
+
+/// Migrations of `migrations` missing from the applied versions and
+/// checksums, fails when an applied one is unknown or was changed since
+pub fn pending_migrations(
+    migrations: &'static [Migration],
+    applied: &[(i32, Vec<u8>)],
+) -> Result<Vec<&'static Migration>, UserCreateError> {
+    for (version, checksum) in applied {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.version == *version)
+            .ok_or_else(|| {
+                UserCreateError::Migration(format!(
+                    "Database has migration {version} which this build does not know about"
+                ))
+            })?;
+        if migration.checksum() != *checksum {
+            return Err(UserCreateError::Migration(format!(
+                "Migration {} was changed after it was applied",
+                migration.name
+            )));
+        }
+    }
+
+    Ok(migrations
+        .iter()
+        .filter(|migration| {
+            !applied
+                .iter()
+                .any(|(version, _)| *version == migration.version)
+        })
+        .collect())
+}
+
+/// Applies every pending migration, each inside its own transaction unless it
+/// is marked as `-- no-transaction`
+pub async fn run_migrations(conn: &mut Client) -> Result<(), UserCreateError> {
+    conn.batch_execute(
         /* language=postgresql */
         "
-  CREATE TABLE moves (
-            match_id UUID NOT NULL,
-            player_id UUID NOT NULL,
-            number SMALLINT NOT NULL,
-            x SMALLINT NOT NULL,
-            seq INT NOT NULL,
-            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-
-            UNIQUE (match_id, seq),
-            FOREIGN KEY (match_id) REFERENCES matches(match_id),
-            FOREIGN KEY (player_id) REFERENCES players(player_id)
+  CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INT PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum BYTEA NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
         );
     ",
     )
-    .await
-    .ok();
-
-    conn.simple_query(
-        "CREATE TABLE queue_times (
-        queue_time INTEGER NOT NULL,
-        queue_id UUID NOT NULL,
-        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
-    )
-    ",
-    )
-    .await
-    .ok();
+    .await?;
+    conn.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    let result = apply_pending(conn).await;
+    conn.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    result
+}
 
-    macro_rules! create_hypertable {
-        ($table:literal, $column:literal) => {
-            conn.simple_query(&format!("SELECT create_hypertable('{}', by_range('{}'), if_not_exists => TRUE, migrate_data => TRUE)", $table, $column))
-                .await
-                .ok();
+async fn apply_pending(conn: &mut Client) -> Result<(), UserCreateError> {
+    for migration in verify_migrations(conn).await? {
+        tracing::info!("Applying migration {}", migration.name);
+        let insert = /* language=postgresql */
+            "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)";
+        let failed = |e: tokio_postgres::Error| {
+            UserCreateError::Migration(format!("{} failed: {e}", migration.name))
         };
+        if migration.transactional() {
+            let tx = conn.transaction().await?;
+            tx.batch_execute(migration.sql).await.map_err(failed)?;
+            tx.execute(
+                insert,
+                &[&migration.version, &migration.name, &migration.checksum()],
+            )
+            .await?;
+            tx.commit().await?;
+        } else {
+            // a batch would still run in an implicit transaction
+            for statement in migration.statements() {
+                conn.batch_execute(statement).await.map_err(failed)?;
+            }
+            conn.execute(
+                insert,
+                &[&migration.version, &migration.name, &migration.checksum()],
+            )
+            .await?;
+        }
     }
-
-    create_hypertable!("moves", "created_at");
-    create_hypertable!("matches", "completed_at");
-    create_hypertable!("started_matches", "created_at");
-    create_hypertable!("queue_times", "created_at");
-
     Ok(())
 }
@@ -0,0 +1,96 @@
+//! Keeps an eye on websocket connections, half-open ones never end their
+//! receive loop so dead users are also swept out of the queues
+
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+
+use crate::{routes::disconnect_user, AppState, User};
+
+/// How often the sweeper looks for dead users
+const SWEEP: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    /// How often the server pings every connection
+    pub ping_interval: Duration,
+    /// Connections that sent nothing for this long, not even a pong, are
+    /// dropped
+    pub idle_timeout: Duration,
+    /// Longest a single connection may stay open
+    pub session_timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(20),
+            idle_timeout: Duration::from_secs(60),
+            session_timeout: Duration::from_secs(4 * 60 * 60),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// The socket handler drops these users itself, the sweeper gives it one
+    /// more ping interval before stepping in
+    fn is_dead(&self, user: &User) -> bool {
+        user.sender.is_closed()
+            || user.last_seen.elapsed() > self.idle_timeout + self.ping_interval
+            || user.connected_at.elapsed() > self.session_timeout + self.ping_interval
+    }
+}
+
+/// Pings the connection until its socket is gone
+pub fn ping(heartbeat: Heartbeat, sender: UnboundedSender<Message>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(heartbeat.ping_interval);
+        // the first tick completes right away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if sender.send(Message::Ping(Vec::new())).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Evicts dead users and drops queue entries of users that are already gone
+pub async fn sweep(state: &AppState) {
+    let heartbeat = state.heartbeat;
+    let mut dead = Vec::new();
+    state
+        .all_users
+        .scan_async(|user_id, user| {
+            if heartbeat.is_dead(user) {
+                dead.push((*user_id, user.sender.clone()));
+            }
+        })
+        .await;
+    for (user_id, sender) in dead {
+        tracing::info!("Evicting stale user {:?}", user_id);
+        // ends the socket handler too if it is still around
+        sender.send(Message::Close(None)).ok();
+        if let Err(e) = disconnect_user(state, user_id).await {
+            tracing::debug!("Failed evicting user: {e:?}");
+        }
+    }
+    state
+        .queues
+        .retain_async(|_, queue| {
+            queue.retain(|id| state.all_users.contains(id));
+            true
+        })
+        .await;
+}
+
+/// Background task that sweeps out dead users
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(SWEEP);
+    loop {
+        interval.tick().await;
+        sweep(&state).await;
+    }
+}
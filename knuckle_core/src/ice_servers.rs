@@ -1,18 +1,10 @@
 use std::sync::Arc;
 
+use lib_knuckle::api_interfaces::IceServers;
 use serde::{Deserialize, Serialize};
 
 use crate::UserCreateError;
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
-pub struct IceServers {
-    urls: Vec<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    username: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    credential: Option<String>,
-}
-
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct IceServersResponse {
@@ -27,6 +19,14 @@ pub enum IceServerProvider {
 }
 
 impl IceServerProvider {
+    /// Label of the provider in the metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            IceServerProvider::Google(_) => "google",
+            IceServerProvider::Cloudflare(_) => "cloudflare",
+        }
+    }
+
     pub async fn get_ice_servers(&self) -> Result<IceServers, UserCreateError> {
         match self {
             IceServerProvider::Google(provider) => provider.get_ice_servers().await,
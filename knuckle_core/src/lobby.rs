@@ -0,0 +1,329 @@
+//! Private lobbies two players find each other in by a short code, both only
+//! enter the lobby queue once the host starts the match
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use lib_knuckle::{api_interfaces::LobbyInfo, protocol::ServerMessage, rules::RuleSet};
+use rand_core::{OsRng, RngCore};
+use scc::HashMap;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{AppState, UserCreateError};
+
+/// No 0/O or 1/I so codes can be read out loud
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 6;
+/// How often expired lobbies are closed
+const SWEEP: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Lobby {
+    /// Private queue both players are put in once the host starts
+    pub queue: Uuid,
+    pub host: Uuid,
+    pub host_key: String,
+    pub guest: Option<Uuid>,
+    password: Option<[u8; 32]>,
+    pub rules: RuleSet,
+    pub expires_at: Instant,
+}
+
+/// Open lobbies by their code
+pub type Lobbies = Arc<HashMap<String, Lobby>>;
+
+impl Lobby {
+    fn hash_password(&self, password: &str) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(self.queue.as_bytes())
+            .chain_update(password.as_bytes())
+            .finalize()
+            .into()
+    }
+
+    fn check_password(&self, password: Option<&str>) -> bool {
+        match (self.password, password) {
+            (None, _) => true,
+            (Some(hash), Some(password)) => self.hash_password(password) == hash,
+            (Some(_), None) => false,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at <= Instant::now()
+    }
+
+    pub fn info(&self, code: &str) -> LobbyInfo {
+        LobbyInfo {
+            code: code.to_owned(),
+            host_key: self.host_key.clone(),
+            has_password: self.password.is_some(),
+            rules: self.rules,
+            guest_joined: self.guest.is_some(),
+            expires_in_secs: self
+                .expires_at
+                .saturating_duration_since(Instant::now())
+                .as_secs(),
+        }
+    }
+}
+
+/// Codes are shown in upper case but typed in any case, with or without spaces
+/// and dashes
+pub fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn generate_code() -> String {
+    (0..CODE_LENGTH)
+        .map(|_| CODE_ALPHABET[OsRng.next_u32() as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+/// Looks up a lobby that did not expire yet
+pub async fn find(state: &AppState, code: &str) -> Option<Lobby> {
+    state
+        .lobbies
+        .read_async(&normalize_code(code), |_, lobby| lobby.clone())
+        .await
+        .filter(|lobby| !lobby.is_expired())
+}
+
+async fn check_not_in_lobby(
+    state: &AppState,
+    user_id: Uuid,
+) -> Result<(), UserCreateError> {
+    let in_lobby = state
+        .all_users
+        .read_async(&user_id, |_, user| user.lobby.is_some())
+        .await
+        .unwrap_or(false);
+    match in_lobby {
+        true => Err(UserCreateError::BadRequest("Already in a lobby".to_owned())),
+        false => Ok(()),
+    }
+}
+
+/// Opens a lobby hosted by an already verified user, returns its queue
+pub async fn create(
+    state: &AppState,
+    host: Uuid,
+    host_key: String,
+    password: Option<&str>,
+    rules: RuleSet,
+) -> Result<Uuid, UserCreateError> {
+    check_not_in_lobby(state, host).await?;
+    let mut lobby = Lobby {
+        queue: Uuid::new_v4(),
+        host,
+        host_key,
+        guest: None,
+        password: None,
+        rules,
+        expires_at: Instant::now() + state.lobby_ttl,
+    };
+    lobby.password = password
+        .filter(|password| !password.is_empty())
+        .map(|password| lobby.hash_password(password));
+    let queue = lobby.queue;
+
+    // retry until the code is not taken by another open lobby
+    let code = loop {
+        let code = generate_code();
+        match state.lobbies.insert_async(code.clone(), lobby).await {
+            Ok(()) => break code,
+            Err((_, rejected)) => lobby = rejected,
+        }
+    };
+
+    state
+        .all_users
+        .update_async(&host, |_, user| {
+            user.set_rules(rules).set_lobby(code.clone());
+        })
+        .await;
+    state.send_to(
+        host,
+        ServerMessage::LobbyCreated {
+            code,
+            expires_in_secs: state.lobby_ttl.as_secs(),
+        },
+    )?;
+    Ok(queue)
+}
+
+/// Adds a verified user as the guest and tells the host, returns the lobby
+/// queue
+pub async fn join(
+    state: &AppState,
+    code: &str,
+    guest: Uuid,
+    guest_key: String,
+    password: Option<&str>,
+) -> Result<Uuid, UserCreateError> {
+    check_not_in_lobby(state, guest).await?;
+    let code = normalize_code(code);
+    let lobby = state
+        .lobbies
+        .update_async(&code, |_, lobby| {
+            if lobby.is_expired() {
+                return Err(UserCreateError::LobbyNotFound);
+            }
+            if !lobby.check_password(password) {
+                return Err(UserCreateError::WrongLobbyPassword);
+            }
+            if lobby.guest.is_some() || lobby.host == guest {
+                return Err(UserCreateError::LobbyFull);
+            }
+            lobby.guest = Some(guest);
+            Ok(lobby.clone())
+        })
+        .await
+        .ok_or(UserCreateError::LobbyNotFound)??;
+
+    state
+        .all_users
+        .update_async(&guest, |_, user| {
+            user.set_rules(lobby.rules).set_lobby(code.clone());
+        })
+        .await;
+    state.send_to(
+        guest,
+        ServerMessage::LobbyJoined {
+            lobby: lobby.info(&code),
+        },
+    )?;
+    state.send_to(lobby.host, ServerMessage::LobbyGuestJoined { guest_key })?;
+    Ok(lobby.queue)
+}
+
+/// Closes the lobby of the host and hands both players to the matchmaker
+pub async fn start(state: &AppState, host: Uuid) -> Result<(), UserCreateError> {
+    let code = state
+        .all_users
+        .read_async(&host, |_, user| user.lobby.clone())
+        .await
+        .flatten()
+        .ok_or(UserCreateError::LobbyNotFound)?;
+    let removed = state
+        .lobbies
+        .remove_if_async(&code, |lobby| {
+            lobby.host == host && lobby.guest.is_some() && !lobby.is_expired()
+        })
+        .await;
+    let Some((
+        _,
+        Lobby {
+            queue,
+            guest: Some(guest),
+            ..
+        },
+    )) = removed
+    else {
+        return Err(match find(state, &code).await {
+            Some(lobby) if lobby.host != host => UserCreateError::NotLobbyHost,
+            Some(_) => UserCreateError::BadRequest("Waiting for a guest".to_owned()),
+            None => UserCreateError::LobbyNotFound,
+        });
+    };
+
+    for user_id in [host, guest] {
+        state
+            .all_users
+            .update_async(&user_id, |_, user| {
+                user.lobby = None;
+                user.in_queue_since = Instant::now();
+            })
+            .await;
+    }
+    // the matchmaker pairs anyone in a private queue on its next tick
+    state
+        .queues
+        .entry_async(queue)
+        .await
+        .or_insert(Vec::with_capacity(2))
+        .get_mut()
+        .extend([guest, host]);
+    Ok(())
+}
+
+/// A disconnected host closes their lobby, a disconnected guest frees it up
+/// for someone else
+pub async fn leave(state: &AppState, user_id: Uuid) -> Result<(), UserCreateError> {
+    let Some(code) = state
+        .all_users
+        .read_async(&user_id, |_, user| user.lobby.clone())
+        .await
+        .flatten()
+    else {
+        return Ok(());
+    };
+    if let Some((_, lobby)) = state
+        .lobbies
+        .remove_if_async(&code, |lobby| lobby.host == user_id)
+        .await
+    {
+        if let Some(guest) = lobby.guest {
+            close(state, guest, "The host left")?;
+        }
+        return Ok(());
+    }
+    let host = state
+        .lobbies
+        .update_async(&code, |_, lobby| {
+            lobby.guest = lobby.guest.filter(|&guest| guest != user_id);
+            lobby.host
+        })
+        .await;
+    if let Some(host) = host {
+        state.send_to(host, ServerMessage::LobbyGuestLeft)?;
+    }
+    Ok(())
+}
+
+fn close(state: &AppState, user_id: Uuid, reason: &str) -> Result<(), UserCreateError> {
+    state
+        .all_users
+        .update(&user_id, |_, user| user.lobby = None);
+    state.send_to(
+        user_id,
+        ServerMessage::LobbyClosed {
+            reason: reason.to_owned(),
+        },
+    )
+}
+
+/// Background task that closes lobbies nobody started in time
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(SWEEP);
+    loop {
+        interval.tick().await;
+        let mut expired = Vec::new();
+        state
+            .lobbies
+            .retain_async(|_, lobby| {
+                if lobby.is_expired() {
+                    expired.extend(lobby.guest);
+                    expired.push(lobby.host);
+                }
+                !lobby.is_expired()
+            })
+            .await;
+        for user_id in expired {
+            if let Err(e) = close(&state, user_id, "The lobby expired") {
+                tracing::debug!("Failed closing lobby: {e:?}");
+            }
+        }
+        // started lobbies leave an empty private queue behind
+        state
+            .queues
+            .retain_async(|queue_name, queue| queue_name.is_nil() || !queue.is_empty())
+            .await;
+    }
+}
@@ -6,34 +6,66 @@ use axum::{
 use axum_thiserror::ErrorStatus;
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
+use bot::{BotData, BotPlayer};
 use clap::Parser;
-use database::init_db;
 use ed25519_dalek::SigningKey;
 use embed::static_handler;
+use heartbeat::Heartbeat;
 use http::{
     header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
     HeaderValue, StatusCode,
 };
 use ice_servers::{
-    CloudflareIceServerProvider, GoogleIceServerProvider, IceServerProvider,
+    CloudflareIceServerProvider, GoogleIceServerProvider, IceServerData,
+    IceServerProvider,
 };
+use lib_knuckle::{
+    error::{GameError, ProtocolError},
+    protocol::ServerMessage,
+    rules::RuleSet,
+};
+use lobby::Lobbies;
+use matchmaker::SharedPendingSeed;
+use metrics::Metrics;
 use rand_core::OsRng;
-use routes::{leader_board, set_name, signup, submit_game, ws_handler};
+use relay::SharedRelay;
+use routes::{
+    create_tournament, leader_board, leader_board_rank, list_matches, lobby_info,
+    match_details, metrics, rating_leader_board, register_tournament, set_name, signup,
+    start_tournament, submit_game, tournament_info, tournaments, ws_handler,
+    ToTextMessage,
+};
 use scc::HashMap;
-use std::{sync::Arc, time::Instant};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use store::{Backend, PostgresStore, SharedStore, SqliteStore};
 use strum::EnumMessage;
 use thiserror::Error;
 use tokio::{fs, signal, sync::Mutex};
 use tokio_postgres::NoTls;
+use tournament::Tournaments;
 use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::{ContextV7, Uuid};
 
+pub mod bot;
 pub mod database;
 pub mod embed;
+pub mod heartbeat;
 pub mod ice_servers;
-pub mod pool_extractor;
+pub mod lobby;
+pub mod matchmaker;
+pub mod metrics;
+pub mod relay;
 pub mod routes;
+pub mod spectate;
+pub mod store;
+#[cfg(test)]
+mod tests;
+pub mod tournament;
 
 #[derive(Error, Debug, ErrorStatus, strum_macros::EnumMessage)]
 pub enum UserCreateError {
@@ -58,6 +90,9 @@ pub enum UserCreateError {
     #[error("Pool Error: {0}")]
     #[status(StatusCode::INTERNAL_SERVER_ERROR)]
     PoolError(#[from] bb8::RunError<tokio_postgres::Error>),
+    #[error("Internal Database Error: {0}")]
+    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
+    SqliteError(#[from] rusqlite::Error),
     #[error("Signature Error: {0}")]
     #[status(StatusCode::BAD_REQUEST)]
     SignatureError(#[from] ed25519_dalek::SignatureError),
@@ -79,12 +114,131 @@ pub enum UserCreateError {
     #[error("User does not exist")]
     #[status(StatusCode::BAD_REQUEST)]
     UserDoesNotExist,
+    #[error("Invalid move signature: {0}")]
+    #[status(StatusCode::UNAUTHORIZED)]
+    InvalidMoveSignature(GameError),
+    #[error("Move out of order: {0}")]
+    #[status(StatusCode::CONFLICT)]
+    MoveOutOfOrder(GameError),
+    #[error("Illegal move: {0}")]
+    #[status(StatusCode::UNPROCESSABLE_ENTITY)]
+    IllegalMove(GameError),
+    #[error("Timeouts can only be claimed in games relayed by the server")]
+    #[status(StatusCode::UNPROCESSABLE_ENTITY)]
+    UnverifiedTimeoutClaim,
+    #[error("Match was already saved")]
+    #[status(StatusCode::CONFLICT)]
+    MatchAlreadySaved,
+    #[error("Game already completed")]
+    #[status(StatusCode::GONE)]
+    GameAlreadyCompleted,
+    #[error("Match not found")]
+    #[status(StatusCode::NOT_FOUND)]
+    MatchNotFound,
+    #[error("Lobby not found")]
+    #[status(StatusCode::NOT_FOUND)]
+    LobbyNotFound,
+    #[error("Lobby is full")]
+    #[status(StatusCode::CONFLICT)]
+    LobbyFull,
+    #[error("Wrong lobby password")]
+    #[status(StatusCode::FORBIDDEN)]
+    WrongLobbyPassword,
+    #[error("Only the host can start the lobby")]
+    #[status(StatusCode::FORBIDDEN)]
+    NotLobbyHost,
+    #[error("Tournament not found")]
+    #[status(StatusCode::NOT_FOUND)]
+    TournamentNotFound,
+    #[error("Tournament already exists")]
+    #[status(StatusCode::CONFLICT)]
+    TournamentAlreadyExists,
+    #[error("Only the organizer can start the tournament")]
+    #[status(StatusCode::FORBIDDEN)]
+    NotTournamentOrganizer,
+    #[error("Unknown message type {0}")]
+    #[status(StatusCode::BAD_REQUEST)]
+    UnknownMessage(String),
+    #[error("Malformed message: {0}")]
+    #[status(StatusCode::BAD_REQUEST)]
+    MalformedMessage(String),
+    #[error("Unsupported protocol version {version}, need at least {min}")]
+    #[status(StatusCode::BAD_REQUEST)]
+    UnsupportedProtocolVersion { version: u32, min: u32 },
+    #[error("Connection was idle for too long")]
+    #[status(StatusCode::REQUEST_TIMEOUT)]
+    IdleTimeout,
+    #[error("Session expired, please reconnect")]
+    #[status(StatusCode::REQUEST_TIMEOUT)]
+    SessionExpired,
+    #[error("Migration error: {0}")]
+    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
+    Migration(String),
+}
+
+impl From<GameError> for UserCreateError {
+    fn from(error: GameError) -> Self {
+        match error {
+            GameError::InvalidSignature
+            | GameError::MalformedSignature { .. }
+            | GameError::MalformedKey
+            | GameError::InvalidDiceLink
+            | GameError::MissingSigningKey => Self::InvalidMoveSignature(error),
+            GameError::OutOfOrderSeq { .. }
+            | GameError::TimeWentBackwards { .. }
+            | GameError::MoveFromFuture { .. }
+            | GameError::TurnTimeExceeded { .. } => Self::MoveOutOfOrder(error),
+            GameError::ColumnFull { .. }
+            | GameError::ColumnOutOfRange { .. }
+            | GameError::Decode { .. }
+            | GameError::TimeoutNotReached { .. } => Self::IllegalMove(error),
+            GameError::InvalidRuleSet { reason } => Self::BadRequest(reason),
+            GameError::UnsupportedRecordVersion { .. }
+            | GameError::MalformedContribution { .. }
+            | GameError::SeedRevealMismatch
+            | GameError::MissingDiceChain => Self::BadRequest(error.to_string()),
+            GameError::GameCompleted => Self::GameAlreadyCompleted,
+        }
+    }
+}
+
+impl From<ProtocolError> for UserCreateError {
+    fn from(error: ProtocolError) -> Self {
+        match error {
+            ProtocolError::UnknownMessage { kind } => Self::UnknownMessage(kind),
+            ProtocolError::MalformedMessage { reason } => Self::MalformedMessage(reason),
+            ProtocolError::UnsupportedVersion { version, min } => {
+                Self::UnsupportedProtocolVersion { version, min }
+            }
+        }
+    }
 }
 
 impl UserCreateError {
     pub fn get_name(&self) -> &'static str {
         self.get_serializations()[0]
     }
+
+    /// Whether a websocket can stay open after the error, mistakes of the
+    /// player are answered and only failures of the server or the connection
+    /// itself end it
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Internal(_)
+                | Self::DatabaseError(_)
+                | Self::PoolError(_)
+                | Self::SqliteError(_)
+                | Self::SystemTimeError(_)
+                | Self::SendError(_)
+                | Self::JsonError(_)
+                | Self::ReqwestError(_)
+                | Self::UnsupportedProtocolVersion { .. }
+                | Self::IdleTimeout
+                | Self::SessionExpired
+                | Self::Migration(_)
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -94,9 +248,43 @@ pub struct User {
     pub_key: Option<String>,
     player_id: Option<Uuid>,
     in_queue_since: Instant,
+    rules: RuleSet,
+    bot_sender: Option<tokio::sync::mpsc::UnboundedSender<Vec<u8>>>,
+    relay: Option<SharedRelay>,
+    skill: f64,
+    /// Code of the lobby the user waits in
+    lobby: Option<String>,
+    /// Tournament the user waits for their next game in
+    tournament: Option<Uuid>,
+    /// Commitment to the seed contribution for the next match
+    seed_commitment: Option<String>,
+    pending_seed: Option<SharedPendingSeed>,
+    connected_at: Instant,
+    /// Last time anything arrived on the socket, pongs included
+    last_seen: Instant,
 }
 
 impl User {
+    fn new(sender: tokio::sync::mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            partner_id: None,
+            sender,
+            pub_key: None,
+            player_id: None,
+            in_queue_since: Instant::now(),
+            rules: RuleSet::default(),
+            bot_sender: None,
+            relay: None,
+            skill: 0.0,
+            lobby: None,
+            tournament: None,
+            seed_commitment: None,
+            pending_seed: None,
+            connected_at: Instant::now(),
+            last_seen: Instant::now(),
+        }
+    }
+
     fn set_pub_key(&mut self, pub_key: String) -> &mut Self {
         self.pub_key = Some(pub_key);
         self
@@ -109,6 +297,45 @@ impl User {
         self.partner_id = Some(partner_id);
         self
     }
+    fn set_rules(&mut self, rules: RuleSet) -> &mut Self {
+        self.rules = rules;
+        self
+    }
+    fn set_bot_sender(
+        &mut self,
+        bot_sender: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+    ) -> &mut Self {
+        self.bot_sender = Some(bot_sender);
+        self
+    }
+    fn set_relay(&mut self, relay: SharedRelay) -> &mut Self {
+        self.relay = Some(relay);
+        self
+    }
+    fn set_skill(&mut self, skill: f64) -> &mut Self {
+        self.skill = skill;
+        self
+    }
+    fn set_lobby(&mut self, code: String) -> &mut Self {
+        self.lobby = Some(code);
+        self
+    }
+    fn set_tournament(&mut self, tournament_id: Uuid) -> &mut Self {
+        self.tournament = Some(tournament_id);
+        self
+    }
+    fn set_seed_commitment(&mut self, commitment: String) -> &mut Self {
+        self.seed_commitment = Some(commitment);
+        self
+    }
+    fn set_pending_seed(&mut self, pending: SharedPendingSeed) -> &mut Self {
+        self.pending_seed = Some(pending);
+        self
+    }
+    /// Hidden dice games wait until the player committed to a seed
+    fn can_start(&self) -> bool {
+        !self.rules.hidden_dice || self.seed_commitment.is_some()
+    }
 }
 
 pub type AllUsers = Arc<HashMap<Uuid, User>>;
@@ -118,16 +345,93 @@ pub struct AppState {
     queues: Arc<HashMap<Uuid, Vec<Uuid>>>,
     all_users: AllUsers,
     dice_seed_signing_keys: Arc<Mutex<SigningKey>>,
+    ice_servers: IceServerData,
+    store: SharedStore,
+    bot: BotData,
+    /// In-flight matches by the public key of each player, kept around so
+    /// relayed games can be resumed after a disconnect
+    live_matches: Arc<HashMap<String, SharedRelay>>,
+    reconnect_grace: Duration,
+    lobbies: Lobbies,
+    lobby_ttl: Duration,
+    /// How far spectators lag behind the players
+    spectator_delay: Duration,
+    tournaments: Tournaments,
+    tournament_match_timeout: Duration,
+    heartbeat: Heartbeat,
+    metrics: Metrics,
 }
 
 impl AppState {
+    fn new(
+        store: SharedStore,
+        dice_seed_signing_keys: SigningKey,
+        ice_servers: IceServerData,
+        bot: BotData,
+        reconnect_grace: Duration,
+        lobby_ttl: Duration,
+        spectator_delay: Duration,
+    ) -> Self {
+        let app_state = Self {
+            queues: Arc::new(HashMap::new()),
+            all_users: Arc::new(HashMap::new()),
+            dice_seed_signing_keys: Arc::new(Mutex::new(dice_seed_signing_keys)),
+            ice_servers,
+            store,
+            bot,
+            live_matches: Arc::new(HashMap::new()),
+            reconnect_grace,
+            lobbies: Arc::new(HashMap::new()),
+            lobby_ttl,
+            spectator_delay,
+            tournaments: Arc::new(HashMap::new()),
+            tournament_match_timeout: Duration::from_secs(1800),
+            heartbeat: Heartbeat::default(),
+            metrics: Metrics::new(),
+        };
+        app_state.queues.insert(Uuid::nil(), Vec::new()).ok();
+        app_state
+    }
+
+    fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    fn with_tournament_match_timeout(mut self, timeout: Duration) -> Self {
+        self.tournament_match_timeout = timeout;
+        self
+    }
+
     fn get_user_clone(&self, user_id: &Uuid) -> Option<User> {
         self.all_users.read(user_id, |_, v| v.clone())
     }
+
+    /// Sends a message to a connected user, users that already left are
+    /// skipped
+    fn send_to(
+        &self,
+        user_id: Uuid,
+        message: ServerMessage,
+    ) -> Result<(), UserCreateError> {
+        if let Some(sender) = self.all_users.read(&user_id, |_, user| user.sender.clone())
+        {
+            sender.send(message.to_text_message()?)?;
+        }
+        Ok(())
+    }
 }
 
 pub type SharedContextV7 = Arc<Mutex<ContextV7>>;
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum MigrateMode {
+    /// Apply pending migrations and exit
+    Run,
+    /// Check applied migrations and exit with an error if any are pending
+    Verify,
+}
+
 #[derive(Parser, Debug)]
 struct Args {
     #[clap(
@@ -136,12 +440,100 @@ struct Args {
         default_value = "host=localhost user=postgres password=postgres"
     )]
     database_stringlike: String,
+    #[clap(long, env = "DATABASE", value_enum, default_value_t = Backend::Postgres)]
+    database: Backend,
+    /// Database file of the sqlite backend, `:memory:` keeps everything in
+    /// memory
+    #[clap(long, env = "SQLITE_PATH", default_value = "knuckle.sqlite")]
+    sqlite_path: String,
     #[clap(long, env = "TURN_TOKEN_ID")]
     turn_token_id: Option<String>,
     #[clap(long, env = "API_TOKEN")]
     api_token: Option<String>,
     #[clap(long, env = "SEED_FILE")]
     seed_file: Option<String>,
+    /// Seconds a player waits in the public queue before playing a bot,
+    /// bots are disabled when unset
+    #[clap(long, env = "BOT_WAIT_SECS")]
+    bot_wait_secs: Option<u64>,
+    /// Seconds a disconnected player has to resume a relayed game
+    #[clap(long, env = "RECONNECT_GRACE_SECS", default_value_t = 60)]
+    reconnect_grace_secs: u64,
+    /// Seconds a private lobby stays open before the host has to start it
+    #[clap(long, env = "LOBBY_TTL_SECS", default_value_t = 900)]
+    lobby_ttl_secs: u64,
+    /// Seconds spectators see moves after they were played
+    #[clap(long, env = "SPECTATOR_DELAY_SECS", default_value_t = 10)]
+    spectator_delay_secs: u64,
+    /// Seconds a tournament game may take before it is played again, relayed
+    /// games that are still going are never cut short
+    #[clap(long, env = "TOURNAMENT_MATCH_TIMEOUT_SECS", default_value_t = 1800)]
+    tournament_match_timeout_secs: u64,
+    /// Seconds between websocket pings
+    #[clap(long, env = "PING_INTERVAL_SECS", default_value_t = 20)]
+    ping_interval_secs: u64,
+    /// Seconds without any message or pong before a websocket is dropped
+    #[clap(long, env = "IDLE_TIMEOUT_SECS", default_value_t = 60)]
+    idle_timeout_secs: u64,
+    /// Seconds a websocket may stay connected in total
+    #[clap(long, env = "SESSION_TIMEOUT_SECS", default_value_t = 14400)]
+    session_timeout_secs: u64,
+    /// Serve `/metrics` on this address instead of the public port, for
+    /// example `127.0.0.1:9090`
+    #[clap(long, env = "METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
+    /// Only run or verify the database migrations instead of starting the
+    /// server, migrations are applied on startup when unset
+    #[clap(long, env = "MIGRATE", value_enum)]
+    migrate: Option<MigrateMode>,
+}
+
+/// Prometheus metrics, on the public port unless an admin address is set
+fn metrics_app(app_state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .layer(Extension(app_state))
+}
+
+/// Every route of the server, the matchmaker runs separately
+fn app(app_state: AppState) -> Router {
+    Router::new()
+        .route("/_astro/*file", get(static_handler))
+        .route("/assets/*file", get(static_handler))
+        .route("/fonts/*file", get(static_handler))
+        .route("/og/*file", get(static_handler))
+        .route("/_app/*file", get(static_handler))
+        .route("/", get(static_handler))
+        .route("/index.html", get(static_handler))
+        .route("/signup", get(signup))
+        .route("/ws", get(ws_handler))
+        .route("/submit_game", post(submit_game))
+        .route("/leaderboard", get(leader_board))
+        .route("/leaderboard/rank", get(leader_board_rank))
+        .route("/leaderboard/rating", get(rating_leader_board))
+        .route("/matches", get(list_matches))
+        .route("/matches/:match_id", get(match_details))
+        .route("/set_name", post(set_name))
+        .route("/lobby/:code", get(lobby_info))
+        .route("/tournaments", get(tournaments).post(create_tournament))
+        .route("/tournaments/:tournament_id", get(tournament_info))
+        .route(
+            "/tournaments/:tournament_id/register",
+            post(register_tournament),
+        )
+        .route("/tournaments/:tournament_id/start", post(start_tournament))
+        .with_state(app_state.store.clone())
+        .layer(Extension(app_state))
+        .layer(Extension(Arc::new(Mutex::new(ContextV7::new()))))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(AllowOrigin::predicate(
+                    |_origin: &HeaderValue, _request_parts: &http::request::Parts| true,
+                ))
+                .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
+                .allow_private_network(true)
+                .allow_methods(Any),
+        )
 }
 
 #[tokio::main]
@@ -156,8 +548,6 @@ async fn main() {
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
-    tracing::debug!("connecting to Postgresql");
-
     let args = Args::parse();
 
     let ice_server_provider: IceServerProvider =
@@ -172,12 +562,54 @@ async fn main() {
             IceServerProvider::Google(GoogleIceServerProvider)
         };
 
-    let manager =
-        PostgresConnectionManager::new_from_stringlike(args.database_stringlike, NoTls)
+    tracing::debug!("connecting to {:?}", args.database);
+    let store: SharedStore = match args.database {
+        Backend::Postgres => {
+            let manager = PostgresConnectionManager::new_from_stringlike(
+                args.database_stringlike,
+                NoTls,
+            )
             .unwrap();
-    let pool = Pool::builder().build(manager).await.unwrap();
+            let pool = Pool::builder().build(manager).await.unwrap();
+            Arc::new(PostgresStore::new(pool))
+        }
+        Backend::Sqlite => Arc::new(SqliteStore::open(&args.sqlite_path).unwrap()),
+    };
 
-    init_db(pool.get_owned().await.unwrap()).await.unwrap();
+    match args.migrate {
+        Some(MigrateMode::Verify) => {
+            match store.pending_migrations().await {
+                Ok(pending) if pending.is_empty() => {
+                    tracing::info!("Database schema is up to date");
+                }
+                Ok(pending) => {
+                    for name in pending {
+                        tracing::error!("Migration {name} is pending");
+                    }
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    tracing::error!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(MigrateMode::Run) => {
+            store.run_migrations().await.unwrap();
+            return;
+        }
+        None => store.run_migrations().await.unwrap(),
+    }
+
+    // relayed matches only live in memory, so moves left from before a
+    // restart can never be resumed
+    let dropped = store.clear_all_live_moves().await.unwrap();
+    if dropped > 0 {
+        tracing::info!(
+            "Dropped {dropped} moves of relayed matches from before the restart"
+        );
+    }
 
     let seed_file = args.seed_file.unwrap_or("server_seed".to_string());
 
@@ -191,49 +623,57 @@ async fn main() {
         }
     };
 
-    let app_state = AppState {
-        queues: Arc::new(HashMap::new()),
-        all_users: Arc::new(HashMap::new()),
-        dice_seed_signing_keys: Arc::new(Mutex::new(dice_seed_signing_keys)),
+    let bot = match args.bot_wait_secs {
+        Some(wait) => Some(Arc::new(
+            BotPlayer::load_or_create(&store, Duration::from_secs(wait))
+                .await
+                .unwrap(),
+        )),
+        None => None,
     };
 
-    app_state.queues.insert(Uuid::nil(), Vec::new()).ok();
-
-    let uuid_clock = ContextV7::new();
-
-    let app = Router::new()
-        .route("/_astro/*file", get(static_handler))
-        .route("/assets/*file", get(static_handler))
-        .route("/fonts/*file", get(static_handler))
-        .route("/og/*file", get(static_handler))
-        .route("/_app/*file", get(static_handler))
-        .route("/", get(static_handler))
-        .route("/index.html", get(static_handler))
-        .route("/signup", get(signup))
-        .route("/ws", get(ws_handler))
-        .route("/submit_game", post(submit_game))
-        .route("/leaderboard", get(leader_board))
-        .route("/set_name", post(set_name))
-        .with_state(pool)
-        .layer(Extension(app_state))
-        .layer(Extension(Arc::new(Mutex::new(uuid_clock))))
-        .layer(Extension(Arc::new(ice_server_provider)))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(AllowOrigin::predicate(
-                    |_origin: &HeaderValue, _request_parts: &http::request::Parts| true,
-                ))
-                .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
-                .allow_private_network(true)
-                .allow_methods(Any),
-        );
+    let app_state = AppState::new(
+        store,
+        dice_seed_signing_keys,
+        Arc::new(ice_server_provider),
+        bot,
+        Duration::from_secs(args.reconnect_grace_secs),
+        Duration::from_secs(args.lobby_ttl_secs),
+        Duration::from_secs(args.spectator_delay_secs),
+    )
+    .with_heartbeat(Heartbeat {
+        ping_interval: Duration::from_secs(args.ping_interval_secs),
+        idle_timeout: Duration::from_secs(args.idle_timeout_secs),
+        session_timeout: Duration::from_secs(args.session_timeout_secs),
+    })
+    .with_tournament_match_timeout(Duration::from_secs(
+        args.tournament_match_timeout_secs,
+    ));
+    let loaded = tournament::load(&app_state).await.unwrap();
+    if loaded > 0 {
+        tracing::info!("Loaded {loaded} tournaments");
+    }
+    tokio::spawn(matchmaker::run(app_state.clone()));
+    tokio::spawn(heartbeat::run(app_state.clone()));
+    tokio::spawn(lobby::run(app_state.clone()));
+    tokio::spawn(tournament::run(app_state.clone()));
+    let app = match args.metrics_addr {
+        Some(metrics_addr) => {
+            tracing::info!("Serving metrics at {metrics_addr}");
+            let listener = tokio::net::TcpListener::bind(metrics_addr).await.unwrap();
+            let metrics_app = metrics_app(app_state.clone());
+            tokio::spawn(async move { axum::serve(listener, metrics_app).await });
+            app(app_state)
+        }
+        None => app(app_state.clone()).merge(metrics_app(app_state)),
+    };
 
     tracing::info!("Starting at localhost:8083");
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8083").await.unwrap();
     let terminate_signal = signal::ctrl_c();
 
     use parking_lot::deadlock;
-    use std::{thread, time::Duration};
+    use std::thread;
     thread::spawn(move || loop {
         thread::sleep(Duration::from_secs(10));
         let deadlocks = deadlock::check_deadlock();
@@ -261,12 +701,3 @@ async fn main() {
         }
     }
 }
-
-/// Utility function for mapping any error into a `500 Internal Server Error`
-/// response.
-fn internal_error<E>(err: E) -> (StatusCode, String)
-where
-    E: std::error::Error,
-{
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
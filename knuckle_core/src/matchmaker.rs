@@ -0,0 +1,537 @@
+use std::{
+    cmp::Reverse,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use ed25519_dalek::Signer;
+use lib_knuckle::{
+    api_interfaces::GameBody,
+    error::GameError,
+    protocol::ServerMessage,
+    rules::RuleSet,
+    seed::{self, SeedCommitments, SeedReveal},
+};
+use rand_core::{OsRng, RngCore};
+use tokio::sync::Mutex;
+use uuid::{NoContext, Timestamp, Uuid};
+
+use crate::{
+    relay::RelayMatch,
+    routes::ToTextMessage,
+    store::{SharedStore, StartedMatch},
+    AppState, UserCreateError,
+};
+
+/// How often the queues are scanned for pairs
+const TICK: Duration = Duration::from_millis(500);
+/// Rating difference accepted right after joining
+const BASE_BAND: f64 = 100.0;
+/// Extra rating difference accepted for every second spent in the queue
+const BAND_GROWTH_PER_SEC: f64 = 25.0;
+
+/// Skill estimate used for matchmaking, the Glicko-2 rating that is updated
+/// from every rated match the player finished
+pub async fn skill_estimate(
+    store: &SharedStore,
+    player_id: Uuid,
+) -> Result<f64, UserCreateError> {
+    store.player_rating(player_id).await
+}
+
+fn skill_band(waited: Duration) -> f64 {
+    BASE_BAND + BAND_GROWTH_PER_SEC * waited.as_secs_f64()
+}
+
+struct Candidate {
+    user_id: Uuid,
+    rules: RuleSet,
+    skill: f64,
+    waited: Duration,
+}
+
+/// Pairs the longest waiting players first, each with the closest opponent
+/// inside the wider of both skill bands, `anyone` skips the skill check
+fn find_pairs(mut candidates: Vec<Candidate>, anyone: bool) -> Vec<(Uuid, Uuid)> {
+    candidates.sort_by_key(|candidate| Reverse(candidate.waited));
+    let mut paired = vec![false; candidates.len()];
+    let mut pairs = Vec::new();
+    for i in 0..candidates.len() {
+        if paired[i] {
+            continue;
+        }
+        let me = &candidates[i];
+        let best = candidates
+            .iter()
+            .enumerate()
+            .skip(i + 1)
+            .filter(|(j, other)| !paired[*j] && other.rules == me.rules)
+            .map(|(j, other)| (j, (other.skill - me.skill).abs(), other))
+            .filter(|(_, diff, other)| {
+                anyone || *diff <= skill_band(me.waited).max(skill_band(other.waited))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((j, _, other)) = best {
+            paired[i] = true;
+            paired[j] = true;
+            // the newer player starts, like when pairing happened on join
+            pairs.push((other.user_id, me.user_id));
+        }
+    }
+    pairs
+}
+
+/// Takes matched players out of a queue
+async fn take_pairs(state: &AppState, queue_name: Uuid) -> Vec<(Uuid, Uuid)> {
+    state
+        .queues
+        .update_async(&queue_name, |_, queue| {
+            let candidates = queue
+                .iter()
+                .filter_map(|id| {
+                    state
+                        .all_users
+                        .read(id, |_, user| {
+                            user.can_start().then(|| Candidate {
+                                user_id: *id,
+                                rules: user.rules,
+                                skill: user.skill,
+                                waited: user.in_queue_since.elapsed(),
+                            })
+                        })
+                        .flatten()
+                })
+                .collect::<Vec<_>>();
+            // private queues only hold players that want to play each other
+            let pairs = find_pairs(candidates, !queue_name.is_nil());
+            queue.retain(|id| !pairs.iter().any(|(a, b)| a == id || b == id));
+            pairs
+        })
+        .await
+        .unwrap_or_default()
+}
+
+/// Background task that pairs up players waiting in the queues
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let mut queue_names = Vec::new();
+        state
+            .queues
+            .scan_async(|queue_name, _| queue_names.push(*queue_name))
+            .await;
+        for queue_name in queue_names {
+            for (user_id, partner_user_id) in take_pairs(&state, queue_name).await {
+                if let Err(e) =
+                    start_match(&state, Some(queue_name), user_id, partner_user_id, None)
+                        .await
+                {
+                    tracing::error!("Failed starting match: {e:?}");
+                }
+            }
+        }
+    }
+}
+
+/// Signs the match parameters and sends both players the pairing, `user_id`
+/// starts. When both players committed to a seed contribution the match only
+/// begins after they revealed them. A player that left in the meantime puts
+/// the other one back in the queue they came from, rematches continue the
+/// series of an earlier match. Returns the id of the started match
+pub(crate) async fn start_match(
+    state: &AppState,
+    queue_name: Option<Uuid>,
+    user_id: Uuid,
+    partner_user_id: Uuid,
+    series_id: Option<Uuid>,
+) -> Result<Option<Uuid>, UserCreateError> {
+    let (user, partner_user) = match (
+        state.get_user_clone(&user_id),
+        state.get_user_clone(&partner_user_id),
+    ) {
+        (Some(user), Some(partner_user)) => (user, partner_user),
+        (Some(_), None) | (None, Some(_)) => {
+            let waiting = if state.all_users.contains(&user_id) {
+                user_id
+            } else {
+                partner_user_id
+            };
+            requeue(state, queue_name, &[waiting]).await;
+            return Ok(None);
+        }
+        (None, None) => return Ok(None),
+    };
+    tracing::debug!("{:?} {:?}", &user, &partner_user);
+
+    let rules = user.rules;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let (user_pub_key, user_player_id) = match (user.pub_key, user.player_id) {
+        (Some(pub_key), Some(player_id)) => (pub_key, player_id),
+        _ => return Err(UserCreateError::Internal("User pub_key not set".to_owned())),
+    };
+    let (partner_pub_key, partner_player_id) =
+        match (partner_user.pub_key, partner_user.player_id) {
+            (Some(pub_key), Some(player_id)) => (pub_key, player_id),
+            _ => {
+                return Err(UserCreateError::Internal(
+                    "Partner pub_key not set".to_owned(),
+                ))
+            }
+        };
+
+    // nothing is changed yet, so both players can go back to the queue when
+    // the provider fails
+    let requested = Instant::now();
+    let ice_servers = state.ice_servers.get_ice_servers().await;
+    state.metrics.ice_request(
+        state.ice_servers.name(),
+        requested.elapsed(),
+        ice_servers.is_ok(),
+    );
+    let ice_servers = match ice_servers {
+        Ok(ice_servers) => ice_servers,
+        Err(e) => {
+            requeue(state, queue_name, &[user_id, partner_user_id]).await;
+            return Err(e);
+        }
+    };
+
+    // the dice only come from a commit-reveal when both players committed,
+    // a commitment is only good for one match
+    let commit = match (user.seed_commitment, partner_user.seed_commitment) {
+        (Some(first), Some(second)) => {
+            let nonce = seed::new_contribution();
+            let commitments = SeedCommitments {
+                server: seed::commitment(&nonce)?,
+                first,
+                second,
+            };
+            Some((nonce, commitments))
+        }
+        _ if rules.hidden_dice => {
+            return Err(UserCreateError::BadRequest(
+                "Hidden dice need both players to commit to a seed".to_owned(),
+            ))
+        }
+        _ => None,
+    };
+    let seed = match commit {
+        Some(_) => 0,
+        None => OsRng.next_u32() as u64,
+    };
+    let signed_seed = match &commit {
+        Some((_, commitments)) => commitments.encode(),
+        None => seed.to_string(),
+    };
+
+    let game_signature = STANDARD_NO_PAD.encode(
+        state
+            .dice_seed_signing_keys
+            .lock()
+            .await
+            .sign(
+                format!(
+                    "{signed_seed}:{time}:{}:{}:{}",
+                    user_pub_key,
+                    partner_pub_key,
+                    rules.encode()
+                )
+                .as_bytes(),
+            )
+            .to_bytes(),
+    );
+
+    let match_id = Uuid::new_v7(Timestamp::now(NoContext));
+    let start = MatchStart {
+        match_id,
+        series_id: series_id.unwrap_or(match_id),
+        users: (user_id, partner_user_id),
+        players: (user_player_id, partner_player_id),
+        body: GameBody {
+            seed,
+            time,
+            your_key: user_pub_key.clone(),
+            opponent_key: partner_pub_key.clone(),
+            starting: true,
+            rules,
+            signature: game_signature.clone(),
+            moves: Vec::new(),
+            seed_reveal: None,
+        },
+    };
+    let commitments = commit.as_ref().map(|(_, commitments)| commitments.clone());
+    let pending = commit.map(|(nonce, commitments)| {
+        Arc::new(Mutex::new(PendingSeed {
+            start: Some(start.clone()),
+            nonce,
+            commitments,
+            reveals: [None, None],
+        }))
+    });
+
+    for (id, partner_id) in [(user_id, partner_user_id), (partner_user_id, user_id)] {
+        state
+            .all_users
+            .update_async(&id, |_, item| {
+                item.set_partner_id(partner_id);
+                item.seed_commitment = None;
+                if let Some(pending) = &pending {
+                    item.set_pending_seed(pending.clone());
+                }
+            })
+            .await;
+    }
+    if pending.is_none() {
+        begin_match(state, start).await?;
+    }
+
+    tracing::debug!("Sending Paired");
+
+    partner_user.sender.send(
+        ServerMessage::Paired {
+            public_key: partner_pub_key.clone(),
+            partner_key: user_pub_key.clone(),
+            initiator: false,
+            seed,
+            signature: game_signature.clone(),
+            ice_servers: ice_servers.clone(),
+            time,
+            rules,
+            bot: false,
+            commitments: commitments.clone(),
+        }
+        .to_text_message()?,
+    )?;
+    user.sender.send(
+        ServerMessage::Paired {
+            public_key: user_pub_key,
+            partner_key: partner_pub_key,
+            initiator: true,
+            seed,
+            signature: game_signature,
+            ice_servers,
+            time,
+            rules,
+            bot: false,
+            commitments,
+        }
+        .to_text_message()?,
+    )?;
+
+    match queue_name {
+        Some(queue_name) => {
+            let now = Instant::now();
+            let waits = [now - user.in_queue_since, now - partner_user.in_queue_since];
+            state.metrics.paired("player", &waits);
+            state
+                .store
+                .record_queue_time(queue_name, waits[0] + waits[1])
+                .await?;
+        }
+        None => state.metrics.paired("player", &[]),
+    }
+
+    Ok(Some(match_id))
+}
+
+/// Puts players back at the front of the queue they were taken from, matches
+/// that did not come from a queue have nothing to go back to
+async fn requeue(state: &AppState, queue_name: Option<Uuid>, user_ids: &[Uuid]) {
+    if let Some(queue_name) = queue_name {
+        state
+            .queues
+            .update_async(&queue_name, |_, queue| {
+                for user_id in user_ids.iter().rev() {
+                    queue.insert(0, *user_id);
+                }
+            })
+            .await;
+    }
+}
+
+/// Everything needed to begin a signed match once its seed is known
+#[derive(Clone)]
+struct MatchStart {
+    match_id: Uuid,
+    series_id: Uuid,
+    /// Initiator first
+    users: (Uuid, Uuid),
+    players: (Uuid, Uuid),
+    /// The match parameters from the initiators point of view
+    body: GameBody,
+}
+
+/// Records the match and sets up the relay both players can fall back to
+async fn begin_match(state: &AppState, start: MatchStart) -> Result<(), UserCreateError> {
+    state
+        .store
+        .start_match(StartedMatch {
+            match_id: start.match_id,
+            seed: start.body.seed,
+            time: start.body.time,
+            players: start.players,
+            bot_game: false,
+            created_at: SystemTime::now(),
+            series_id: start.series_id,
+        })
+        .await?;
+
+    let keys = [start.body.your_key.clone(), start.body.opponent_key.clone()];
+    let relay =
+        RelayMatch::new(start.match_id, start.series_id, start.body, start.users)?;
+    for key in keys {
+        state.live_matches.upsert_async(key, relay.clone()).await;
+    }
+    for id in [start.users.0, start.users.1] {
+        state
+            .all_users
+            .update_async(&id, |_, item| {
+                item.set_relay(relay.clone());
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// Match waiting for both players to reveal their seed contributions
+pub struct PendingSeed {
+    /// Taken by whoever completes the reveal
+    start: Option<MatchStart>,
+    nonce: String,
+    commitments: SeedCommitments,
+    /// Contributions of the initiator and of the other player
+    reveals: [Option<String>; 2],
+}
+
+pub type SharedPendingSeed = Arc<Mutex<PendingSeed>>;
+
+impl fmt::Debug for PendingSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingSeed")
+            .field("match_id", &self.start.as_ref().map(|start| start.match_id))
+            .field("reveals", &self.reveals.each_ref().map(Option::is_some))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Checks a revealed contribution against the players commitment, the match
+/// begins once both players revealed and both get the derived seed
+pub(crate) async fn reveal_seed(
+    state: &AppState,
+    user_id: Uuid,
+    contribution: &str,
+) -> Result<(), UserCreateError> {
+    let pending = state
+        .all_users
+        .read(&user_id, |_, user| user.pending_seed.clone())
+        .flatten()
+        .ok_or_else(|| UserCreateError::BadRequest("No seed to reveal".to_owned()))?;
+    let (start, reveal) = {
+        let mut pending = pending.lock().await;
+        let Some(users) = pending.start.as_ref().map(|start| start.users) else {
+            return Ok(());
+        };
+        let (index, commitment) = match user_id == users.0 {
+            true => (0, &pending.commitments.first),
+            false => (1, &pending.commitments.second),
+        };
+        if seed::commitment(contribution)? != *commitment {
+            return Err(GameError::SeedRevealMismatch.into());
+        }
+        pending.reveals[index] = Some(contribution.to_owned());
+        let [Some(first), Some(second)] = pending.reveals.clone() else {
+            return Ok(());
+        };
+        let reveal = SeedReveal {
+            server: pending.nonce.clone(),
+            first,
+            second,
+        };
+        let mut start = pending.start.take().expect("checked above");
+        start.body.seed = reveal.verify(&pending.commitments)?;
+        start.body.seed_reveal = Some(reveal.clone());
+        (start, reveal)
+    };
+
+    let users = start.users;
+    let seed = start.body.seed;
+    for id in [users.0, users.1] {
+        state
+            .all_users
+            .update_async(&id, |_, item| {
+                item.pending_seed = None;
+            })
+            .await;
+    }
+    begin_match(state, start).await?;
+    for id in [users.0, users.1] {
+        state.send_to(
+            id,
+            ServerMessage::SeedRevealed {
+                seed,
+                reveal: reveal.clone(),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(skill: f64, waited_secs: u64) -> Candidate {
+        Candidate {
+            user_id: Uuid::new_v4(),
+            rules: RuleSet::default(),
+            skill,
+            waited: Duration::from_secs(waited_secs),
+        }
+    }
+
+    #[test]
+    fn test_close_pair() {
+        let (older, newer) = (candidate(1500.0, 2), candidate(1550.0, 1));
+        let expected = vec![(newer.user_id, older.user_id)];
+        assert_eq!(find_pairs(vec![newer, older], false), expected);
+    }
+
+    #[test]
+    fn test_far_pair() {
+        let candidates = || vec![candidate(1500.0, 0), candidate(1900.0, 0)];
+        assert!(find_pairs(candidates(), false).is_empty());
+        assert_eq!(find_pairs(candidates(), true).len(), 1);
+    }
+
+    #[test]
+    fn test_far_pair_once_waited() {
+        // the band grows by 25 a second, 400 apart needs 12 seconds
+        assert!(
+            find_pairs(vec![candidate(1500.0, 11), candidate(1900.0, 0)], false)
+                .is_empty()
+        );
+        let (waiting, strong) = (candidate(1500.0, 12), candidate(1900.0, 0));
+        let expected = vec![(strong.user_id, waiting.user_id)];
+        assert_eq!(find_pairs(vec![waiting, strong], false), expected);
+    }
+
+    #[test]
+    fn test_closest_within_band() {
+        let me = candidate(1500.0, 5);
+        let far = candidate(1580.0, 0);
+        let close = candidate(1510.0, 0);
+        let expected = vec![(close.user_id, me.user_id)];
+        assert_eq!(find_pairs(vec![far, me, close], false), expected);
+    }
+
+    #[test]
+    fn test_different_rules_not_paired() {
+        let mut other = candidate(1500.0, 0);
+        other.rules.turn_limit_secs = Some(30);
+        assert!(find_pairs(vec![candidate(1500.0, 0), other], true).is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! Prometheus metrics of the game server, gauges of the shared state are only
+//! filled in when scraped
+
+use std::time::Duration;
+
+use lib_knuckle::error::GameError;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{AppState, UserCreateError};
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    connected_users: IntGauge,
+    queue_length: IntGaugeVec,
+    pairings: IntCounterVec,
+    queue_wait: Histogram,
+    submitted_games: IntCounterVec,
+    validation_failures: IntCounterVec,
+    ice_request_seconds: HistogramVec,
+    ice_errors: IntCounterVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let metrics = Self {
+            registry: Registry::new_custom(Some("knuckle".to_owned()), None).unwrap(),
+            connected_users: IntGauge::new(
+                "connected_users",
+                "Users connected over the websocket",
+            )
+            .unwrap(),
+            queue_length: IntGaugeVec::new(
+                Opts::new("queue_length", "Players waiting in each queue"),
+                &["queue"],
+            )
+            .unwrap(),
+            pairings: IntCounterVec::new(
+                Opts::new("pairings_total", "Matches handed out to players"),
+                &["kind"],
+            )
+            .unwrap(),
+            queue_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    "queue_wait_seconds",
+                    "How long players waited in a queue before they were paired",
+                )
+                .buckets(vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]),
+            )
+            .unwrap(),
+            submitted_games: IntCounterVec::new(
+                Opts::new("submitted_games_total", "Finished games by outcome"),
+                &["outcome"],
+            )
+            .unwrap(),
+            validation_failures: IntCounterVec::new(
+                Opts::new(
+                    "validation_failures_total",
+                    "Moves and games that failed validation by reason",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            ice_request_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "ice_request_seconds",
+                    "Time taken to fetch ice servers from the provider",
+                ),
+                &["provider"],
+            )
+            .unwrap(),
+            ice_errors: IntCounterVec::new(
+                Opts::new("ice_errors_total", "Failed ice server requests"),
+                &["provider"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGauge::new(
+                "db_pool_connections",
+                "Open database connections",
+            )
+            .unwrap(),
+            db_pool_idle_connections: IntGauge::new(
+                "db_pool_idle_connections",
+                "Open database connections nobody is using",
+            )
+            .unwrap(),
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.connected_users.clone()),
+            Box::new(metrics.queue_length.clone()),
+            Box::new(metrics.pairings.clone()),
+            Box::new(metrics.queue_wait.clone()),
+            Box::new(metrics.submitted_games.clone()),
+            Box::new(metrics.validation_failures.clone()),
+            Box::new(metrics.ice_request_seconds.clone()),
+            Box::new(metrics.ice_errors.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+            Box::new(metrics.db_pool_idle_connections.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// `kind` is either `player` or `bot`, waits are only known for players
+    /// that came from a queue
+    pub fn paired(&self, kind: &str, waits: &[Duration]) {
+        self.pairings.with_label_values(&[kind]).inc();
+        for wait in waits {
+            self.queue_wait.observe(wait.as_secs_f64());
+        }
+    }
+
+    pub fn game_submitted(&self, saved: &Result<(), UserCreateError>) {
+        let outcome = match saved {
+            Ok(()) => "accepted",
+            Err(e) => {
+                self.validation_failed(e);
+                e.get_name()
+            }
+        };
+        self.submitted_games.with_label_values(&[outcome]).inc();
+    }
+
+    /// Only counts errors about the game itself, not missing players or the
+    /// database
+    pub fn validation_failed(&self, error: &UserCreateError) {
+        let reason = match error {
+            UserCreateError::InvalidMoveSignature(e)
+            | UserCreateError::MoveOutOfOrder(e)
+            | UserCreateError::IllegalMove(e) => reason(e),
+            UserCreateError::InvalidSignature => "InvalidSignature".to_owned(),
+            _ => return,
+        };
+        self.validation_failures.with_label_values(&[&reason]).inc();
+    }
+
+    pub fn ice_request(&self, provider: &str, took: Duration, ok: bool) {
+        self.ice_request_seconds
+            .with_label_values(&[provider])
+            .observe(took.as_secs_f64());
+        if !ok {
+            self.ice_errors.with_label_values(&[provider]).inc();
+        }
+    }
+
+    /// Text exposition of every metric, with the gauges read from the state
+    pub async fn render(&self, state: &AppState) -> Result<String, UserCreateError> {
+        self.connected_users.set(state.all_users.len() as i64);
+        // private queue names are what lets players find each other, so
+        // they are only counted together
+        let (mut public, mut private) = (0, 0);
+        state
+            .queues
+            .scan_async(|queue_name, queue| match queue_name.is_nil() {
+                true => public += queue.len() as i64,
+                false => private += queue.len() as i64,
+            })
+            .await;
+        self.queue_length.with_label_values(&["public"]).set(public);
+        self.queue_length
+            .with_label_values(&["private"])
+            .set(private);
+        if let Some(pool) = state.store.pool_state() {
+            self.db_pool_connections.set(pool.connections as i64);
+            self.db_pool_idle_connections
+                .set(pool.idle_connections as i64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| UserCreateError::Internal(e.to_string()))?;
+        String::from_utf8(buffer).map_err(|e| UserCreateError::Internal(e.to_string()))
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Variant name of the error, the same as its `type` in json
+fn reason(error: &GameError) -> String {
+    serde_json::to_value(error)
+        .ok()
+        .and_then(|value| value["type"].as_str().map(str::to_owned))
+        .unwrap_or_else(|| "Unknown".to_owned())
+}
@@ -0,0 +1,349 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use lib_knuckle::{
+    api_interfaces::{GameBody, LiveMatch},
+    error::GameError,
+    game::{BoardData, Game, HistoryItem, ServerGameInfo, CLOCK_SKEW_MS},
+    keys::Keys,
+    verifying_key_from_string,
+};
+use tokio::sync::{broadcast, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    routes::{save_abandoned, save_game},
+    store::SharedStore,
+    AppState, UserCreateError,
+};
+
+/// Server side copy of a match, used when WebRTC cant connect and both
+/// players send their signed moves over the websocket instead
+pub struct RelayMatch {
+    match_id: Uuid,
+    series_id: Uuid,
+    game: Game,
+    body: GameBody,
+    /// Websocket user of the initiator and of the other player, `None` while
+    /// they are disconnected
+    users: [Option<Uuid>; 2],
+    relayed: bool,
+    saved: bool,
+    /// Which of `users` asked for a rematch
+    rematch: [bool; 2],
+    /// Board after every move so far, starting with the empty one
+    snapshots: Vec<Snapshot>,
+    spectators: broadcast::Sender<Snapshot>,
+}
+
+/// Board of a relayed match right after a move, stamped with when the server
+/// saw it
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub at: Instant,
+    pub board: BoardData,
+    pub completed: bool,
+}
+
+pub enum Rematch {
+    /// Only this player asked so far, the other one has to accept
+    Waiting { partner: Uuid },
+    /// Both want to play again, the player that did not start this match
+    /// starts the next one
+    Agreed { initiator: Uuid, other: Uuid },
+}
+
+/// More than any board has moves, spectators only lag behind by their delay
+const SPECTATOR_BACKLOG: usize = 256;
+
+pub type SharedRelay = Arc<Mutex<RelayMatch>>;
+
+impl fmt::Debug for RelayMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayMatch")
+            .field("match_id", &self.match_id)
+            .field("users", &self.users)
+            .field("relayed", &self.relayed)
+            .field("saved", &self.saved)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RelayMatch {
+    /// `body` holds the match parameters from the initiators point of view
+    pub fn new(
+        match_id: Uuid,
+        series_id: Uuid,
+        body: GameBody,
+        users: (Uuid, Uuid),
+    ) -> Result<SharedRelay, UserCreateError> {
+        let (my_keys, other_keys) = match (
+            verifying_key_from_string(&body.your_key),
+            verifying_key_from_string(&body.opponent_key),
+        ) {
+            (Some(my_keys), Some(other_keys)) => (my_keys, other_keys),
+            _ => return Err(UserCreateError::InvalidSignature),
+        };
+        let game = Game::new(
+            Keys::VerifyOnly {
+                my_keys,
+                other_keys,
+            },
+            body.rules,
+            ServerGameInfo::new(body.seed, body.starting)
+                .with_time(body.time)
+                .with_reveal(body.seed_reveal.as_ref()),
+        )?;
+        let snapshots = vec![Snapshot::of(&game)];
+        Ok(Arc::new(Mutex::new(Self {
+            match_id,
+            series_id,
+            game,
+            body,
+            users: [Some(users.0), Some(users.1)],
+            relayed: false,
+            saved: false,
+            rematch: [false; 2],
+            snapshots,
+            spectators: broadcast::channel(SPECTATOR_BACKLOG).0,
+        })))
+    }
+
+    pub fn match_id(&self) -> Uuid {
+        self.match_id
+    }
+
+    pub fn series_id(&self) -> Uuid {
+        self.series_id
+    }
+
+    pub fn body(&self) -> &GameBody {
+        &self.body
+    }
+
+    pub fn summary(&self) -> LiveMatch {
+        LiveMatch {
+            match_id: self.match_id.to_string(),
+            initiator_key: self.body.your_key.clone(),
+            other_key: self.body.opponent_key.clone(),
+            rules: self.body.rules,
+            moves: self.game.history().len() as u32,
+            relayed: self.relayed,
+        }
+    }
+
+    /// Snapshots a new spectator has to be sent and a receiver for the later
+    /// ones, everything before the latest snapshot older than `delay` is
+    /// skipped
+    pub fn spectate(
+        &self,
+        delay: Duration,
+    ) -> (Vec<Snapshot>, broadcast::Receiver<Snapshot>) {
+        let visible = Instant::now().checked_sub(delay);
+        let first = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| visible.is_some_and(|visible| snapshot.at <= visible))
+            .unwrap_or(0);
+        (
+            self.snapshots[first..].to_vec(),
+            self.spectators.subscribe(),
+        )
+    }
+
+    pub fn keys(&self) -> [&str; 2] {
+        [&self.body.your_key, &self.body.opponent_key]
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.game.is_completed()
+    }
+
+    /// Only relayed games can be resumed, the server never sees the moves of
+    /// games played over WebRTC
+    pub fn is_relayed(&self) -> bool {
+        self.relayed
+    }
+
+    /// Validates a move from either player and keeps it until the game is
+    /// saved, returns whether the game is over
+    pub async fn play(
+        &mut self,
+        store: &SharedStore,
+        data: &[u8],
+    ) -> Result<bool, UserCreateError> {
+        let item =
+            bincode::deserialize::<HistoryItem>(data).map_err(|e| GameError::Decode {
+                reason: e.to_string(),
+            })?;
+        // the game only checks moves are not dated ahead, the server clock
+        // also keeps players from dating them back to speed up the other clock
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let earliest = now.saturating_sub(CLOCK_SKEW_MS);
+        if self.body.rules.turn_limit_secs.is_some() && item.time() < earliest {
+            return Err(GameError::TimeWentBackwards {
+                last: earliest,
+                got: item.time(),
+            }
+            .into());
+        }
+        self.game.add_opponent_move(item)?;
+        self.relayed = true;
+        let snapshot = Snapshot::of(&self.game);
+        self.snapshots.push(snapshot.clone());
+        // nobody might be watching
+        self.spectators.send(snapshot).ok();
+
+        store
+            .push_live_move(
+                self.match_id,
+                self.game.history().len() as u32,
+                data.to_vec(),
+            )
+            .await?;
+
+        Ok(self.game.is_completed())
+    }
+
+    /// Moves of the match so far, read back from the database
+    pub async fn stored_history(
+        &self,
+        store: &SharedStore,
+    ) -> Result<Vec<HistoryItem>, UserCreateError> {
+        store
+            .live_moves(self.match_id)
+            .await?
+            .iter()
+            .map(|item| {
+                bincode::deserialize::<HistoryItem>(item)
+                    .map_err(|e| UserCreateError::Internal(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn index_of(&self, pub_key: &str) -> Option<usize> {
+        self.keys().iter().position(|key| *key == pub_key)
+    }
+
+    /// Marks the websocket user as gone, returns the user of the other player
+    pub fn disconnect(&mut self, user_id: Uuid) -> Option<Uuid> {
+        let index = self.users.iter().position(|user| *user == Some(user_id))?;
+        self.users[index] = None;
+        self.users[1 - index]
+    }
+
+    pub fn is_connected(&self, pub_key: &str) -> bool {
+        self.index_of(pub_key)
+            .is_some_and(|index| self.users[index].is_some())
+    }
+
+    pub fn partner_of(&self, pub_key: &str) -> Option<Uuid> {
+        self.users[1 - self.index_of(pub_key)?]
+    }
+
+    /// Attaches a new websocket user to a player that lost their connection,
+    /// returns the user of the other player
+    pub fn reconnect(
+        &mut self,
+        pub_key: &str,
+        user_id: Uuid,
+    ) -> Result<Option<Uuid>, UserCreateError> {
+        let index = match self.index_of(pub_key) {
+            Some(index) if self.users[index].is_none() => index,
+            _ => {
+                return Err(UserCreateError::BadRequest(
+                    "Match can not be resumed".to_owned(),
+                ))
+            }
+        };
+        self.users[index] = Some(user_id);
+        Ok(self.users[1 - index])
+    }
+
+    /// Counts the player in for another match against the same opponent, the
+    /// server can only tell that relayed matches are over
+    pub fn request_rematch(&mut self, user_id: Uuid) -> Result<Rematch, UserCreateError> {
+        if self.relayed && !self.game.is_completed() {
+            return Err(UserCreateError::BadRequest(
+                "Match is not over yet".to_owned(),
+            ));
+        }
+        let index = self
+            .users
+            .iter()
+            .position(|user| *user == Some(user_id))
+            .ok_or_else(|| UserCreateError::BadRequest("Not in this match".to_owned()))?;
+        let partner = self.users[1 - index].ok_or_else(|| {
+            UserCreateError::BadRequest("Partner is not connected".to_owned())
+        })?;
+        self.rematch[index] = true;
+        if self.rematch != [true; 2] {
+            return Ok(Rematch::Waiting { partner });
+        }
+        self.rematch = [false; 2];
+        Ok(Rematch::Agreed {
+            initiator: self.users[1].unwrap_or(partner),
+            other: self.users[0].unwrap_or(partner),
+        })
+    }
+
+    /// Turns down a rematch, returns the user of the other player
+    pub fn decline_rematch(&mut self, user_id: Uuid) -> Option<Uuid> {
+        let index = self.users.iter().position(|user| *user == Some(user_id))?;
+        self.rematch = [false; 2];
+        self.users[1 - index]
+    }
+
+    /// Stores the finished game, does nothing once that worked so a failed
+    /// save can be tried again
+    pub async fn save(&mut self, state: &AppState) -> Result<(), UserCreateError> {
+        if self.saved || !self.game.is_completed() {
+            return Ok(());
+        }
+        let body = GameBody {
+            moves: self.game.history().to_vec(),
+            ..self.body.clone()
+        };
+        match save_game(state, body).await {
+            // a player may have submitted the game on their own as well
+            Ok(()) | Err(UserCreateError::MatchAlreadySaved) => self.saved = true,
+            Err(e) => return Err(e),
+        }
+        state.store.clear_live_moves(self.match_id).await?;
+        Ok(())
+    }
+
+    /// Stores an unfinished game as lost by the player that left
+    pub async fn save_forfeit(
+        &mut self,
+        state: &AppState,
+        leaver_key: &str,
+    ) -> Result<(), UserCreateError> {
+        if self.saved || self.game.is_completed() {
+            return Ok(());
+        }
+        let body = GameBody {
+            moves: self.game.history().to_vec(),
+            ..self.body.clone()
+        };
+        match save_abandoned(state, body, leaver_key).await {
+            Ok(()) | Err(UserCreateError::MatchAlreadySaved) => self.saved = true,
+            Err(e) => return Err(e),
+        }
+        state.store.clear_live_moves(self.match_id).await?;
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    fn of(game: &Game) -> Self {
+        Self {
+            at: Instant::now(),
+            board: game.get_board_data(),
+            completed: game.is_completed(),
+        }
+    }
+}
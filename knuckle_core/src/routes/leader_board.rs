@@ -1,54 +1,79 @@
-use crate::{pool_extractor::DatabaseConnection, UserCreateError};
-use axum::Json;
-use lib_knuckle::api_interfaces::{LeaderBoard, LeaderBoardEntry};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use lib_knuckle::api_interfaces::{
+    LeaderBoard, LeaderBoardRank, LeaderBoardWindow, RatingLeaderBoard,
+};
+use serde::Deserialize;
+
+use crate::{store::SharedStore, UserCreateError};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderBoardQuery {
+    #[serde(default)]
+    window: LeaderBoardWindow,
+    /// Zero based
+    page: Option<u32>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RankQuery {
+    /// Public key of the player
+    player: String,
+    #[serde(default)]
+    window: LeaderBoardWindow,
+}
+
+/// Midnight UTC of the first day in the window
+fn window_start(
+    window: LeaderBoardWindow,
+) -> Result<Option<SystemTime>, UserCreateError> {
+    const DAY: u64 = 24 * 60 * 60;
+    let Some(days) = window.days() else {
+        return Ok(None);
+    };
+    let today = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / DAY * DAY;
+    Ok(Some(
+        UNIX_EPOCH + Duration::from_secs(today - (days - 1) * DAY),
+    ))
+}
 
 pub async fn leader_board(
-    DatabaseConnection(conn): DatabaseConnection,
-) -> Result<axum::Json<LeaderBoard>, UserCreateError> {
-    let leader_board = conn
-        .query(
-            /* language=postgresql */
-            "
-SELECT
-    p.name,
-    COALESCE(SUM(
-        CASE
-            WHEN m.player1 = p.player_id THEN m.points_p1
-            WHEN m.player2 = p.player_id THEN m.points_p2
-        END
-    ), 0) AS total_points,
-    COUNT(DISTINCT m.match_id) AS total_games,
-    COUNT(CASE WHEN m.winner = p.player_id THEN 1 END) AS total_wins
-FROM
-    players p
-LEFT JOIN
-    matches m ON p.player_id IN (m.player1, m.player2)
-GROUP BY
-    p.player_id
-ORDER BY
-    total_points DESC, total_wins DESC, total_games DESC, p.name;
-            ",
-            &[],
-        )
-        .await?;
-    let leader_board = leader_board
-        .iter()
-        .map(|row| {
-            let name: &str = row.get(0);
-            let total_points: i64 = row.get(1);
-            let total_games: i64 = row.get(2);
-            let total_wins: i64 = row.get(3);
-            LeaderBoardEntry {
-                name: name.to_owned(),
-                total_points: total_points as u32,
-                total_games: total_games as u32,
-                total_wins: total_wins as u32,
-            }
-        })
-        .collect::<Vec<_>>();
-
-    Ok(Json(LeaderBoard {
-        total: leader_board.len() as u32,
-        entries: leader_board,
-    }))
+    State(store): State<SharedStore>,
+    Query(query): Query<LeaderBoardQuery>,
+) -> Result<Json<LeaderBoard>, UserCreateError> {
+    let page = query.page.unwrap_or_default();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let start = window_start(query.window)?;
+    Ok(Json(store.leader_board(start, page, limit).await?))
+}
+
+/// Position of a single player, so the client does not have to page through
+/// the whole leaderboard
+pub async fn leader_board_rank(
+    State(store): State<SharedStore>,
+    Query(query): Query<RankQuery>,
+) -> Result<Json<LeaderBoardRank>, UserCreateError> {
+    let start = window_start(query.window)?;
+    Ok(Json(
+        store
+            .leader_board_rank(start, STANDARD_NO_PAD.decode(&query.player)?)
+            .await?,
+    ))
+}
+
+/// Players ordered by their Glicko-2 rating, only players with at least one
+/// rated game show up
+pub async fn rating_leader_board(
+    State(store): State<SharedStore>,
+) -> Result<axum::Json<RatingLeaderBoard>, UserCreateError> {
+    Ok(Json(store.rating_leader_board().await?))
 }
@@ -0,0 +1,17 @@
+use axum::{extract::Path, Extension, Json};
+use lib_knuckle::api_interfaces::LobbyInfo;
+
+use crate::{lobby, AppState, UserCreateError};
+
+/// Lets a client show who hosts a lobby and ask for its password before
+/// joining
+pub async fn lobby_info(
+    Extension(state): Extension<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<LobbyInfo>, UserCreateError> {
+    let code = lobby::normalize_code(&code);
+    let lobby = lobby::find(&state, &code)
+        .await
+        .ok_or(UserCreateError::LobbyNotFound)?;
+    Ok(Json(lobby.info(&code)))
+}
@@ -0,0 +1,73 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use lib_knuckle::api_interfaces::{MatchDetails, MatchList, MatchResult};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    store::{MatchFilter, SharedStore},
+    UserCreateError,
+};
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct MatchesQuery {
+    /// Public key of the player whose matches are listed
+    player: String,
+    result: Option<MatchResult>,
+    /// Public key of the opponent
+    opponent: Option<String>,
+    /// Unix millis, inclusive
+    from: Option<u64>,
+    /// Unix millis, exclusive
+    to: Option<u64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+fn to_system_time(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+pub async fn list_matches(
+    State(store): State<SharedStore>,
+    Query(query): Query<MatchesQuery>,
+) -> Result<Json<MatchList>, UserCreateError> {
+    let player_id = store
+        .player_id(STANDARD_NO_PAD.decode(&query.player)?)
+        .await?
+        .ok_or(UserCreateError::UserDoesNotExist)?;
+    let filter = MatchFilter {
+        opponent: query
+            .opponent
+            .as_deref()
+            .map(|key| STANDARD_NO_PAD.decode(key))
+            .transpose()?,
+        result: query.result,
+        from: query.from.map(to_system_time),
+        to: query.to.map(to_system_time),
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+        offset: query.offset.unwrap_or_default(),
+    };
+
+    Ok(Json(store.list_matches(player_id, filter).await?))
+}
+
+pub async fn match_details(
+    State(store): State<SharedStore>,
+    Path(match_id): Path<Uuid>,
+) -> Result<Json<MatchDetails>, UserCreateError> {
+    Ok(Json(
+        store
+            .match_details(match_id)
+            .await?
+            .ok_or(UserCreateError::MatchNotFound)?,
+    ))
+}
@@ -0,0 +1,11 @@
+use axum::{http::header::CONTENT_TYPE, response::IntoResponse, Extension};
+use prometheus::TEXT_FORMAT;
+
+use crate::{AppState, UserCreateError};
+
+pub async fn metrics(
+    Extension(state): Extension<AppState>,
+) -> Result<impl IntoResponse, UserCreateError> {
+    let body = state.metrics.render(&state).await?;
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], body))
+}
@@ -11,12 +11,20 @@
 
 mod leader_board;
 pub use leader_board::*;
+mod lobby;
+pub use lobby::*;
+mod matches;
+pub use matches::*;
+mod metrics;
+pub use metrics::*;
 mod set_name;
 pub use set_name::*;
 mod signup;
 pub use signup::*;
 mod submit_game;
 pub use submit_game::*;
+mod tournament;
+pub use tournament::*;
 mod websocket;
 
 pub use websocket::*;
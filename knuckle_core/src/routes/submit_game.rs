@@ -2,25 +2,112 @@ use axum::{Extension, Json};
 use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
 use lib_knuckle::{
     api_interfaces::GameBody,
-    game::{Game, GameEnd, ServerGameInfo},
+    game::{BoardData, Game, GameEnd, HistoryForSql, ServerGameInfo},
     keys::Keys,
+    rating::Outcome,
+    seed::signed_seed,
     signature_from_string, verifying_key_from_string,
 };
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio_postgres::types::ToSql;
+
 use uuid::Uuid;
 
-use crate::{pool_extractor::DatabaseConnection, AppState, UserCreateError};
+use crate::{
+    store::{FinishedMatch, StartedMatch},
+    tournament, AppState, UserCreateError,
+};
 
-fn unix_timestamp_to_system_time(timestamp: u64) -> SystemTime {
-    UNIX_EPOCH + Duration::from_millis(timestamp)
+/// Players and moves of a game whose signatures all checked out
+struct CheckedGame {
+    user_id: Uuid,
+    partner_id: Uuid,
+    board: BoardData,
+    moves: Vec<HistoryForSql>,
 }
 
 pub async fn submit_game(
-    DatabaseConnection(conn): DatabaseConnection,
     Extension(state): Extension<AppState>,
     Json(body): Json<GameBody>,
 ) -> Result<String, UserCreateError> {
+    // the server never sees when the moves of a WebRTC game were played, so
+    // it can't tell whether a turn really ran out
+    let saved = match body.moves.iter().any(|item| item.is_timeout_claim()) {
+        true => Err(UserCreateError::UnverifiedTimeoutClaim),
+        false => validate_and_store(&state, body).await,
+    };
+    state.metrics.game_submitted(&saved);
+    saved?;
+    Ok("Ok".to_owned())
+}
+
+/// Validates a finished game against the server signed match parameters and
+/// stores it with all of its moves and the new ratings
+pub(crate) async fn save_game(
+    state: &AppState,
+    body: GameBody,
+) -> Result<(), UserCreateError> {
+    let saved = validate_and_store(state, body).await;
+    state.metrics.game_submitted(&saved);
+    saved
+}
+
+/// Stores a game a player walked away from as a forfeit loss for them, the
+/// moves until then still have to be valid
+pub(crate) async fn save_abandoned(
+    state: &AppState,
+    body: GameBody,
+    leaver_key: &str,
+) -> Result<(), UserCreateError> {
+    let checked = check_game(state, &body).await?;
+    if checked.board.is_completed() {
+        return Err(UserCreateError::GameAlreadyCompleted);
+    }
+    let (winner, outcome) = match leaver_key == body.your_key {
+        true => (checked.partner_id, Outcome::LossByForfeit),
+        false => (checked.user_id, Outcome::WinByForfeit),
+    };
+    store_game(state, &body, checked, Some(winner), "forfeit", outcome).await
+}
+
+async fn validate_and_store(
+    state: &AppState,
+    body: GameBody,
+) -> Result<(), UserCreateError> {
+    let checked = check_game(state, &body).await?;
+    let (user_id, partner_id) = (checked.user_id, checked.partner_id);
+    let (winner, result, outcome) = match checked.board.winner {
+        GameEnd {
+            winner: true,
+            win_by_tie: false,
+            win_by_forfeit: false,
+        } => (Some(user_id), "win", Outcome::Win),
+        GameEnd {
+            winner: false,
+            win_by_tie: false,
+            win_by_forfeit: false,
+        } => (Some(partner_id), "win", Outcome::Loss),
+        GameEnd {
+            win_by_tie: true, ..
+        } => (None, "tie", Outcome::Tie),
+        GameEnd {
+            winner: true,
+            win_by_forfeit: true,
+            ..
+        } => (Some(user_id), "forfeit", Outcome::WinByForfeit),
+        GameEnd {
+            winner: false,
+            win_by_forfeit: true,
+            ..
+        } => (Some(partner_id), "forfeit", Outcome::LossByForfeit),
+    };
+    store_game(state, &body, checked, winner, result, outcome).await
+}
+
+/// Checks the game against the server signed match parameters and the
+/// signatures of every move
+async fn check_game(
+    state: &AppState,
+    body: &GameBody,
+) -> Result<CheckedGame, UserCreateError> {
     if body.your_key == body.opponent_key {
         return Err(UserCreateError::BadRequest(
             "Good luck playing against yourself :)".to_owned(),
@@ -34,7 +121,16 @@ pub async fn submit_game(
         true => (body.your_key.clone(), body.opponent_key.clone()),
         false => (body.opponent_key.clone(), body.your_key.clone()),
     };
-    let data_to_check = format!("{}:{}:{}:{}", body.seed, body.time, keys.0, keys.1);
+    // seeds from a commit-reveal are signed through the commitments, so the
+    // contributions have to hash to them and derive the submitted seed
+    let data_to_check = format!(
+        "{}:{}:{}:{}:{}",
+        signed_seed(body.seed, body.seed_reveal.as_ref())?,
+        body.time,
+        keys.0,
+        keys.1,
+        body.rules.encode()
+    );
     let is_valid = state
         .dice_seed_signing_keys
         .lock()
@@ -54,138 +150,89 @@ pub async fn submit_game(
         }
     };
 
-    let user_id: Uuid = conn
-        .query_one(
-            /* language=postgresql */
-            "SELECT player_id FROM players WHERE public_key = $1",
-            &[&STANDARD_NO_PAD.decode(&body.your_key)?],
-        )
+    let user_id = state
+        .store
+        .player_id(STANDARD_NO_PAD.decode(&body.your_key)?)
         .await?
-        .get(0);
-    let partner_id: Uuid = conn
-        .query_one(
-            /* language=postgresql */
-            "SELECT player_id FROM players WHERE public_key = $1",
-            &[&STANDARD_NO_PAD.decode(&body.opponent_key)?],
-        )
+        .ok_or(UserCreateError::UserDoesNotExist)?;
+    let partner_id = state
+        .store
+        .player_id(STANDARD_NO_PAD.decode(&body.opponent_key)?)
         .await?
-        .get(0);
+        .ok_or(UserCreateError::UserDoesNotExist)?;
 
     tracing::debug!("User {:?} Partner {:?}", user_id, partner_id);
 
-    let game = Game::validate_entire_game(
+    let (board_data, sql_history) = Game::validate_entire_game(
         Keys::VerifyOnly {
             my_keys: verify_your,
             other_keys: verify_other,
         },
         (user_id, partner_id),
-        (3, 3),
-        ServerGameInfo::new(body.seed, body.starting),
-        body.moves,
-    );
-
-    if let Err(e) = game {
-        return Err(UserCreateError::BadRequest(e.to_string()));
-    };
-
-    let (board_data, sql_history) = game.map_err(UserCreateError::BadRequest)?;
-
-    let (winner, result) = match board_data.winner {
-        GameEnd {
-            winner: true,
-            win_by_tie: false,
-            win_by_forfeit: false,
-        } => (Some(user_id), "win".to_string()),
-        GameEnd {
-            winner: false,
-            win_by_tie: false,
-            win_by_forfeit: false,
-        } => (Some(partner_id), "win".to_string()),
-        GameEnd {
-            win_by_tie: true, ..
-        } => (None, "tie".to_string()),
-        GameEnd {
-            winner: true,
-            win_by_forfeit: true,
-            ..
-        } => (Some(user_id), "forfeit".to_string()),
-        GameEnd {
-            winner: false,
-            win_by_forfeit: true,
-            ..
-        } => (Some(partner_id), "forfeit".to_string()),
-    };
+        body.rules,
+        ServerGameInfo::new(body.seed, body.starting)
+            .with_time(body.time)
+            .with_reveal(body.seed_reveal.as_ref()),
+        body.moves.clone(),
+    )?;
+    Ok(CheckedGame {
+        user_id,
+        partner_id,
+        board: board_data,
+        moves: sql_history,
+    })
+}
 
-    let existing_match = conn
-        .query_one(
-            /* language=postgresql */
-            "SELECT match_id, created_at FROM started_matches WHERE seed = $1 AND time = $2",
-            &[&(body.seed as i64), &(body.time as i64)],
-        )
-        .await
-        .map_err(|_e| UserCreateError::BadRequest("Cant find match".to_owned()))?;
-
-    let match_id: Uuid = existing_match.get(0);
-    let created_at: SystemTime = existing_match.get(1);
-
-    conn.query(
-        /* language=postgresql */
-        "INSERT INTO matches(
-        match_id,
-        seed,
-        time,
-        player1,
-        player2,
-        winner,
-        result,
-        points_p1,
-        points_p2,
-        started_at
-    ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
-        &[
-            &match_id,
-            &(body.seed as i64),
-            &(body.time as i64),
-            &user_id,
-            &partner_id,
-            &winner,
-            &result,
-            &(board_data.points.me.iter().sum::<u32>() as i16),
-            &(board_data.points.other.iter().sum::<u32>() as i16),
-            &created_at,
-        ],
-    )
-    .await?;
-
-    let mut query = String::from(
-        "INSERT INTO moves (match_id, player_id, number, x, seq, created_at) VALUES ",
+async fn store_game(
+    state: &AppState,
+    body: &GameBody,
+    checked: CheckedGame,
+    winner: Option<Uuid>,
+    result: &'static str,
+    outcome: Outcome,
+) -> Result<(), UserCreateError> {
+    let CheckedGame {
+        user_id,
+        partner_id,
+        board: board_data,
+        moves: sql_history,
+    } = checked;
+    let started = state
+        .store
+        .started_match(body.seed, body.time)
+        .await?
+        .ok_or_else(|| UserCreateError::BadRequest("Cant find match".to_owned()))?;
+    let match_id = started.match_id;
+    let points = (
+        board_data.points.me.iter().sum::<u32>(),
+        board_data.points.other.iter().sum::<u32>(),
     );
-    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-
-    for (i, item) in sql_history.iter().enumerate() {
-        if i != 0 {
-            query.push_str(", ");
-        }
-        query.push_str(&format!(
-            "(${}, ${}, ${}, ${}, ${}, ${})",
-            i * 6 + 1,
-            i * 6 + 2,
-            i * 6 + 3,
-            i * 6 + 4,
-            i * 6 + 5,
-            i * 6 + 6,
-        ));
-        params.push(&match_id);
-        params.push(&item.player);
-        params.push(Box::leak(Box::new(item.number as i16)));
-        params.push(Box::leak(Box::new(item.x as i16)));
-        params.push(Box::leak(Box::new(item.seq as i32)));
-        params.push(Box::leak(Box::new(unix_timestamp_to_system_time(item.now))));
-    }
 
-    conn.execute(&query, params.as_slice()).await?;
+    state
+        .store
+        .save_match(FinishedMatch {
+            // the submitter is always stored as the first player
+            started: StartedMatch {
+                players: (user_id, partner_id),
+                ..started
+            },
+            winner,
+            result,
+            outcome,
+            points,
+            rules: body.rules,
+            moves: sql_history,
+        })
+        .await?;
+
+    let winner_key = match winner {
+        Some(id) if id == user_id => Some(body.your_key.as_str()),
+        Some(_) => Some(body.opponent_key.as_str()),
+        None => None,
+    };
+    tournament::record_result(state, match_id, winner_key).await;
 
     println!("signature is valid");
 
-    Ok("Ok".to_owned())
+    Ok(())
 }
@@ -0,0 +1,85 @@
+use axum::{extract::Path, Extension, Json};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use lib_knuckle::{
+    api_interfaces::{NewTournament, TournamentAction, TournamentInfo},
+    signature_from_string, verifying_key_from_string,
+};
+use uuid::Uuid;
+
+use crate::{tournament, AppState, UserCreateError};
+
+fn verify(pub_key: &str, signature: &str, message: &str) -> Result<(), UserCreateError> {
+    match (
+        signature_from_string(signature),
+        verifying_key_from_string(pub_key),
+    ) {
+        (Some(signature), Some(pub_key)) => {
+            Ok(pub_key.verify_strict(message.as_bytes(), &signature)?)
+        }
+        _ => Err(UserCreateError::InvalidSignature),
+    }
+}
+
+pub async fn tournaments(
+    Extension(state): Extension<AppState>,
+) -> Json<Vec<TournamentInfo>> {
+    Json(tournament::list(&state).await)
+}
+
+/// Signed by the organizer with `create:<tournament_id>:<name>`
+pub async fn create_tournament(
+    Extension(state): Extension<AppState>,
+    Json(body): Json<NewTournament>,
+) -> Result<Json<TournamentInfo>, UserCreateError> {
+    let tournament_id = Uuid::parse_str(&body.tournament_id)?;
+    verify(
+        &body.organizer_key,
+        &body.signature,
+        &format!("create:{tournament_id}:{}", body.name),
+    )?;
+    Ok(Json(tournament::create(&state, tournament_id, body).await?))
+}
+
+pub async fn tournament_info(
+    Extension(state): Extension<AppState>,
+    Path(tournament_id): Path<Uuid>,
+) -> Result<Json<TournamentInfo>, UserCreateError> {
+    Ok(Json(tournament::info(&state, tournament_id).await?))
+}
+
+/// Signed by the player with `register:<tournament_id>`
+pub async fn register_tournament(
+    Extension(state): Extension<AppState>,
+    Path(tournament_id): Path<Uuid>,
+    Json(body): Json<TournamentAction>,
+) -> Result<Json<TournamentInfo>, UserCreateError> {
+    verify(
+        &body.pub_key,
+        &body.signature,
+        &format!("register:{tournament_id}"),
+    )?;
+    state
+        .store
+        .player_id(STANDARD_NO_PAD.decode(&body.pub_key)?)
+        .await?
+        .ok_or(UserCreateError::UserDoesNotExist)?;
+    Ok(Json(
+        tournament::register(&state, tournament_id, body.pub_key).await?,
+    ))
+}
+
+/// Signed by the organizer with `start:<tournament_id>`
+pub async fn start_tournament(
+    Extension(state): Extension<AppState>,
+    Path(tournament_id): Path<Uuid>,
+    Json(body): Json<TournamentAction>,
+) -> Result<Json<TournamentInfo>, UserCreateError> {
+    verify(
+        &body.pub_key,
+        &body.signature,
+        &format!("start:{tournament_id}"),
+    )?;
+    Ok(Json(
+        tournament::start(&state, tournament_id, &body.pub_key).await?,
+    ))
+}
@@ -1,4 +1,7 @@
-use std::time::{Instant, SystemTime};
+use std::{
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 
 use axum::{
     extract::{
@@ -8,60 +11,63 @@ use axum::{
     Extension,
 };
 use base64::{engine::general_purpose::STANDARD_NO_PAD, prelude::Engine};
-use ed25519_dalek::Signer;
 use futures::{SinkExt, StreamExt};
-use lib_knuckle::{signature_from_string, verifying_key_from_string};
-use rand_core::OsRng;
-use serde::{Deserialize, Serialize};
-use uuid::{NoContext, Timestamp, Uuid};
+use lib_knuckle::{
+    protocol::{negotiate, ClientMessage, ServerMessage},
+    rules::RuleSet,
+    seed::validate_commitment,
+    signature_from_string, verifying_key_from_string,
+};
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+use uuid::Uuid;
 
 use crate::{
-    ice_servers::{IceServerData, IceServers},
-    pool_extractor::{Conn, DatabaseConnection},
-    AllUsers, AppState, User, UserCreateError,
+    heartbeat, lobby,
+    matchmaker::{reveal_seed, skill_estimate, start_match},
+    relay::{Rematch, SharedRelay},
+    spectate,
+    store::SharedStore,
+    tournament, AllUsers, AppState, User, UserCreateError,
 };
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     Extension(state): Extension<AppState>,
-    Extension(ice_server_provider): Extension<IceServerData>,
-    DatabaseConnection(conn): DatabaseConnection,
 ) -> impl axum::response::IntoResponse {
     tracing::debug!("Got WS connection");
     ws.on_upgrade(|socket| async {
-        if let Err(e) = handle_socket(socket, state, conn, ice_server_provider).await {
+        if let Err(e) = handle_socket(socket, state).await {
             tracing::error!("Error in websocket: {e:?}");
         }
     })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
-enum SendMessages {
-    #[serde(rename = "verify")]
-    Verify { verify_time: String },
-    #[serde(rename = "paired")]
-    Paired {
-        public_key: String,
-        partner_key: String,
-        initiator: bool,
-        seed: u32,
-        signature: String,
-        time: u64,
-        ice_servers: IceServers,
-    },
-    #[serde(rename = "partner-left")]
-    PartnerLeft,
-    #[serde(rename = "disconnected")]
-    Disconnected { reason: String, name: String },
+pub trait ToTextMessage {
+    fn to_text_message(&self) -> Result<Message, serde_json::Error>;
 }
 
-impl SendMessages {
+impl ToTextMessage for ServerMessage {
     fn to_text_message(&self) -> Result<Message, serde_json::Error> {
         Ok(Message::Text(serde_json::to_string(self)?))
     }
 }
 
+/// Answers a message the server could not act on, the connection stays open
+pub(crate) fn send_error(
+    sender: &UnboundedSender<Message>,
+    error: &UserCreateError,
+) -> Result<(), UserCreateError> {
+    tracing::debug!("Rejected message: {error:?}");
+    sender.send(
+        ServerMessage::Error {
+            name: error.get_name().to_owned(),
+            reason: error.to_string(),
+        }
+        .to_text_message()?,
+    )?;
+    Ok(())
+}
+
 trait TrickedShenanigans<T> {
     fn ok_or_badrequest(self, error: &str) -> Result<T, UserCreateError>;
     fn ok_or_internal(self, error: &str) -> Result<T, UserCreateError>;
@@ -92,32 +98,29 @@ fn verify_signature(
     Ok(())
 }
 
-async fn resolve_user_name(conn: &Conn, pub_key: &str) -> Result<Uuid, UserCreateError> {
-    let data = conn
-        .query_one(
-            "SELECT player_id FROM players WHERE public_key = $1",
-            &[&STANDARD_NO_PAD.decode(pub_key).unwrap()],
-        )
-        .await
-        .map_err(|_e| UserCreateError::UserDoesNotExist)?;
-    let id: Uuid = data.get(0);
-    Ok(id)
+async fn resolve_user_name(
+    store: &SharedStore,
+    pub_key: &str,
+) -> Result<Uuid, UserCreateError> {
+    store
+        .player_id(STANDARD_NO_PAD.decode(pub_key)?)
+        .await?
+        .ok_or(UserCreateError::UserDoesNotExist)
 }
 
 pub async fn verify_user(
-    conn: &Conn,
+    store: &SharedStore,
     pub_key: &str,
-    signature: Option<&str>,
+    signature: &str,
     queue: Option<&str>,
     secret: u64,
     all_users: AllUsers,
     user_id: Uuid,
 ) -> Result<Uuid, UserCreateError> {
-    let signature = signature.ok_or_badrequest("Missing signature")?;
     verify_signature(signature, pub_key, &secret.to_string())?;
 
     tracing::debug!("Setting pub_key and player_id");
-    let id = resolve_user_name(conn, pub_key).await?;
+    let id = resolve_user_name(store, pub_key).await?;
 
     all_users
         .update_async(&user_id, |_, item| {
@@ -136,25 +139,283 @@ pub async fn verify_user(
     Ok(queue_name)
 }
 
+fn requested_rules(rules: Option<RuleSet>) -> Result<RuleSet, UserCreateError> {
+    let rules = rules.unwrap_or_default();
+    rules.validate()?;
+    Ok(rules)
+}
+
+/// Remembers the commitment for the next match, players that never commit get
+/// a server picked seed
+async fn requested_commitment(
+    state: &AppState,
+    user_id: Uuid,
+    commitment: Option<String>,
+) -> Result<(), UserCreateError> {
+    if let Some(commitment) = commitment {
+        validate_commitment(&commitment)?;
+        state
+            .all_users
+            .update_async(&user_id, |_, item| {
+                item.set_seed_commitment(commitment);
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// Hidden dice are rolled from the seed contributions, so those games can only
+/// start once the player committed to one
+fn check_commitment(
+    state: &AppState,
+    user_id: Uuid,
+    rules: &RuleSet,
+) -> Result<(), UserCreateError> {
+    let committed = state
+        .all_users
+        .read(&user_id, |_, user| user.seed_commitment.is_some())
+        .unwrap_or(false);
+    if rules.hidden_dice && !committed {
+        return Err(UserCreateError::BadRequest(
+            "Hidden dice need a seed commitment".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Acts on one json message, errors the player caused are answered by the
+/// caller and the others end the connection
+async fn handle_message(
+    state: &AppState,
+    tx: &UnboundedSender<Message>,
+    user_id: Uuid,
+    secret: u64,
+    spectating: &mut Option<JoinHandle<()>>,
+    message: ClientMessage,
+    text: String,
+) -> Result<(), UserCreateError> {
+    match message {
+        ClientMessage::Hello { version } => {
+            let version = negotiate(version)?;
+            tx.send(ServerMessage::Hello { version }.to_text_message()?)?;
+        }
+        ClientMessage::Join {
+            pub_key,
+            signature,
+            queue,
+            tournament,
+            lobby: code,
+            password,
+            rules,
+            commitment,
+        } => {
+            let queue_name = verify_user(
+                &state.store,
+                &pub_key,
+                &signature,
+                queue.as_deref(),
+                secret,
+                state.all_users.clone(),
+                user_id,
+            )
+            .await?;
+            requested_commitment(state, user_id, commitment).await?;
+
+            // tournament players are paired by their bracket
+            if let Some(tournament_id) = tournament {
+                tournament::wait(
+                    state,
+                    Uuid::parse_str(&tournament_id)?,
+                    user_id,
+                    &pub_key,
+                )
+                .await?;
+                return Ok(());
+            }
+
+            // lobby guests wait until the host starts the match
+            if let Some(code) = code {
+                lobby::join(state, &code, user_id, pub_key, password.as_deref()).await?;
+                return Ok(());
+            }
+
+            let rules = requested_rules(rules)?;
+            check_commitment(state, user_id, &rules)?;
+            let player_id = state
+                .all_users
+                .read(&user_id, |_, user| user.player_id)
+                .flatten()
+                .ok_or_internal("User player_id not set")?;
+            let skill = skill_estimate(&state.store, player_id).await?;
+            let queued_at = Instant::now();
+            state
+                .all_users
+                .update_async(&user_id, |_, item| {
+                    item.set_rules(rules).set_skill(skill);
+                    item.in_queue_since = queued_at;
+                })
+                .await;
+
+            // the matchmaker task pairs players up from the queue
+            let mut entry = state
+                .queues
+                .entry_async(queue_name)
+                .await
+                .or_insert(Vec::with_capacity(2));
+            entry.get_mut().push(user_id);
+            drop(entry);
+
+            // bots only fill in for the public queue and can not
+            // play hidden dice without a seed reveal
+            if let Some(bot) = state
+                .bot
+                .clone()
+                .filter(|_| queue_name.is_nil() && !rules.hidden_dice)
+            {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(bot.wait()).await;
+                    // a player that was paired and queued again since then
+                    // belongs to the timer of the later join
+                    let same_wait = state
+                        .all_users
+                        .read_async(&user_id, |_, user| user.in_queue_since == queued_at)
+                        .await
+                        .unwrap_or(false);
+                    let still_waiting = same_wait
+                        && state
+                            .queues
+                            .update_async(&queue_name, |_, q| {
+                                let len = q.len();
+                                q.retain(|&id| id != user_id);
+                                len != q.len()
+                            })
+                            .await
+                            .unwrap_or(false);
+                    if still_waiting {
+                        if let Err(e) = bot.start_game(&state, user_id).await {
+                            tracing::error!("Failed starting bot game: {e:?}");
+                        }
+                    }
+                });
+            }
+        }
+        ClientMessage::CreateLobby {
+            pub_key,
+            signature,
+            password,
+            rules,
+            commitment,
+        } => {
+            verify_user(
+                &state.store,
+                &pub_key,
+                &signature,
+                None,
+                secret,
+                state.all_users.clone(),
+                user_id,
+            )
+            .await?;
+            requested_commitment(state, user_id, commitment).await?;
+            let rules = requested_rules(rules)?;
+            check_commitment(state, user_id, &rules)?;
+            lobby::create(state, user_id, pub_key, password.as_deref(), rules).await?;
+        }
+        ClientMessage::StartLobby => lobby::start(state, user_id).await?,
+        ClientMessage::Commit { commitment } => {
+            requested_commitment(state, user_id, Some(commitment)).await?;
+        }
+        ClientMessage::Reveal { contribution } => {
+            reveal_seed(state, user_id, &contribution).await?;
+        }
+        ClientMessage::Rematch { commitment } => {
+            requested_commitment(state, user_id, commitment).await?;
+            let relay = state
+                .all_users
+                .read(&user_id, |_, user| user.relay.clone())
+                .flatten()
+                .ok_or_badrequest("No match to rematch")?;
+            let (rematch, series_id) = {
+                let mut relay = relay.lock().await;
+                check_commitment(state, user_id, &relay.body().rules)?;
+                (relay.request_rematch(user_id)?, relay.series_id())
+            };
+            match rematch {
+                Rematch::Waiting { partner } => {
+                    state.send_to(partner, ServerMessage::RematchRequested)?
+                }
+                Rematch::Agreed { initiator, other } => {
+                    start_match(state, None, initiator, other, Some(series_id)).await?;
+                }
+            }
+        }
+        ClientMessage::DeclineRematch => {
+            let relay = state
+                .all_users
+                .read(&user_id, |_, user| user.relay.clone())
+                .flatten()
+                .ok_or_badrequest("No match to rematch")?;
+            let partner = relay.lock().await.decline_rematch(user_id);
+            if let Some(partner) = partner {
+                state.send_to(partner, ServerMessage::RematchDeclined)?;
+            }
+        }
+        // spectating needs no account
+        ClientMessage::ListLiveMatches => {
+            tx.send(
+                ServerMessage::LiveMatches {
+                    matches: spectate::live_matches(state).await,
+                }
+                .to_text_message()?,
+            )?;
+        }
+        ClientMessage::Spectate { match_id } => {
+            let task = spectate::spectate(state, Uuid::parse_str(&match_id)?, tx.clone())
+                .await?;
+            if let Some(previous) = spectating.replace(task) {
+                previous.abort();
+            }
+        }
+        ClientMessage::StopSpectating => {
+            if let Some(task) = spectating.take() {
+                task.abort();
+            }
+        }
+        ClientMessage::IceCandidate { .. }
+        | ClientMessage::Offer { .. }
+        | ClientMessage::Answer { .. }
+        | ClientMessage::Candidate { .. }
+        | ClientMessage::Relay => {
+            let partner_id = state
+                .all_users
+                .get(&user_id)
+                .ok_or_internal("User not in all_users something broke lol")?
+                .partner_id;
+            if let Some(partner_user_id) = partner_id {
+                if let Some(partner_user) = state.all_users.get(&partner_user_id) {
+                    partner_user.sender.send(Message::Text(text))?;
+                }
+            }
+        }
+        ClientMessage::Reconnect { pub_key, signature } => {
+            verify_signature(&signature, &pub_key, &secret.to_string())?;
+            let player_id = resolve_user_name(&state.store, &pub_key).await?;
+            resume_match(state, user_id, player_id, &pub_key).await?;
+        }
+    }
+    Ok(())
+}
+
 pub async fn handle_socket(
     socket: WebSocket,
     state: AppState,
-    conn: Conn,
-    ice_server_provider: IceServerData,
 ) -> Result<(), UserCreateError> {
     let (mut sender, mut receiver) = socket.split();
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
-    let mut csprng = OsRng;
-
     let user_id = Uuid::new_v4();
-    let user = User {
-        partner_id: None,
-        sender: tx.clone(),
-        pub_key: None,
-        player_id: None,
-        in_queue_since: Instant::now(),
-    };
+    let user = User::new(tx.clone());
 
     tracing::debug!("{:?}", &state.all_users);
     let secret = SystemTime::now()
@@ -165,7 +426,7 @@ pub async fn handle_socket(
 
     sender
         .send(
-            SendMessages::Verify {
+            ServerMessage::Verify {
                 verify_time: secret.to_string(),
             }
             .to_text_message()?,
@@ -181,157 +442,96 @@ pub async fn handle_socket(
         }
     });
 
-    let mut queue_name = Uuid::nil();
+    let heartbeat = state.heartbeat;
+    let pinger = heartbeat::ping(heartbeat, tx.clone());
+    let session_end = Instant::now() + heartbeat.session_timeout;
+    let mut spectating: Option<JoinHandle<()>> = None;
 
     let data_handler = async {
-        while let Some(Ok(message)) = receiver.next().await {
+        loop {
+            // half-open connections never end the stream, so stop waiting
+            // once not even a pong came back
+            let idle_end = Instant::now() + heartbeat.idle_timeout;
+            let message = match tokio::time::timeout_at(
+                idle_end.min(session_end).into(),
+                receiver.next(),
+            )
+            .await
+            {
+                Ok(Some(Ok(message))) => message,
+                Ok(_) => break,
+                Err(_) if Instant::now() >= session_end => {
+                    return Err(UserCreateError::SessionExpired)
+                }
+                Err(_) => return Err(UserCreateError::IdleTimeout),
+            };
+            state
+                .all_users
+                .update_async(&user_id, |_, user| user.last_seen = Instant::now())
+                .await;
             if let Message::Text(text) = message {
-                let data: serde_json::Value = serde_json::from_str(&text)?;
-                match data["type"].as_str() {
-                    Some("join") => {
-                        let pub_key = data["pub_key"]
-                            .as_str()
-                            .ok_or_badrequest("Missing pub_key")?;
-                        queue_name = verify_user(
-                            &conn,
-                            pub_key,
-                            data["signature"].as_str(),
-                            data["queue"].as_str(),
-                            secret,
-                            state.all_users.clone(),
-                            user_id,
-                        )
-                        .await?;
-
-                        let pop = || async {
-                            let mut entry = state
-                                .queues
-                                .entry_async(queue_name)
-                                .await
-                                .or_insert(Vec::with_capacity(2));
-                            entry.get_mut().pop()
-                        };
-
-                        if let Some(partner_user_id) = pop().await {
-                            tracing::debug!("Partner found!");
-                            let partner_user =
-                                state.get_user_clone(&partner_user_id).ok_or_internal(
-                                    "Partner not in all_users something broke lol",
-                                )?;
-
-                            let user = state.get_user_clone(&user_id).ok_or_internal(
-                                "User not in all_users something broke lol",
-                            )?;
-
-                            tracing::debug!("{:?} {:?}", &user, &partner_user);
-                            let seed = rand_core::RngCore::next_u32(&mut csprng);
-                            let time = std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)?
-                                .as_secs();
-
-                            let user_pub_key = pub_key.to_owned();
-                            let partner_pub_key = partner_user
-                                .pub_key
-                                .clone()
-                                .ok_or_internal("Partner pub_key not set")?;
-
-                            let game_signature = STANDARD_NO_PAD.encode(
-                                state
-                                    .dice_seed_signing_keys
-                                    .lock()
-                                    .await
-                                    .sign(
-                                        format!(
-                                            "{seed}:{time}:{}:{}",
-                                            user_pub_key, partner_pub_key
-                                        )
-                                        .as_bytes(),
-                                    )
-                                    .to_bytes(),
-                            );
-
-                            conn.execute(
-                                /* language=postgresql */
-                                "INSERT INTO started_matches (match_id, time, seed, player1, player2) VALUES ($1, $2, $3, $4, $5)",
-                            &[&Uuid::new_v7(Timestamp::now(NoContext)), &(time as i64),  &(seed as i64), &user.player_id.unwrap(), &partner_user.player_id.unwrap()])
-                            .await?;
-
-                            tracing::debug!("Sending Paired");
-
-                            let ice_servers =
-                                ice_server_provider.get_ice_servers().await?;
-
-                            partner_user.sender.send(
-                                SendMessages::Paired {
-                                    public_key: partner_pub_key.clone(),
-                                    partner_key: user_pub_key.clone(),
-                                    initiator: false,
-                                    seed,
-                                    signature: game_signature.clone(),
-                                    ice_servers: ice_servers.clone(),
-                                    time,
-                                }
-                                .to_text_message()?,
-                            )?;
-                            tracing::debug!("Sending User Text");
-
-                            tx.send(
-                                SendMessages::Paired {
-                                    public_key: user_pub_key,
-                                    partner_key: partner_pub_key,
-                                    initiator: true,
-                                    seed,
-                                    signature: game_signature,
-                                    ice_servers,
-                                    time,
-                                }
-                                .to_text_message()?,
-                            )?;
-                            tracing::debug!("Done sending user text");
-
-                            state
-                                .all_users
-                                .update_async(&user_id, |_, item| {
-                                    item.set_partner_id(partner_user_id);
-                                })
-                                .await;
-                            state
-                                .all_users
-                                .update_async(&partner_user_id, |_, item| {
-                                    item.set_partner_id(user_id);
-                                })
-                                .await;
-
-                            conn.execute("INSERT INTO queue_times (queue_time, queue_id) VALUES ($1, $2)", &[&((user.in_queue_since.elapsed() + partner_user.in_queue_since.elapsed()).as_secs() as i32), &queue_name]).await?;
-                        } else {
-                            tracing::debug!("Partner not found!");
-                            let mut entry = state
-                                .queues
-                                .entry_async(queue_name)
-                                .await
-                                .or_insert(Vec::with_capacity(2));
-                            entry.get_mut().push(user_id);
-                        }
+                let message = match ClientMessage::parse(&text) {
+                    Ok(message) => message,
+                    // answered instead of dropping the connection, newer
+                    // clients may send messages this server does not know yet
+                    Err(e) => {
+                        send_error(&tx, &e.into())?;
+                        continue;
                     }
-                    Some("ice-candidate")
-                    | Some("offer")
-                    | Some("answer")
-                    | Some("candidate") => {
-                        let partner_id = state
-                            .all_users
-                            .get(&user_id)
-                            .ok_or_internal("User not in all_users something broke lol")?
-                            .partner_id;
-                        if let Some(partner_user_id) = partner_id {
-                            if let Some(partner_user) =
-                                state.all_users.get(&partner_user_id)
-                            {
-                                partner_user.sender.send(Message::Text(text))?;
-                            }
+                };
+                let handled = handle_message(
+                    &state,
+                    &tx,
+                    user_id,
+                    secret,
+                    &mut spectating,
+                    message,
+                    text,
+                )
+                .await;
+                match handled {
+                    Err(e) if e.is_recoverable() => send_error(&tx, &e)?,
+                    handled => handled?,
+                }
+            } else if let Message::Binary(data) = message {
+                // moves for bot and relayed games, normal games send them over
+                // WebRTC
+                let (bot_sender, relay, partner_id) = state
+                    .all_users
+                    .read(&user_id, |_, user| {
+                        (user.bot_sender.clone(), user.relay.clone(), user.partner_id)
+                    })
+                    .ok_or_internal("User not in all_users something broke lol")?;
+                if let Some(bot_sender) = bot_sender {
+                    bot_sender.send(data).ok();
+                } else if let Some(relay) = relay {
+                    let mut relay = relay.lock().await;
+                    // the match goes on without the rejected move
+                    let completed = match relay.play(&state.store, &data).await {
+                        Ok(completed) => completed,
+                        Err(e) => {
+                            state.metrics.validation_failed(&e);
+                            send_error(&tx, &e)?;
+                            continue;
                         }
+                    };
+                    if let Some(partner_sender) = partner_id.and_then(|partner_id| {
+                        state
+                            .all_users
+                            .read(&partner_id, |_, partner| partner.sender.clone())
+                    }) {
+                        partner_sender.send(Message::Binary(data))?;
                     }
-                    option => {
-                        dbg!(&option);
+                    if completed {
+                        // the match stays live so leaving tries the save again
+                        if let Err(e) = relay.save(&state).await {
+                            tracing::error!("Failed saving relayed match: {e:?}");
+                            send_error(&tx, &e)?;
+                            continue;
+                        }
+                        for key in relay.keys() {
+                            state.live_matches.remove_async(key).await;
+                        }
                     }
                 }
             }
@@ -340,10 +540,14 @@ pub async fn handle_socket(
     };
 
     let out: Result<(), UserCreateError> = data_handler.await;
+    pinger.abort();
+    if let Some(task) = spectating {
+        task.abort();
+    }
     if let Err(e) = out {
         tracing::debug!("User disconnected: {e:?}");
         tx.send(
-            SendMessages::Disconnected {
+            ServerMessage::Disconnected {
                 name: e.get_name().to_owned(),
                 reason: e.to_string(),
             }
@@ -351,25 +555,196 @@ pub async fn handle_socket(
         )?;
     }
     tracing::info!("User {:?} disconnected", user_id);
-    {
-        let partner_id = state.all_users.get(&user_id).unwrap().partner_id;
-        if let Some(partner_user_id) = partner_id {
-            if let Some(partner_user) = state.all_users.get(&partner_user_id) {
-                partner_user
-                    .sender
-                    .send(SendMessages::PartnerLeft.to_text_message()?)?;
-            }
+    disconnect_user(&state, user_id).await
+}
+
+/// Removes a user from the queues and all users and tells their partner,
+/// relayed matches stay resumable for the reconnect grace
+pub(crate) async fn disconnect_user(
+    state: &AppState,
+    user_id: Uuid,
+) -> Result<(), UserCreateError> {
+    // the sweeper may have evicted the user already
+    let Some((partner_id, relay, pub_key)) = state
+        .all_users
+        .read_async(&user_id, |_, user| {
+            (user.partner_id, user.relay.clone(), user.pub_key.clone())
+        })
+        .await
+    else {
+        return Ok(());
+    };
+    let resumable = match &relay {
+        Some(relay) => {
+            let relay = relay.lock().await;
+            relay.is_relayed() && !relay.is_completed()
+        }
+        None => false,
+    };
+    // a partner that is gone too must not stop the cleanup
+    if let (true, Some(relay), Some(pub_key)) = (resumable, relay.clone(), pub_key) {
+        let partner_id = relay.lock().await.disconnect(user_id);
+        if let Some(partner_user) = partner_id.and_then(|id| state.get_user_clone(&id)) {
+            partner_user
+                .sender
+                .send(
+                    ServerMessage::PartnerDisconnected {
+                        grace_secs: state.reconnect_grace.as_secs(),
+                    }
+                    .to_text_message()?,
+                )
+                .ok();
+        }
+        tokio::spawn(expire_match(state.clone(), relay, pub_key));
+    } else {
+        if let Some(relay) = relay {
+            forget_match(state, &relay).await;
+        }
+        if let Some(partner_user) = partner_id.and_then(|id| state.get_user_clone(&id)) {
+            partner_user
+                .sender
+                .send(ServerMessage::PartnerLeft.to_text_message()?)
+                .ok();
+        }
+    }
+    if let Err(e) = lobby::leave(state, user_id).await {
+        tracing::debug!("Failed leaving lobby: {e:?}");
+    }
+    state
+        .queues
+        .retain_async(|_, queue| {
+            queue.retain(|&id| id != user_id);
+            true
+        })
+        .await;
+
+    state.all_users.remove_async(&user_id).await;
+    Ok(())
+}
+
+/// Drops the match from the live matches, unless a player already moved on to
+/// a newer one
+async fn forget_match(state: &AppState, relay: &SharedRelay) {
+    let keys = {
+        let mut relay = relay.lock().await;
+        // a finished game whose save failed gets one more try
+        if let Err(e) = relay.save(state).await {
+            tracing::error!("Failed saving relayed match: {e:?}");
+        }
+        relay.keys().map(|key| key.to_owned())
+    };
+    for key in keys {
+        state
+            .live_matches
+            .remove_if_async(&key, |live| Arc::ptr_eq(live, relay))
+            .await;
+    }
+}
+
+/// Gives up on a relayed match once the disconnected player did not come back
+/// within the grace window
+async fn expire_match(state: AppState, relay: SharedRelay, pub_key: String) {
+    tokio::time::sleep(state.reconnect_grace).await;
+    let (partner_id, match_id, stored) = {
+        let mut relay = relay.lock().await;
+        if relay.is_connected(&pub_key) || relay.is_completed() {
+            return;
+        }
+        let partner_id = relay.partner_of(&pub_key);
+        // the player that stayed wins, storing the match also settles a
+        // tournament series
+        let stored = match partner_id {
+            Some(_) => match relay.save_forfeit(&state, &pub_key).await {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::error!("Failed saving abandoned match: {e:?}");
+                    false
+                }
+            },
+            None => false,
+        };
+        (partner_id, relay.match_id(), stored)
+    };
+    forget_match(&state, &relay).await;
+    // nothing was stored when both are gone, a tournament replays the game
+    if !stored {
+        tournament::record_result(&state, match_id, None).await;
+    }
+    if let Some(partner_user) = partner_id.and_then(|id| state.get_user_clone(&id)) {
+        if let Ok(message) = ServerMessage::PartnerLeft.to_text_message() {
+            partner_user.sender.send(message).ok();
         }
     }
-    {
+}
+
+/// Moves a player that lost their connection back into their relayed match
+async fn resume_match(
+    state: &AppState,
+    user_id: Uuid,
+    player_id: Uuid,
+    pub_key: &str,
+) -> Result<(), UserCreateError> {
+    let relay = state
+        .live_matches
+        .read(pub_key, |_, relay| relay.clone())
+        .ok_or_badrequest("No match to resume")?;
+    let mut guard = relay.lock().await;
+    if guard.is_completed() {
+        return Err(UserCreateError::BadRequest(
+            "Match is already completed".to_owned(),
+        ));
+    }
+    let partner_id = guard.reconnect(pub_key, user_id)?;
+    let moves = guard.stored_history(&state.store).await?;
+    let body = guard.body().clone();
+    drop(guard);
+
+    let initiator = body.your_key == pub_key;
+    state
+        .all_users
+        .update_async(&user_id, |_, item| {
+            item.set_pub_key(pub_key.to_owned())
+                .set_player_id(player_id)
+                .set_relay(relay.clone());
+            if let Some(partner_id) = partner_id {
+                item.set_partner_id(partner_id);
+            }
+        })
+        .await;
+    if let Some(partner_id) = partner_id {
         state
-            .queues
-            .update_async(&queue_name, |_, q| {
-                q.retain(|&id| id != user_id);
+            .all_users
+            .update_async(&partner_id, |_, item| {
+                item.set_partner_id(user_id);
             })
             .await;
+        if let Some(partner_user) = state.get_user_clone(&partner_id) {
+            partner_user
+                .sender
+                .send(ServerMessage::PartnerReconnected.to_text_message()?)?;
+        }
     }
 
-    state.all_users.remove(&user_id);
+    let user = state
+        .get_user_clone(&user_id)
+        .ok_or_internal("User not in all_users something broke lol")?;
+    user.sender.send(
+        ServerMessage::Resume {
+            public_key: pub_key.to_owned(),
+            partner_key: if initiator {
+                body.opponent_key
+            } else {
+                body.your_key
+            },
+            initiator,
+            seed: body.seed,
+            signature: body.signature,
+            time: body.time,
+            rules: body.rules,
+            moves,
+            seed_reveal: body.seed_reveal,
+        }
+        .to_text_message()?,
+    )?;
     Ok(())
 }
@@ -0,0 +1,110 @@
+//! Lets anyone follow relayed matches, boards are held back by the spectator
+//! delay so watching a stream of the match does not help the players
+
+use std::{sync::Arc, time::Duration};
+
+use axum::extract::ws::Message;
+use lib_knuckle::{api_interfaces::LiveMatch, protocol::ServerMessage};
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc::UnboundedSender},
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+use crate::{
+    relay::{SharedRelay, Snapshot},
+    routes::ToTextMessage,
+    AppState, UserCreateError,
+};
+
+/// Every live match once, the same relay is stored under both players
+pub(crate) async fn relays(state: &AppState) -> Vec<SharedRelay> {
+    let mut relays: Vec<SharedRelay> = Vec::new();
+    state
+        .live_matches
+        .scan_async(|_, relay| {
+            if !relays.iter().any(|known| Arc::ptr_eq(known, relay)) {
+                relays.push(relay.clone());
+            }
+        })
+        .await;
+    relays
+}
+
+/// Relayed matches that are still being played, the boards of WebRTC games
+/// never reach the server
+pub async fn live_matches(state: &AppState) -> Vec<LiveMatch> {
+    let mut matches = Vec::new();
+    for relay in relays(state).await {
+        let relay = relay.lock().await;
+        if relay.is_relayed() && !relay.is_completed() {
+            matches.push(relay.summary());
+        }
+    }
+    matches
+}
+
+/// Starts sending the boards of a match to the user, the task ends once the
+/// match does or the user is gone
+pub async fn spectate(
+    state: &AppState,
+    match_id: Uuid,
+    sender: UnboundedSender<Message>,
+) -> Result<JoinHandle<()>, UserCreateError> {
+    let mut found = None;
+    for relay in relays(state).await {
+        let relay = relay.lock().await;
+        if relay.is_relayed() && relay.match_id() == match_id {
+            found = Some(relay.spectate(state.spectator_delay));
+            break;
+        }
+    }
+    let (backlog, mut receiver) = found.ok_or(UserCreateError::MatchNotFound)?;
+    let delay = state.spectator_delay;
+
+    Ok(tokio::spawn(async move {
+        let mut pending = backlog.into_iter();
+        loop {
+            let snapshot = match pending.next() {
+                Some(snapshot) => snapshot,
+                None => match receiver.recv().await {
+                    Ok(snapshot) => snapshot,
+                    // boards hold the whole game, a skipped one is not missed
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            match forward(&sender, match_id, delay, snapshot).await {
+                Some(true) => break,
+                Some(false) => {}
+                None => return,
+            }
+        }
+        if let Ok(message) = (ServerMessage::SpectatorEnded {
+            match_id: match_id.to_string(),
+        })
+        .to_text_message()
+        {
+            sender.send(message).ok();
+        }
+    }))
+}
+
+/// Sends the board once the delay passed, returns whether it was the last one
+/// or `None` when the user is gone
+async fn forward(
+    sender: &UnboundedSender<Message>,
+    match_id: Uuid,
+    delay: Duration,
+    snapshot: Snapshot,
+) -> Option<bool> {
+    tokio::time::sleep_until((snapshot.at + delay).into()).await;
+    let message = ServerMessage::SpectatorBoard {
+        match_id: match_id.to_string(),
+        board: snapshot.board,
+    }
+    .to_text_message()
+    .ok()?;
+    sender.send(message).ok()?;
+    Some(snapshot.completed)
+}
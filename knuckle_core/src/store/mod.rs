@@ -0,0 +1,184 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use lib_knuckle::{
+    api_interfaces::{
+        LeaderBoard, LeaderBoardRank, MatchDetails, MatchList, MatchResult,
+        RatingLeaderBoard,
+    },
+    game::HistoryForSql,
+    rating::Outcome,
+    rules::RuleSet,
+};
+use uuid::Uuid;
+
+use crate::UserCreateError;
+
+mod postgres;
+pub use postgres::*;
+mod sqlite;
+pub use sqlite::*;
+
+pub type SharedStore = Arc<dyn Store>;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Backend {
+    Postgres,
+    /// Embedded database in a single file, for local development and tests
+    Sqlite,
+}
+
+/// A match that was signed and handed out to both players
+#[derive(Debug, Clone)]
+pub struct StartedMatch {
+    pub match_id: Uuid,
+    pub seed: u64,
+    pub time: u64,
+    pub players: (Uuid, Uuid),
+    pub bot_game: bool,
+    pub created_at: SystemTime,
+    /// Match that started the series, its own id unless it is a rematch or a
+    /// later game of a tournament series
+    pub series_id: Uuid,
+}
+
+/// A validated game ready to be stored, the first player is the one that
+/// submitted it
+#[derive(Debug, Clone)]
+pub struct FinishedMatch {
+    pub started: StartedMatch,
+    pub winner: Option<Uuid>,
+    pub result: &'static str,
+    /// From the point of view of the first player
+    pub outcome: Outcome,
+    pub points: (u32, u32),
+    pub rules: RuleSet,
+    pub moves: Vec<HistoryForSql>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchFilter {
+    pub opponent: Option<Vec<u8>>,
+    pub result: Option<MatchResult>,
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// Connections of a database pool
+#[derive(Debug, Clone, Copy)]
+pub struct PoolState {
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+/// Everything the server keeps outside of memory
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Names of the migrations that still have to be applied, fails when the
+    /// applied ones do not match this build
+    async fn pending_migrations(&self) -> Result<Vec<&'static str>, UserCreateError>;
+    async fn run_migrations(&self) -> Result<(), UserCreateError>;
+
+    async fn create_player(
+        &self,
+        player_id: Uuid,
+        public_key: Vec<u8>,
+        secret_key: Vec<u8>,
+        name: String,
+        is_bot: bool,
+    ) -> Result<(), UserCreateError>;
+    async fn set_player_name(
+        &self,
+        public_key: Vec<u8>,
+        name: String,
+    ) -> Result<(), UserCreateError>;
+    async fn player_id(
+        &self,
+        public_key: Vec<u8>,
+    ) -> Result<Option<Uuid>, UserCreateError>;
+    async fn player_rating(&self, player_id: Uuid) -> Result<f64, UserCreateError>;
+    /// Player id and secret key of the bot, if it was registered before
+    async fn bot_player(&self) -> Result<Option<(Uuid, Vec<u8>)>, UserCreateError>;
+
+    async fn start_match(&self, started: StartedMatch) -> Result<(), UserCreateError>;
+    async fn started_match(
+        &self,
+        seed: u64,
+        time: u64,
+    ) -> Result<Option<StartedMatch>, UserCreateError>;
+    /// Stores the match with its moves and, unless it was a bot game, the new
+    /// ratings of both players in one transaction
+    async fn save_match(&self, finished: FinishedMatch) -> Result<(), UserCreateError>;
+
+    async fn push_live_move(
+        &self,
+        match_id: Uuid,
+        seq: u32,
+        item: Vec<u8>,
+    ) -> Result<(), UserCreateError>;
+    async fn live_moves(&self, match_id: Uuid) -> Result<Vec<Vec<u8>>, UserCreateError>;
+    async fn clear_live_moves(&self, match_id: Uuid) -> Result<(), UserCreateError>;
+    /// Drops the moves of every relayed match, returns how many there were
+    async fn clear_all_live_moves(&self) -> Result<u64, UserCreateError>;
+
+    /// Replaces the stored state of a tournament, `state` is its json
+    async fn save_tournament(
+        &self,
+        tournament_id: Uuid,
+        state: String,
+    ) -> Result<(), UserCreateError>;
+    async fn tournaments(&self) -> Result<Vec<(Uuid, String)>, UserCreateError>;
+
+    async fn record_queue_time(
+        &self,
+        queue_id: Uuid,
+        queue_time: Duration,
+    ) -> Result<(), UserCreateError>;
+
+    async fn list_matches(
+        &self,
+        player_id: Uuid,
+        filter: MatchFilter,
+    ) -> Result<MatchList, UserCreateError>;
+    async fn match_details(
+        &self,
+        match_id: Uuid,
+    ) -> Result<Option<MatchDetails>, UserCreateError>;
+    /// Players ranked by their points since `start`, all time when `None`
+    async fn leader_board(
+        &self,
+        start: Option<SystemTime>,
+        page: u32,
+        limit: u32,
+    ) -> Result<LeaderBoard, UserCreateError>;
+    async fn leader_board_rank(
+        &self,
+        start: Option<SystemTime>,
+        public_key: Vec<u8>,
+    ) -> Result<LeaderBoardRank, UserCreateError>;
+    async fn rating_leader_board(&self) -> Result<RatingLeaderBoard, UserCreateError>;
+
+    /// Stores without a connection pool have nothing to report
+    fn pool_state(&self) -> Option<PoolState> {
+        None
+    }
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn match_result(result: &str) -> MatchResult {
+    match result {
+        "win" => MatchResult::Win,
+        "loss" => MatchResult::Loss,
+        _ => MatchResult::Tie,
+    }
+}
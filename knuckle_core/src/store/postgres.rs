@@ -0,0 +1,840 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
+use lib_knuckle::{
+    api_interfaces::{
+        LeaderBoard, LeaderBoardEntry, LeaderBoardRank, MatchDetails, MatchList,
+        MatchMove, MatchPlayer, MatchSummary, RatingLeaderBoard, RatingLeaderBoardEntry,
+    },
+    rating::{Outcome, Rating},
+    rules::RuleSet,
+};
+use tokio_postgres::{types::ToSql, NoTls, Row, Transaction};
+use uuid::Uuid;
+
+use super::{
+    match_result, to_millis, FinishedMatch, MatchFilter, PoolState, StartedMatch, Store,
+};
+use crate::{
+    database::{run_migrations, verify_migrations},
+    UserCreateError,
+};
+
+pub type ConnectionPool = Pool<PostgresConnectionManager<NoTls>>;
+
+/// Ranks every player, those without a game in the window with zero counts,
+/// `$1` is the first day of the window. Reads the daily continuous aggregates
+/// instead of the raw matches
+const RANKED_PLAYERS: &str = /* language=postgresql */
+    "
+WITH stats AS (
+    SELECT
+        player_id,
+        SUM(points) AS total_points,
+        SUM(games) AS total_games,
+        SUM(wins) AS total_wins
+    FROM player_daily_stats
+    WHERE $1::TIMESTAMPTZ IS NULL OR day >= $1
+    GROUP BY player_id
+)
+SELECT
+    p.name,
+    p.public_key,
+    COALESCE(s.total_points, 0)::BIGINT AS total_points,
+    COALESCE(s.total_games, 0)::BIGINT AS total_games,
+    COALESCE(s.total_wins, 0)::BIGINT AS total_wins,
+    RANK() OVER (ORDER BY COALESCE(s.total_points, 0) DESC, COALESCE(s.total_wins, 0) DESC, COALESCE(s.total_games, 0) DESC) AS rank,
+    COUNT(*) OVER () AS total
+FROM
+    players p
+LEFT JOIN
+    stats s ON s.player_id = p.player_id
+WHERE
+    NOT p.is_bot
+";
+
+/// The TimescaleDB backed store used in production
+pub struct PostgresStore {
+    pool: ConnectionPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: ConnectionPool) -> Self {
+        Self { pool }
+    }
+
+    /// Total number of ranked players, taken from the window function when
+    /// there is a row to read it from
+    async fn ranked_total(
+        &self,
+        rows: &[Row],
+        start: Option<SystemTime>,
+    ) -> Result<u32, UserCreateError> {
+        let total: i64 = match rows.first() {
+            Some(row) => row.get(6),
+            None => self
+                .pool
+                .get()
+                .await?
+                .query_one(
+                    &format!("SELECT COUNT(*) FROM ({RANKED_PLAYERS}) ranked"),
+                    &[&start],
+                )
+                .await?
+                .get(0),
+        };
+        Ok(total as u32)
+    }
+}
+
+fn unix_timestamp_to_system_time(timestamp: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(timestamp)
+}
+
+fn entry_from_row(row: &Row) -> LeaderBoardEntry {
+    let name: &str = row.get(0);
+    let total_points: i64 = row.get(2);
+    let total_games: i64 = row.get(3);
+    let total_wins: i64 = row.get(4);
+    let rank: i64 = row.get(5);
+    LeaderBoardEntry {
+        rank: rank as u32,
+        name: name.to_owned(),
+        total_points: total_points as u32,
+        total_games: total_games as u32,
+        total_wins: total_wins as u32,
+    }
+}
+
+fn rating_from_row(row: &Row) -> Rating {
+    Rating {
+        rating: row.get(1),
+        deviation: row.get(2),
+        volatility: row.get(3),
+    }
+}
+
+/// Rates a finished match and keeps the before and after ratings of both
+/// players, `outcome` is from the point of view of the first player
+async fn update_ratings(
+    tx: &Transaction<'_>,
+    match_id: Uuid,
+    (player_id, opponent_id): (Uuid, Uuid),
+    outcome: Outcome,
+) -> Result<(), UserCreateError> {
+    // lock both rows so concurrent submits of the same players can not lose an
+    // update
+    let rows = tx
+        .query(
+            /* language=postgresql */
+            "SELECT player_id, rating, rating_deviation, rating_volatility FROM players WHERE player_id IN ($1, $2) FOR UPDATE",
+            &[&player_id, &opponent_id],
+        )
+        .await?;
+    let find = |id: Uuid| {
+        rows.iter()
+            .find(|row| row.get::<_, Uuid>(0) == id)
+            .map(rating_from_row)
+            .ok_or(UserCreateError::UserDoesNotExist)
+    };
+    let (player, opponent) = (find(player_id)?, find(opponent_id)?);
+    let (next_player, next_opponent) = Rating::rate_match(player, opponent, outcome);
+
+    for (id, before, after) in [
+        (player_id, player, next_player),
+        (opponent_id, opponent, next_opponent),
+    ] {
+        tx.execute(
+            /* language=postgresql */
+            "UPDATE players SET rating = $2, rating_deviation = $3, rating_volatility = $4 WHERE player_id = $1",
+            &[&id, &after.rating, &after.deviation, &after.volatility],
+        )
+        .await?;
+        tx.execute(
+            /* language=postgresql */
+            "INSERT INTO rating_history (match_id, player_id, rating_before, rating_after, deviation_before, deviation_after, volatility) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[
+                &match_id,
+                &id,
+                &before.rating,
+                &after.rating,
+                &before.deviation,
+                &after.deviation,
+                &after.volatility,
+            ],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Store for PostgresStore {
+    async fn pending_migrations(&self) -> Result<Vec<&'static str>, UserCreateError> {
+        let conn = self.pool.get().await?;
+        Ok(verify_migrations(&conn)
+            .await?
+            .into_iter()
+            .map(|migration| migration.name)
+            .collect())
+    }
+
+    async fn run_migrations(&self) -> Result<(), UserCreateError> {
+        let mut conn = self.pool.get().await?;
+        run_migrations(&mut conn).await
+    }
+
+    async fn create_player(
+        &self,
+        player_id: Uuid,
+        public_key: Vec<u8>,
+        secret_key: Vec<u8>,
+        name: String,
+        is_bot: bool,
+    ) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "INSERT INTO players (player_id, public_key, secret_key, name, is_bot) VALUES ($1, $2, $3, $4, $5)",
+                &[&player_id, &public_key, &secret_key, &name, &is_bot],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_player_name(
+        &self,
+        public_key: Vec<u8>,
+        name: String,
+    ) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "UPDATE players SET name = $1 WHERE public_key = $2",
+                &[&name, &public_key],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn player_id(
+        &self,
+        public_key: Vec<u8>,
+    ) -> Result<Option<Uuid>, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                /* language=postgresql */
+                "SELECT player_id FROM players WHERE public_key = $1",
+                &[&public_key],
+            )
+            .await?
+            .map(|row| row.get(0)))
+    }
+
+    async fn player_rating(&self, player_id: Uuid) -> Result<f64, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .query_one(
+                /* language=postgresql */
+                "SELECT rating FROM players WHERE player_id = $1",
+                &[&player_id],
+            )
+            .await?
+            .get(0))
+    }
+
+    async fn bot_player(&self) -> Result<Option<(Uuid, Vec<u8>)>, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                /* language=postgresql */
+                "SELECT player_id, secret_key FROM players WHERE is_bot LIMIT 1",
+                &[],
+            )
+            .await?
+            .map(|row| (row.get(0), row.get(1))))
+    }
+
+    async fn start_match(&self, started: StartedMatch) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "INSERT INTO started_matches (match_id, time, seed, player1, player2, bot_game, created_at, series_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &started.match_id,
+                    &(started.time as i64),
+                    &(started.seed as i64),
+                    &started.players.0,
+                    &started.players.1,
+                    &started.bot_game,
+                    &started.created_at,
+                    &started.series_id,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn started_match(
+        &self,
+        seed: u64,
+        time: u64,
+    ) -> Result<Option<StartedMatch>, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                /* language=postgresql */
+                "SELECT match_id, player1, player2, bot_game, created_at, COALESCE(series_id, match_id) FROM started_matches WHERE seed = $1 AND time = $2",
+                &[&(seed as i64), &(time as i64)],
+            )
+            .await?
+            .map(|row| StartedMatch {
+                match_id: row.get(0),
+                seed,
+                time,
+                players: (row.get(1), row.get(2)),
+                bot_game: row.get(3),
+                created_at: row.get(4),
+                series_id: row.get(5),
+            }))
+    }
+
+    async fn save_match(&self, finished: FinishedMatch) -> Result<(), UserCreateError> {
+        let FinishedMatch {
+            started,
+            winner,
+            result,
+            outcome,
+            points,
+            rules,
+            moves,
+        } = finished;
+        let match_id = started.match_id;
+        let (user_id, partner_id) = started.players;
+        let points_p1 = points.0 as i16;
+        let points_p2 = points.1 as i16;
+
+        let mut conn = self.pool.get().await?;
+        let tx = conn.transaction().await?;
+
+        let inserted = tx
+            .execute(
+                /* language=postgresql */
+                "INSERT INTO matches(
+        match_id,
+        seed,
+        time,
+        player1,
+        player2,
+        winner,
+        result,
+        points_p1,
+        points_p2,
+        bot_game,
+        rules,
+        started_at,
+        series_id
+    ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+    ON CONFLICT (match_id) DO NOTHING",
+                &[
+                    &match_id,
+                    &(started.seed as i64),
+                    &(started.time as i64),
+                    &user_id,
+                    &partner_id,
+                    &winner,
+                    &result,
+                    &points_p1,
+                    &points_p2,
+                    &started.bot_game,
+                    &rules.encode(),
+                    &started.created_at,
+                    &started.series_id,
+                ],
+            )
+            .await?;
+        // both players may send the same game
+        if inserted == 0 {
+            return Err(UserCreateError::MatchAlreadySaved);
+        }
+
+        // one row per player so the leaderboard aggregates do not need a union
+        for (player_id, points) in [(user_id, points_p1), (partner_id, points_p2)] {
+            tx.execute(
+                /* language=postgresql */
+                "INSERT INTO match_players (match_id, player_id, points, won, bot_game) VALUES ($1, $2, $3, $4, $5)",
+                &[&match_id, &player_id, &points, &(winner == Some(player_id)), &started.bot_game],
+            )
+            .await?;
+        }
+
+        let columns = moves
+            .iter()
+            .map(|item| {
+                (
+                    item.number as i16,
+                    item.x as i16,
+                    item.seq as i32,
+                    unix_timestamp_to_system_time(item.now),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut query = String::from(
+            "INSERT INTO moves (match_id, player_id, number, x, seq, created_at) VALUES ",
+        );
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+        for (i, (item, (number, x, seq, created_at))) in
+            moves.iter().zip(&columns).enumerate()
+        {
+            if i != 0 {
+                query.push_str(", ");
+            }
+            query.push_str(&format!(
+                "(${}, ${}, ${}, ${}, ${}, ${})",
+                i * 6 + 1,
+                i * 6 + 2,
+                i * 6 + 3,
+                i * 6 + 4,
+                i * 6 + 5,
+                i * 6 + 6,
+            ));
+            params.push(&match_id);
+            params.push(&item.player);
+            params.push(number);
+            params.push(x);
+            params.push(seq);
+            params.push(created_at);
+        }
+
+        if !moves.is_empty() {
+            tx.execute(&query, params.as_slice()).await?;
+        }
+
+        // bot games stay out of the rankings
+        if !started.bot_game {
+            update_ratings(&tx, match_id, (user_id, partner_id), outcome).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn push_live_move(
+        &self,
+        match_id: Uuid,
+        seq: u32,
+        item: Vec<u8>,
+    ) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "INSERT INTO live_moves (match_id, seq, item) VALUES ($1, $2, $3)",
+                &[&match_id, &(seq as i32), &item],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn live_moves(&self, match_id: Uuid) -> Result<Vec<Vec<u8>>, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .query(
+                /* language=postgresql */
+                "SELECT item FROM live_moves WHERE match_id = $1 ORDER BY seq",
+                &[&match_id],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    async fn clear_live_moves(&self, match_id: Uuid) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "DELETE FROM live_moves WHERE match_id = $1",
+                &[&match_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_all_live_moves(&self) -> Result<u64, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .execute(/* language=postgresql */ "DELETE FROM live_moves", &[])
+            .await?)
+    }
+
+    async fn save_tournament(
+        &self,
+        tournament_id: Uuid,
+        state: String,
+    ) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "INSERT INTO tournaments (tournament_id, state) VALUES ($1, $2)
+                 ON CONFLICT (tournament_id) DO UPDATE SET state = $2, updated_at = NOW()",
+                &[&tournament_id, &state],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn tournaments(&self) -> Result<Vec<(Uuid, String)>, UserCreateError> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .query(
+                /* language=postgresql */
+                "SELECT tournament_id, state FROM tournaments",
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn record_queue_time(
+        &self,
+        queue_id: Uuid,
+        queue_time: Duration,
+    ) -> Result<(), UserCreateError> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                /* language=postgresql */
+                "INSERT INTO queue_times (queue_time, queue_id) VALUES ($1, $2)",
+                &[&(queue_time.as_secs() as i32), &queue_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list_matches(
+        &self,
+        player_id: Uuid,
+        filter: MatchFilter,
+    ) -> Result<MatchList, UserCreateError> {
+        let result = filter.result.map(|result| result.as_str());
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                /* language=postgresql */
+                "
+SELECT
+    m.match_id,
+    o.name,
+    o.public_key,
+    CASE
+        WHEN m.winner IS NULL THEN 'tie'
+        WHEN m.winner = $1 THEN 'win'
+        ELSE 'loss'
+    END AS outcome,
+    m.result = 'forfeit',
+    CASE WHEN m.player1 = $1 THEN m.points_p1 ELSE m.points_p2 END,
+    CASE WHEN m.player1 = $1 THEN m.points_p2 ELSE m.points_p1 END,
+    m.bot_game,
+    m.started_at,
+    m.completed_at,
+    COUNT(*) OVER () AS total
+FROM
+    matches m
+JOIN
+    players o ON o.player_id = CASE WHEN m.player1 = $1 THEN m.player2 ELSE m.player1 END
+WHERE
+    $1 IN (m.player1, m.player2)
+    AND ($2::BYTEA IS NULL OR o.public_key = $2)
+    AND ($3::TEXT IS NULL OR CASE
+        WHEN m.winner IS NULL THEN 'tie'
+        WHEN m.winner = $1 THEN 'win'
+        ELSE 'loss'
+    END = $3)
+    AND ($4::TIMESTAMPTZ IS NULL OR m.completed_at >= $4)
+    AND ($5::TIMESTAMPTZ IS NULL OR m.completed_at < $5)
+ORDER BY
+    m.completed_at DESC
+LIMIT $6 OFFSET $7;
+                ",
+                &[
+                    &player_id,
+                    &filter.opponent,
+                    &result,
+                    &filter.from,
+                    &filter.to,
+                    &(filter.limit as i64),
+                    &(filter.offset as i64),
+                ],
+            )
+            .await?;
+
+        let total = rows
+            .first()
+            .map(|row| row.get::<_, i64>(10) as u32)
+            .unwrap_or_default();
+        let entries = rows
+            .iter()
+            .map(|row| {
+                let match_id: Uuid = row.get(0);
+                let opponent_key: Vec<u8> = row.get(2);
+                let points: i16 = row.get(5);
+                let opponent_points: i16 = row.get(6);
+                MatchSummary {
+                    match_id: match_id.to_string(),
+                    opponent_name: row.get(1),
+                    opponent_key: STANDARD_NO_PAD.encode(opponent_key),
+                    result: match_result(row.get(3)),
+                    forfeit: row.get(4),
+                    points: points as u32,
+                    opponent_points: opponent_points as u32,
+                    bot_game: row.get(7),
+                    started_at: to_millis(row.get(8)),
+                    completed_at: to_millis(row.get(9)),
+                }
+            })
+            .collect();
+
+        Ok(MatchList { total, entries })
+    }
+
+    async fn match_details(
+        &self,
+        match_id: Uuid,
+    ) -> Result<Option<MatchDetails>, UserCreateError> {
+        let conn = self.pool.get().await?;
+        let Some(row) = conn
+            .query_opt(
+                /* language=postgresql */
+                "
+SELECT
+    p1.name,
+    p1.public_key,
+    m.points_p1,
+    p2.name,
+    p2.public_key,
+    m.points_p2,
+    w.public_key,
+    m.result,
+    m.bot_game,
+    m.rules,
+    m.started_at,
+    m.completed_at,
+    COALESCE(m.series_id, m.match_id)
+FROM
+    matches m
+JOIN
+    players p1 ON p1.player_id = m.player1
+JOIN
+    players p2 ON p2.player_id = m.player2
+LEFT JOIN
+    players w ON w.player_id = m.winner
+WHERE
+    m.match_id = $1;
+                ",
+                &[&match_id],
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let player = |name: usize, key: usize, points: usize| {
+            let public_key: Vec<u8> = row.get(key);
+            let points: i16 = row.get(points);
+            MatchPlayer {
+                name: row.get(name),
+                public_key: STANDARD_NO_PAD.encode(public_key),
+                points: points as u32,
+            }
+        };
+        let player1 = player(0, 1, 2);
+        let player2 = player(3, 4, 5);
+        let winner: Option<Vec<u8>> = row.get(6);
+        let rules = RuleSet::decode(row.get(9))?;
+
+        let moves = conn
+            .query(
+                /* language=postgresql */
+                "
+SELECT
+    mv.seq,
+    p.public_key,
+    mv.x,
+    mv.number,
+    mv.created_at
+FROM
+    moves mv
+JOIN
+    players p ON p.player_id = mv.player_id
+WHERE
+    mv.match_id = $1
+ORDER BY
+    mv.seq;
+                ",
+                &[&match_id],
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                let seq: i32 = row.get(0);
+                let player: Vec<u8> = row.get(1);
+                let x: i16 = row.get(2);
+                let number: i16 = row.get(3);
+                MatchMove {
+                    seq: seq as u32,
+                    player: STANDARD_NO_PAD.encode(player),
+                    x: x as u16,
+                    number: number as u8,
+                    created_at: to_millis(row.get(4)),
+                }
+            })
+            .collect();
+
+        Ok(Some(MatchDetails {
+            match_id: match_id.to_string(),
+            player1,
+            player2,
+            winner: winner.map(|key| STANDARD_NO_PAD.encode(key)),
+            result: row.get(7),
+            bot_game: row.get(8),
+            rules,
+            started_at: to_millis(row.get(10)),
+            completed_at: to_millis(row.get(11)),
+            series_id: row.get::<_, Uuid>(12).to_string(),
+            moves,
+        }))
+    }
+
+    async fn leader_board(
+        &self,
+        start: Option<SystemTime>,
+        page: u32,
+        limit: u32,
+    ) -> Result<LeaderBoard, UserCreateError> {
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                &format!("{RANKED_PLAYERS} ORDER BY rank, p.name LIMIT $2 OFFSET $3"),
+                &[&start, &(limit as i64), &(page as i64 * limit as i64)],
+            )
+            .await?;
+
+        Ok(LeaderBoard {
+            total: self.ranked_total(&rows, start).await?,
+            page,
+            limit,
+            entries: rows.iter().map(entry_from_row).collect(),
+        })
+    }
+
+    async fn leader_board_rank(
+        &self,
+        start: Option<SystemTime>,
+        public_key: Vec<u8>,
+    ) -> Result<LeaderBoardRank, UserCreateError> {
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(
+                &format!("SELECT * FROM ({RANKED_PLAYERS}) ranked WHERE public_key = $2"),
+                &[&start, &public_key],
+            )
+            .await?;
+        Ok(LeaderBoardRank {
+            total: self.ranked_total(&rows, start).await?,
+            entry: rows.first().map(entry_from_row),
+        })
+    }
+
+    async fn rating_leader_board(&self) -> Result<RatingLeaderBoard, UserCreateError> {
+        let leader_board = self
+            .pool
+            .get()
+            .await?
+            .query(
+                /* language=postgresql */
+                "
+SELECT
+    p.name,
+    p.rating,
+    p.rating_deviation,
+    COUNT(h.match_id) AS rated_games
+FROM
+    players p
+JOIN
+    rating_history h ON h.player_id = p.player_id
+WHERE
+    NOT p.is_bot
+GROUP BY
+    p.player_id
+ORDER BY
+    p.rating DESC, p.rating_deviation, p.name;
+                ",
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                let name: &str = row.get(0);
+                let rated_games: i64 = row.get(3);
+                RatingLeaderBoardEntry {
+                    name: name.to_owned(),
+                    rating: row.get(1),
+                    deviation: row.get(2),
+                    rated_games: rated_games as u32,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(RatingLeaderBoard {
+            total: leader_board.len() as u32,
+            entries: leader_board,
+        })
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        let state = self.pool.state();
+        Some(PoolState {
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+        })
+    }
+}
@@ -0,0 +1,811 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use lib_knuckle::{
+    api_interfaces::{
+        LeaderBoard, LeaderBoardEntry, LeaderBoardRank, MatchDetails, MatchList,
+        MatchMove, MatchPlayer, MatchSummary, RatingLeaderBoard, RatingLeaderBoardEntry,
+    },
+    rating::Rating,
+    rules::RuleSet,
+};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use uuid::Uuid;
+
+use super::{match_result, to_millis, FinishedMatch, MatchFilter, StartedMatch, Store};
+use crate::{
+    database::{pending_migrations, Migration, SQLITE_MIGRATIONS},
+    UserCreateError,
+};
+
+/// Same ranking as the Postgres store, read straight from `match_players`
+/// since there are no continuous aggregates
+const RANKED_PLAYERS: &str = "
+WITH stats AS (
+    SELECT
+        player_id,
+        SUM(points) AS total_points,
+        COUNT(*) AS total_games,
+        SUM(won) AS total_wins
+    FROM match_players
+    WHERE NOT bot_game AND (?1 IS NULL OR completed_at >= ?1)
+    GROUP BY player_id
+)
+SELECT
+    p.name,
+    p.public_key,
+    COALESCE(s.total_points, 0) AS total_points,
+    COALESCE(s.total_games, 0) AS total_games,
+    COALESCE(s.total_wins, 0) AS total_wins,
+    RANK() OVER (ORDER BY COALESCE(s.total_points, 0) DESC, COALESCE(s.total_wins, 0) DESC, COALESCE(s.total_games, 0) DESC) AS rank,
+    COUNT(*) OVER () AS total
+FROM
+    players p
+LEFT JOIN
+    stats s ON s.player_id = p.player_id
+WHERE
+    NOT p.is_bot
+";
+
+/// Embedded store for local development and tests, every query runs on a
+/// blocking thread against a single connection
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database file, `:memory:` keeps it in memory
+    pub fn open(path: &str) -> Result<Self, UserCreateError> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T, UserCreateError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, UserCreateError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock()))
+            .await
+            .map_err(|e| UserCreateError::Internal(e.to_string()))?
+    }
+}
+
+fn millis(time: SystemTime) -> i64 {
+    to_millis(time) as i64
+}
+
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis as u64)
+}
+
+/// Checks the applied migrations against the embedded ones like the Postgres
+/// store does and returns the ones that still have to run
+fn verify_migrations(
+    conn: &Connection,
+) -> Result<Vec<&'static Migration>, UserCreateError> {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    let applied = match exists {
+        true => conn
+            .prepare("SELECT version, checksum FROM schema_migrations ORDER BY version")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?,
+        // databases from before checksums only counted their migrations in
+        // user_version, those are taken as they are now
+        false => {
+            let version: usize =
+                conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            if version > SQLITE_MIGRATIONS.len() {
+                return Err(UserCreateError::Migration(format!(
+                    "Database has migration {version} which this build does not know about"
+                )));
+            }
+            SQLITE_MIGRATIONS[..version]
+                .iter()
+                .map(|migration| (migration.version, migration.checksum()))
+                .collect()
+        }
+    };
+    pending_migrations(SQLITE_MIGRATIONS, &applied)
+}
+
+fn record_migration(
+    conn: &Connection,
+    migration: &Migration,
+) -> Result<(), UserCreateError> {
+    conn.execute(
+        "INSERT OR IGNORE INTO schema_migrations (version, name, checksum, applied_at) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            migration.version,
+            migration.name,
+            migration.checksum(),
+            millis(SystemTime::now())
+        ],
+    )?;
+    Ok(())
+}
+
+fn entry_from_row(row: &Row) -> rusqlite::Result<LeaderBoardEntry> {
+    let total_points: i64 = row.get(2)?;
+    let total_games: i64 = row.get(3)?;
+    let total_wins: i64 = row.get(4)?;
+    let rank: i64 = row.get(5)?;
+    Ok(LeaderBoardEntry {
+        rank: rank as u32,
+        name: row.get(0)?,
+        total_points: total_points as u32,
+        total_games: total_games as u32,
+        total_wins: total_wins as u32,
+    })
+}
+
+/// Total number of ranked players, like the Postgres store
+fn ranked_total(
+    conn: &Connection,
+    total: Option<i64>,
+    start: Option<i64>,
+) -> Result<u32, UserCreateError> {
+    let total = match total {
+        Some(total) => total,
+        None => conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({RANKED_PLAYERS}) ranked"),
+            params![start],
+            |row| row.get(0),
+        )?,
+    };
+    Ok(total as u32)
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn pending_migrations(&self) -> Result<Vec<&'static str>, UserCreateError> {
+        self.call(|conn| {
+            Ok(verify_migrations(conn)?
+                .iter()
+                .map(|migration| migration.name)
+                .collect())
+        })
+        .await
+    }
+
+    async fn run_migrations(&self) -> Result<(), UserCreateError> {
+        self.call(|conn| {
+            let pending = verify_migrations(conn)?;
+            let tx = conn.transaction()?;
+            tx.execute_batch(
+                "
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    checksum BLOB NOT NULL,
+    applied_at INTEGER NOT NULL
+);
+                ",
+            )?;
+            // keeps the migrations an older build counted in user_version
+            for migration in SQLITE_MIGRATIONS.iter().filter(|migration| {
+                !pending.iter().any(|p| p.version == migration.version)
+            }) {
+                record_migration(&tx, migration)?;
+            }
+            tx.commit()?;
+
+            for migration in pending {
+                tracing::info!("Applying migration {}", migration.name);
+                let tx = conn.transaction()?;
+                tx.execute_batch(migration.sql).map_err(|e| {
+                    UserCreateError::Migration(format!("{} failed: {e}", migration.name))
+                })?;
+                record_migration(&tx, migration)?;
+                tx.commit()?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_player(
+        &self,
+        player_id: Uuid,
+        public_key: Vec<u8>,
+        secret_key: Vec<u8>,
+        name: String,
+        is_bot: bool,
+    ) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO players (player_id, public_key, secret_key, name, is_bot, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![player_id, public_key, secret_key, name, is_bot, millis(SystemTime::now())],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_player_name(
+        &self,
+        public_key: Vec<u8>,
+        name: String,
+    ) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE players SET name = ?1 WHERE public_key = ?2",
+                params![name, public_key],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn player_id(
+        &self,
+        public_key: Vec<u8>,
+    ) -> Result<Option<Uuid>, UserCreateError> {
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT player_id FROM players WHERE public_key = ?1",
+                    params![public_key],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn player_rating(&self, player_id: Uuid) -> Result<f64, UserCreateError> {
+        self.call(move |conn| {
+            Ok(conn.query_row(
+                "SELECT rating FROM players WHERE player_id = ?1",
+                params![player_id],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn bot_player(&self) -> Result<Option<(Uuid, Vec<u8>)>, UserCreateError> {
+        self.call(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT player_id, secret_key FROM players WHERE is_bot LIMIT 1",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn start_match(&self, started: StartedMatch) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO started_matches (match_id, time, seed, player1, player2, bot_game, created_at, series_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    started.match_id,
+                    started.time as i64,
+                    started.seed as i64,
+                    started.players.0,
+                    started.players.1,
+                    started.bot_game,
+                    millis(started.created_at),
+                    started.series_id,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn started_match(
+        &self,
+        seed: u64,
+        time: u64,
+    ) -> Result<Option<StartedMatch>, UserCreateError> {
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT match_id, player1, player2, bot_game, created_at, COALESCE(series_id, match_id) FROM started_matches WHERE seed = ?1 AND time = ?2",
+                    params![seed as i64, time as i64],
+                    |row| {
+                        Ok(StartedMatch {
+                            match_id: row.get(0)?,
+                            seed,
+                            time,
+                            players: (row.get(1)?, row.get(2)?),
+                            bot_game: row.get(3)?,
+                            created_at: from_millis(row.get(4)?),
+                            series_id: row.get(5)?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn save_match(&self, finished: FinishedMatch) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            let FinishedMatch {
+                started,
+                winner,
+                result,
+                outcome,
+                points,
+                rules,
+                moves,
+            } = finished;
+            let match_id = started.match_id;
+            let (user_id, partner_id) = started.players;
+            let now = millis(SystemTime::now());
+
+            let tx = conn.transaction()?;
+            let inserted = tx.execute(
+                "INSERT INTO matches (match_id, seed, time, player1, player2, winner, result, points_p1, points_p2, bot_game, rules, started_at, completed_at, series_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14) ON CONFLICT (match_id) DO NOTHING",
+                params![
+                    match_id,
+                    started.seed as i64,
+                    started.time as i64,
+                    user_id,
+                    partner_id,
+                    winner,
+                    result,
+                    points.0,
+                    points.1,
+                    started.bot_game,
+                    rules.encode(),
+                    millis(started.created_at),
+                    now,
+                    started.series_id,
+                ],
+            )?;
+            // both players may send the same game
+            if inserted == 0 {
+                return Err(UserCreateError::MatchAlreadySaved);
+            }
+            for (player_id, points) in [(user_id, points.0), (partner_id, points.1)] {
+                tx.execute(
+                    "INSERT INTO match_players (match_id, player_id, points, won, bot_game, completed_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![match_id, player_id, points, winner == Some(player_id), started.bot_game, now],
+                )?;
+            }
+            for item in &moves {
+                tx.execute(
+                    "INSERT INTO moves (match_id, player_id, number, x, seq, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![match_id, item.player, item.number, item.x, item.seq, item.now as i64],
+                )?;
+            }
+
+            // bot games stay out of the rankings
+            if !started.bot_game {
+                let rating = |id: Uuid| {
+                    tx.query_row(
+                        "SELECT rating, rating_deviation, rating_volatility FROM players WHERE player_id = ?1",
+                        params![id],
+                        |row| {
+                            Ok(Rating {
+                                rating: row.get(0)?,
+                                deviation: row.get(1)?,
+                                volatility: row.get(2)?,
+                            })
+                        },
+                    )
+                };
+                let (player, opponent) = (rating(user_id)?, rating(partner_id)?);
+                let (next_player, next_opponent) =
+                    Rating::rate_match(player, opponent, outcome);
+                for (id, before, after) in [
+                    (user_id, player, next_player),
+                    (partner_id, opponent, next_opponent),
+                ] {
+                    tx.execute(
+                        "UPDATE players SET rating = ?2, rating_deviation = ?3, rating_volatility = ?4 WHERE player_id = ?1",
+                        params![id, after.rating, after.deviation, after.volatility],
+                    )?;
+                    tx.execute(
+                        "INSERT INTO rating_history (match_id, player_id, rating_before, rating_after, deviation_before, deviation_after, volatility) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        params![
+                            match_id,
+                            id,
+                            before.rating,
+                            after.rating,
+                            before.deviation,
+                            after.deviation,
+                            after.volatility,
+                        ],
+                    )?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn push_live_move(
+        &self,
+        match_id: Uuid,
+        seq: u32,
+        item: Vec<u8>,
+    ) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO live_moves (match_id, seq, item) VALUES (?1, ?2, ?3)",
+                params![match_id, seq, item],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn live_moves(&self, match_id: Uuid) -> Result<Vec<Vec<u8>>, UserCreateError> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT item FROM live_moves WHERE match_id = ?1 ORDER BY seq",
+            )?;
+            let items = statement
+                .query_map(params![match_id], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(items)
+        })
+        .await
+    }
+
+    async fn clear_live_moves(&self, match_id: Uuid) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "DELETE FROM live_moves WHERE match_id = ?1",
+                params![match_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear_all_live_moves(&self) -> Result<u64, UserCreateError> {
+        self.call(|conn| Ok(conn.execute("DELETE FROM live_moves", [])? as u64))
+            .await
+    }
+
+    async fn save_tournament(
+        &self,
+        tournament_id: Uuid,
+        state: String,
+    ) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO tournaments (tournament_id, state, updated_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (tournament_id) DO UPDATE SET state = ?2, updated_at = ?3",
+                params![tournament_id, state, millis(SystemTime::now())],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn tournaments(&self) -> Result<Vec<(Uuid, String)>, UserCreateError> {
+        self.call(|conn| {
+            let mut statement =
+                conn.prepare("SELECT tournament_id, state FROM tournaments")?;
+            let tournaments = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(tournaments)
+        })
+        .await
+    }
+
+    async fn record_queue_time(
+        &self,
+        queue_id: Uuid,
+        queue_time: Duration,
+    ) -> Result<(), UserCreateError> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO queue_times (queue_time, queue_id, created_at) VALUES (?1, ?2, ?3)",
+                params![
+                    queue_time.as_secs() as i64,
+                    queue_id,
+                    millis(SystemTime::now())
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_matches(
+        &self,
+        player_id: Uuid,
+        filter: MatchFilter,
+    ) -> Result<MatchList, UserCreateError> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(
+                "
+SELECT
+    m.match_id,
+    o.name,
+    o.public_key,
+    CASE
+        WHEN m.winner IS NULL THEN 'tie'
+        WHEN m.winner = ?1 THEN 'win'
+        ELSE 'loss'
+    END AS outcome,
+    m.result = 'forfeit',
+    CASE WHEN m.player1 = ?1 THEN m.points_p1 ELSE m.points_p2 END,
+    CASE WHEN m.player1 = ?1 THEN m.points_p2 ELSE m.points_p1 END,
+    m.bot_game,
+    m.started_at,
+    m.completed_at,
+    COUNT(*) OVER () AS total
+FROM
+    matches m
+JOIN
+    players o ON o.player_id = CASE WHEN m.player1 = ?1 THEN m.player2 ELSE m.player1 END
+WHERE
+    ?1 IN (m.player1, m.player2)
+    AND (?2 IS NULL OR o.public_key = ?2)
+    AND (?3 IS NULL OR outcome = ?3)
+    AND (?4 IS NULL OR m.completed_at >= ?4)
+    AND (?5 IS NULL OR m.completed_at < ?5)
+ORDER BY
+    m.completed_at DESC
+LIMIT ?6 OFFSET ?7;
+                ",
+            )?;
+            let rows = statement
+                .query_map(
+                    params![
+                        player_id,
+                        filter.opponent,
+                        filter.result.map(|result| result.as_str()),
+                        filter.from.map(millis),
+                        filter.to.map(millis),
+                        filter.limit,
+                        filter.offset,
+                    ],
+                    |row| {
+                        let match_id: Uuid = row.get(0)?;
+                        let opponent_key: Vec<u8> = row.get(2)?;
+                        let result: String = row.get(3)?;
+                        let started_at: i64 = row.get(8)?;
+                        let completed_at: i64 = row.get(9)?;
+                        let total: i64 = row.get(10)?;
+                        let summary = MatchSummary {
+                            match_id: match_id.to_string(),
+                            opponent_name: row.get(1)?,
+                            opponent_key: STANDARD_NO_PAD.encode(opponent_key),
+                            result: match_result(&result),
+                            forfeit: row.get(4)?,
+                            points: row.get(5)?,
+                            opponent_points: row.get(6)?,
+                            bot_game: row.get(7)?,
+                            started_at: started_at as u64,
+                            completed_at: completed_at as u64,
+                        };
+                        Ok((summary, total as u32))
+                    },
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(MatchList {
+                total: rows.first().map(|(_, total)| *total).unwrap_or_default(),
+                entries: rows.into_iter().map(|(summary, _)| summary).collect(),
+            })
+        })
+        .await
+    }
+
+    async fn match_details(
+        &self,
+        match_id: Uuid,
+    ) -> Result<Option<MatchDetails>, UserCreateError> {
+        self.call(move |conn| {
+            let details = conn
+                .query_row(
+                    "
+SELECT
+    p1.name,
+    p1.public_key,
+    m.points_p1,
+    p2.name,
+    p2.public_key,
+    m.points_p2,
+    w.public_key,
+    m.result,
+    m.bot_game,
+    m.rules,
+    m.started_at,
+    m.completed_at,
+    COALESCE(m.series_id, m.match_id)
+FROM
+    matches m
+JOIN
+    players p1 ON p1.player_id = m.player1
+JOIN
+    players p2 ON p2.player_id = m.player2
+LEFT JOIN
+    players w ON w.player_id = m.winner
+WHERE
+    m.match_id = ?1;
+                    ",
+                    params![match_id],
+                    |row| {
+                        let player = |name: usize, key: usize, points: usize| {
+                            let public_key: Vec<u8> = row.get(key)?;
+                            Ok::<_, rusqlite::Error>(MatchPlayer {
+                                name: row.get(name)?,
+                                public_key: STANDARD_NO_PAD.encode(public_key),
+                                points: row.get(points)?,
+                            })
+                        };
+                        let winner: Option<Vec<u8>> = row.get(6)?;
+                        let rules: String = row.get(9)?;
+                        let started_at: i64 = row.get(10)?;
+                        let completed_at: i64 = row.get(11)?;
+                        let series_id: Uuid = row.get(12)?;
+                        Ok((
+                            MatchDetails {
+                                match_id: match_id.to_string(),
+                                player1: player(0, 1, 2)?,
+                                player2: player(3, 4, 5)?,
+                                winner: winner.map(|key| STANDARD_NO_PAD.encode(key)),
+                                result: row.get(7)?,
+                                bot_game: row.get(8)?,
+                                rules: RuleSet::default(),
+                                started_at: started_at as u64,
+                                completed_at: completed_at as u64,
+                                series_id: series_id.to_string(),
+                                moves: Vec::new(),
+                            },
+                            rules,
+                        ))
+                    },
+                )
+                .optional()?;
+            let Some((mut details, rules)) = details else {
+                return Ok(None);
+            };
+            details.rules = RuleSet::decode(&rules)?;
+
+            let mut statement = conn.prepare(
+                "
+SELECT
+    mv.seq,
+    p.public_key,
+    mv.x,
+    mv.number,
+    mv.created_at
+FROM
+    moves mv
+JOIN
+    players p ON p.player_id = mv.player_id
+WHERE
+    mv.match_id = ?1
+ORDER BY
+    mv.seq;
+                ",
+            )?;
+            details.moves = statement
+                .query_map(params![match_id], |row| {
+                    let player: Vec<u8> = row.get(1)?;
+                    let created_at: i64 = row.get(4)?;
+                    Ok(MatchMove {
+                        seq: row.get(0)?,
+                        player: STANDARD_NO_PAD.encode(player),
+                        x: row.get(2)?,
+                        number: row.get(3)?,
+                        created_at: created_at as u64,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(Some(details))
+        })
+        .await
+    }
+
+    async fn leader_board(
+        &self,
+        start: Option<SystemTime>,
+        page: u32,
+        limit: u32,
+    ) -> Result<LeaderBoard, UserCreateError> {
+        self.call(move |conn| {
+            let start = start.map(millis);
+            let mut statement = conn.prepare(&format!(
+                "{RANKED_PLAYERS} ORDER BY rank, p.name LIMIT ?2 OFFSET ?3"
+            ))?;
+            let rows = statement
+                .query_map(params![start, limit, page as i64 * limit as i64], |row| {
+                    Ok((entry_from_row(row)?, row.get::<_, i64>(6)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(LeaderBoard {
+                total: ranked_total(conn, rows.first().map(|(_, total)| *total), start)?,
+                page,
+                limit,
+                entries: rows.into_iter().map(|(entry, _)| entry).collect(),
+            })
+        })
+        .await
+    }
+
+    async fn leader_board_rank(
+        &self,
+        start: Option<SystemTime>,
+        public_key: Vec<u8>,
+    ) -> Result<LeaderBoardRank, UserCreateError> {
+        self.call(move |conn| {
+            let start = start.map(millis);
+            let row = conn
+                .query_row(
+                    &format!(
+                        "SELECT * FROM ({RANKED_PLAYERS}) ranked WHERE public_key = ?2"
+                    ),
+                    params![start, public_key],
+                    |row| Ok((entry_from_row(row)?, row.get::<_, i64>(6)?)),
+                )
+                .optional()?;
+            Ok(LeaderBoardRank {
+                total: ranked_total(conn, row.as_ref().map(|(_, total)| *total), start)?,
+                entry: row.map(|(entry, _)| entry),
+            })
+        })
+        .await
+    }
+
+    async fn rating_leader_board(&self) -> Result<RatingLeaderBoard, UserCreateError> {
+        self.call(|conn| {
+            let mut statement = conn.prepare(
+                "
+SELECT
+    p.name,
+    p.rating,
+    p.rating_deviation,
+    COUNT(h.match_id) AS rated_games
+FROM
+    players p
+JOIN
+    rating_history h ON h.player_id = p.player_id
+WHERE
+    NOT p.is_bot
+GROUP BY
+    p.player_id
+ORDER BY
+    p.rating DESC, p.rating_deviation, p.name;
+                ",
+            )?;
+            let entries = statement
+                .query_map([], |row| {
+                    Ok(RatingLeaderBoardEntry {
+                        name: row.get(0)?,
+                        rating: row.get(1)?,
+                        deviation: row.get(2)?,
+                        rated_games: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(RatingLeaderBoard {
+                total: entries.len() as u32,
+                entries,
+            })
+        })
+        .await
+    }
+}
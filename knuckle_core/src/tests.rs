@@ -0,0 +1,1707 @@
+//! Runs the whole server in process against an in memory SQLite store and
+//! talks to it like the web client does
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use ed25519_dalek::{Signer, SigningKey};
+use futures::{SinkExt, StreamExt};
+use lib_knuckle::{
+    ai::{Ai, Strategy},
+    api_interfaces::{
+        GameBody, LeaderBoard, LeaderBoardRank, LobbyInfo, MatchDetails, MatchList,
+        MatchResult, NewTournament, RatingLeaderBoard, TournamentAction,
+        TournamentFormat, TournamentInfo, TournamentStatus,
+    },
+    dice::DiceChain,
+    game::{Game, HistoryItem, ServerGameInfo},
+    keys::Keys,
+    protocol::{ServerMessage, PROTOCOL_VERSION},
+    rules::RuleSet,
+    seed::{self, SeedCommitments, SeedReveal},
+    verifying_key_from_string,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream,
+};
+use uuid::Uuid;
+
+use crate::{
+    app,
+    bot::{BotData, BotPlayer},
+    database::SQLITE_MIGRATIONS,
+    heartbeat::{self, Heartbeat},
+    ice_servers::{GoogleIceServerProvider, IceServerProvider},
+    matchmaker, metrics_app,
+    store::{SharedStore, SqliteStore},
+    tournament, AppState, User,
+};
+
+/// Long enough for the matchmaker to pair two waiting players
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct TestServer {
+    addr: SocketAddr,
+    http: reqwest::Client,
+}
+
+pub struct TestPlayer {
+    pub signing_key: SigningKey,
+    pub pub_key: String,
+}
+
+/// Match parameters one player got from the server
+#[derive(Debug, Clone)]
+pub struct Pairing {
+    pub public_key: String,
+    pub partner_key: String,
+    pub initiator: bool,
+    pub seed: u64,
+    pub signature: String,
+    pub time: u64,
+    pub rules: RuleSet,
+    pub commitments: Option<SeedCommitments>,
+    pub seed_reveal: Option<SeedReveal>,
+    pub dice_chain: Option<DiceChain>,
+}
+
+/// Server state on a fresh in memory store, without any background tasks
+async fn test_state() -> AppState {
+    test_state_with(None).await
+}
+
+/// Like `test_state`, with a bot that plays anyone waiting longer than
+/// `bot_wait`
+async fn test_state_with(bot_wait: Option<Duration>) -> AppState {
+    let store: SharedStore = Arc::new(SqliteStore::open(":memory:").unwrap());
+    store.run_migrations().await.unwrap();
+    let bot = match bot_wait {
+        Some(wait) => Some(Arc::new(
+            BotPlayer::load_or_create(&store, wait).await.unwrap(),
+        )),
+        None => None,
+    };
+    state_on(store, bot)
+}
+
+/// Server state on an existing store, like after a restart
+fn state_on(store: SharedStore, bot: BotData) -> AppState {
+    AppState::new(
+        store,
+        SigningKey::generate(&mut rand_core::OsRng),
+        Arc::new(IceServerProvider::Google(GoogleIceServerProvider)),
+        bot,
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+        Duration::ZERO,
+    )
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(Heartbeat::default()).await
+    }
+
+    pub async fn start_with(heartbeat: Heartbeat) -> Self {
+        Self::serve(test_state().await.with_heartbeat(heartbeat)).await
+    }
+
+    pub async fn serve(state: AppState) -> Self {
+        tokio::spawn(matchmaker::run(state.clone()));
+        tokio::spawn(tournament::run(state.clone()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = app(state.clone()).merge(metrics_app(state));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        Self {
+            addr,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> T {
+        let response = self.http.get(self.url(path)).send().await.unwrap();
+        assert!(response.status().is_success(), "GET {path}: {response:?}");
+        response.json().await.unwrap()
+    }
+
+    pub async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> T {
+        let response = self
+            .http
+            .post(self.url(path))
+            .json(body)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "POST {path}: {response:?}");
+        response.json().await.unwrap()
+    }
+
+    pub async fn signup(&self) -> TestPlayer {
+        let keys: serde_json::Value = self.get("/signup").await;
+        let priv_key = STANDARD_NO_PAD
+            .decode(keys["priv_key"].as_str().unwrap())
+            .unwrap();
+        TestPlayer {
+            signing_key: SigningKey::from_bytes(&priv_key.try_into().unwrap()),
+            pub_key: keys["pub_key"].as_str().unwrap().to_owned(),
+        }
+    }
+
+    /// Opens a websocket and answers the verify challenge, returns the socket
+    /// and the challenge for later messages
+    pub async fn connect(&self) -> (Socket, String) {
+        let (mut socket, _) = connect_async(format!("ws://{}/ws", self.addr))
+            .await
+            .unwrap();
+        let verify_time = match receive(&mut socket).await {
+            ServerMessage::Verify { verify_time } => verify_time,
+            other => panic!("Expected verify, got {other:?}"),
+        };
+        (socket, verify_time)
+    }
+
+    /// Connects and joins the public queue
+    pub async fn join(&self, player: &TestPlayer) -> Socket {
+        let (mut socket, verify_time) = self.connect().await;
+        send(
+            &mut socket,
+            serde_json::json!({
+                "type": "join",
+                "pub_key": player.pub_key,
+                "signature": player.sign(&verify_time),
+            }),
+        )
+        .await;
+        socket
+    }
+
+    /// Joins the queue with both players and waits until they are paired,
+    /// the initiator comes first
+    pub async fn pair(
+        &self,
+        first: &TestPlayer,
+        second: &TestPlayer,
+    ) -> ((Socket, Pairing), (Socket, Pairing)) {
+        let mut first_socket = self.join(first).await;
+        let mut second_socket = self.join(second).await;
+        let first_pairing = wait_for_pairing(&mut first_socket).await;
+        let second_pairing = wait_for_pairing(&mut second_socket).await;
+        assert_eq!(first_pairing.partner_key, second_pairing.public_key);
+        assert_eq!(first_pairing.seed, second_pairing.seed);
+        if first_pairing.initiator {
+            (
+                (first_socket, first_pairing),
+                (second_socket, second_pairing),
+            )
+        } else {
+            (
+                (second_socket, second_pairing),
+                (first_socket, first_pairing),
+            )
+        }
+    }
+
+    /// Like `pair` but both players commit to the anchor of a dice chain on
+    /// join and reveal it once paired, the pairings hold the derived seed
+    pub async fn pair_committed(
+        &self,
+        first: &TestPlayer,
+        second: &TestPlayer,
+        rules: RuleSet,
+    ) -> ((Socket, Pairing), (Socket, Pairing)) {
+        let mut joined = Vec::new();
+        for player in [first, second] {
+            let (mut socket, verify_time) = self.connect().await;
+            let dice_chain = DiceChain::new();
+            send(
+                &mut socket,
+                serde_json::json!({
+                    "type": "join",
+                    "pub_key": player.pub_key,
+                    "signature": player.sign(&verify_time),
+                    "rules": rules,
+                    "commitment": seed::commitment(&dice_chain.anchor()).unwrap(),
+                }),
+            )
+            .await;
+            joined.push((socket, dice_chain));
+        }
+        let mut paired = Vec::new();
+        for (mut socket, dice_chain) in joined {
+            let mut pairing = wait_for_pairing(&mut socket).await;
+            send(
+                &mut socket,
+                serde_json::json!({ "type": "reveal", "contribution": dice_chain.anchor() }),
+            )
+            .await;
+            pairing.dice_chain = Some(dice_chain);
+            paired.push((socket, pairing));
+        }
+        for (socket, pairing) in paired.iter_mut() {
+            match receive(socket).await {
+                ServerMessage::SeedRevealed { seed, reveal } => {
+                    pairing.seed = seed;
+                    pairing.seed_reveal = Some(reveal);
+                }
+                other => panic!("Expected seed-revealed, got {other:?}"),
+            }
+        }
+        let second_paired = paired.pop().unwrap();
+        let first_paired = paired.pop().unwrap();
+        match first_paired.1.initiator {
+            true => (first_paired, second_paired),
+            false => (second_paired, first_paired),
+        }
+    }
+
+    pub async fn submit_game(&self, body: &GameBody) -> reqwest::Response {
+        self.http
+            .post(self.url("/submit_game"))
+            .json(body)
+            .send()
+            .await
+            .unwrap()
+    }
+}
+
+impl TestPlayer {
+    pub fn sign(&self, message: &str) -> String {
+        STANDARD_NO_PAD.encode(self.signing_key.sign(message.as_bytes()).to_bytes())
+    }
+}
+
+pub async fn send(socket: &mut Socket, message: serde_json::Value) {
+    socket
+        .send(Message::Text(message.to_string()))
+        .await
+        .unwrap();
+}
+
+/// Next text message from the server, binary moves are skipped
+pub async fn receive(socket: &mut Socket) -> ServerMessage {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, socket.next())
+            .await
+            .expect("Timed out waiting for the server")
+            .expect("Socket closed")
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+async fn wait_for_pairing(socket: &mut Socket) -> Pairing {
+    match receive(socket).await {
+        ServerMessage::Paired {
+            public_key,
+            partner_key,
+            initiator,
+            seed,
+            signature,
+            time,
+            rules,
+            commitments,
+            ..
+        } => Pairing {
+            public_key,
+            partner_key,
+            initiator,
+            seed,
+            signature,
+            time,
+            rules,
+            commitments,
+            seed_reveal: None,
+            dice_chain: None,
+        },
+        other => panic!("Expected paired, got {other:?}"),
+    }
+}
+
+/// The game of one player, set up from their pairing
+pub fn new_game(player: &TestPlayer, opponent: &TestPlayer, pairing: &Pairing) -> Game {
+    let mut game = Game::new(
+        Keys::Sign {
+            my_keys: player.signing_key.clone(),
+            other_keys: opponent.signing_key.verifying_key(),
+        },
+        pairing.rules,
+        ServerGameInfo::new(pairing.seed, pairing.initiator)
+            .with_reveal(pairing.seed_reveal.as_ref()),
+    )
+    .unwrap();
+    if let Some(dice_chain) = pairing.dice_chain.clone() {
+        game.set_dice_chain(dice_chain);
+    }
+    game
+}
+
+/// Plays a full signed game between both players with random moves, returns
+/// the body the initiator submits
+pub fn play_game(
+    (initiator, initiator_pairing): (&TestPlayer, &Pairing),
+    (other, other_pairing): (&TestPlayer, &Pairing),
+) -> GameBody {
+    let mut players = [
+        new_game(initiator, other, initiator_pairing),
+        new_game(other, initiator, other_pairing),
+    ];
+    let mut ai = Ai::with_seed(Strategy::Random, initiator_pairing.seed);
+    let mut turn = 0;
+    while !players[0].is_completed() {
+        let x = ai
+            .choose_column(&players[turn % 2].get_board_data())
+            .unwrap();
+        let item = players[turn % 2].place(x).unwrap();
+        players[(turn + 1) % 2].add_opponent_move(item).unwrap();
+        turn += 1;
+    }
+
+    GameBody {
+        seed: initiator_pairing.seed,
+        time: initiator_pairing.time,
+        your_key: initiator_pairing.public_key.clone(),
+        opponent_key: initiator_pairing.partner_key.clone(),
+        starting: true,
+        rules: initiator_pairing.rules,
+        signature: initiator_pairing.signature.clone(),
+        moves: players[0].history().to_vec(),
+        seed_reveal: initiator_pairing.seed_reveal.clone(),
+    }
+}
+
+#[tokio::test]
+async fn test_full_game() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+
+    // players without games are listed with zero counts
+    let leader_board: LeaderBoard = server.get("/leaderboard").await;
+    assert_eq!(leader_board.total, 2);
+    assert!(leader_board
+        .entries
+        .iter()
+        .all(|entry| entry.total_games == 0 && entry.total_points == 0));
+
+    let ((_first_socket, first), (_second_socket, second)) =
+        server.pair(&alice, &bob).await;
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let body = play_game((initiator, &first), (other, &second));
+    let response = server.submit_game(&body).await;
+    assert!(response.status().is_success(), "{response:?}");
+    // sending it again does not count it twice
+    let response = server.submit_game(&body).await;
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+    let leader_board: LeaderBoard = server.get("/leaderboard").await;
+    assert_eq!(leader_board.total, 2);
+    assert!(leader_board
+        .entries
+        .iter()
+        .all(|entry| entry.total_games == 1));
+    let total_wins = leader_board
+        .entries
+        .iter()
+        .map(|entry| entry.total_wins)
+        .sum::<u32>();
+    assert!(total_wins <= 1);
+
+    let ratings: RatingLeaderBoard = server.get("/leaderboard/rating").await;
+    assert_eq!(ratings.total, 2);
+    assert!(ratings.entries.iter().all(|entry| entry.rated_games == 1));
+    assert!(ratings.entries.iter().all(|entry| entry.deviation < 350.0));
+
+    let matches: MatchList = server
+        .get(&format!("/matches?player={}", urlencode(&alice.pub_key)))
+        .await;
+    assert_eq!(matches.total, 1);
+    assert_eq!(matches.entries[0].opponent_key, bob.pub_key);
+}
+
+#[tokio::test]
+async fn test_leaderboard_pages_and_rank() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let carol = server.signup().await;
+    let ((_first_socket, first), (_second_socket, second)) =
+        server.pair(&alice, &bob).await;
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let body = play_game((initiator, &first), (other, &second));
+    let response = server.submit_game(&body).await;
+    assert!(response.status().is_success(), "{response:?}");
+
+    let mut entries = Vec::new();
+    for page in 0..3 {
+        let leader_board: LeaderBoard = server
+            .get(&format!("/leaderboard?window=daily&page={page}&limit=1"))
+            .await;
+        assert_eq!(leader_board.total, 3);
+        assert_eq!((leader_board.page, leader_board.limit), (page, 1));
+        assert_eq!(leader_board.entries.len(), 1);
+        entries.extend(leader_board.entries);
+    }
+    assert!(entries.windows(2).all(|pair| pair[0].rank <= pair[1].rank));
+    // carol played nothing today and is still ranked, below both players
+    assert_eq!(entries[2].total_games, 0);
+    assert!(entries[..2].iter().all(|entry| entry.total_games == 1));
+    let past_the_end: LeaderBoard =
+        server.get("/leaderboard?window=daily&page=3&limit=1").await;
+    assert_eq!(past_the_end.total, 3);
+    assert!(past_the_end.entries.is_empty());
+
+    for (player, games) in [(&alice, 1), (&bob, 1), (&carol, 0)] {
+        let rank: LeaderBoardRank = server
+            .get(&format!(
+                "/leaderboard/rank?player={}&window=weekly",
+                urlencode(&player.pub_key)
+            ))
+            .await;
+        assert_eq!(rank.total, 3);
+        let entry = rank.entry.unwrap();
+        assert_eq!(entry.total_games, games);
+        assert!(entries
+            .iter()
+            .any(|ranked| ranked.name == entry.name && ranked.rank == entry.rank));
+    }
+    let unknown: LeaderBoardRank = server
+        .get(&format!(
+            "/leaderboard/rank?player={}",
+            urlencode(&STANDARD_NO_PAD.encode([7u8; 32]))
+        ))
+        .await;
+    assert_eq!(unknown.total, 3);
+    assert!(unknown.entry.is_none());
+}
+
+#[tokio::test]
+async fn test_rejects_tampered_game() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((_first_socket, first), (_second_socket, second)) =
+        server.pair(&alice, &bob).await;
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let body = play_game((initiator, &first), (other, &second));
+
+    let response = server
+        .submit_game(&GameBody {
+            seed: body.seed + 1,
+            ..body.clone()
+        })
+        .await;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let mut moves = body.moves.clone();
+    moves.swap(0, 1);
+    let response = server.submit_game(&GameBody { moves, ..body }).await;
+    assert!(response.status().is_client_error());
+
+    let leader_board: LeaderBoard = server.get("/leaderboard").await;
+    assert!(leader_board
+        .entries
+        .iter()
+        .all(|entry| entry.total_games == 0));
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((_first_socket, first), (_second_socket, second)) =
+        server.pair(&alice, &bob).await;
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let body = play_game((initiator, &first), (other, &second));
+    let response = server
+        .submit_game(&GameBody {
+            seed: body.seed + 1,
+            ..body.clone()
+        })
+        .await;
+    assert!(response.status().is_client_error());
+    let response = server.submit_game(&body).await;
+    assert!(response.status().is_success(), "{response:?}");
+
+    let response = server
+        .http
+        .get(server.url("/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{response:?}");
+    let metrics = response.text().await.unwrap();
+    for line in [
+        "knuckle_connected_users 2",
+        "knuckle_queue_length{queue=\"public\"} 0",
+        "knuckle_queue_length{queue=\"private\"} 0",
+        "knuckle_pairings_total{kind=\"player\"} 1",
+        "knuckle_queue_wait_seconds_count 2",
+        "knuckle_submitted_games_total{outcome=\"accepted\"} 1",
+        "knuckle_db_pool_connections",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l.starts_with(line)),
+            "{line} missing from:\n{metrics}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_commit_reveal_seed() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((_first_socket, first), (_second_socket, second)) = server
+        .pair_committed(&alice, &bob, RuleSet::default())
+        .await;
+
+    let commitments = first.commitments.clone().unwrap();
+    assert_eq!(second.commitments, Some(commitments.clone()));
+    let reveal = first.seed_reveal.clone().unwrap();
+    assert_eq!(second.seed_reveal, Some(reveal.clone()));
+    assert_eq!(reveal.verify(&commitments), Ok(first.seed));
+    assert_eq!(first.seed, second.seed);
+
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let body = play_game((initiator, &first), (other, &second));
+
+    // the server signed the commitments, not the seed
+    let response = server
+        .submit_game(&GameBody {
+            seed_reveal: None,
+            ..body.clone()
+        })
+        .await;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // a different server contribution no longer matches the signed commitments
+    let forged = SeedReveal {
+        server: seed::new_contribution(),
+        ..reveal
+    };
+    let response = server
+        .submit_game(&GameBody {
+            seed: forged.seed().unwrap(),
+            seed_reveal: Some(forged),
+            ..body.clone()
+        })
+        .await;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = server.submit_game(&body).await;
+    assert!(response.status().is_success(), "{response:?}");
+}
+
+#[tokio::test]
+async fn test_protocol_errors_keep_connection() {
+    let server = TestServer::start().await;
+    let (mut socket, _) = server.connect().await;
+
+    send(
+        &mut socket,
+        serde_json::json!({ "type": "hello", "version": 99 }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::Hello { version } => assert_eq!(version, PROTOCOL_VERSION),
+        other => panic!("Expected hello, got {other:?}"),
+    }
+
+    send(&mut socket, serde_json::json!({ "type": "dance" })).await;
+    match receive(&mut socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "UnknownMessage"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+    send(&mut socket, serde_json::json!({ "type": "spectate" })).await;
+    match receive(&mut socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "MalformedMessage"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+    send(
+        &mut socket,
+        serde_json::json!({ "type": "spectate", "match_id": "not-a-uuid" }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "UuidParseError"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+    send(
+        &mut socket,
+        serde_json::json!({ "type": "reconnect", "pub_key": "nope", "signature": "nope" }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::Error { .. } => {}
+        other => panic!("Expected error, got {other:?}"),
+    }
+
+    // still connected after all of them
+    send(
+        &mut socket,
+        serde_json::json!({ "type": "list-live-matches" }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::LiveMatches { matches } => assert!(matches.is_empty()),
+        other => panic!("Expected live matches, got {other:?}"),
+    }
+
+    // versions the server no longer speaks end the connection
+    send(
+        &mut socket,
+        serde_json::json!({ "type": "hello", "version": 0 }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::Disconnected { name, .. } => {
+            assert_eq!(name, "UnsupportedProtocolVersion")
+        }
+        other => panic!("Expected disconnected, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_silent_partner_is_dropped() {
+    let server = TestServer::start_with(Heartbeat {
+        ping_interval: Duration::from_millis(100),
+        idle_timeout: Duration::from_millis(500),
+        session_timeout: Duration::from_secs(60),
+    })
+    .await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((silent, _), (mut socket, _)) = server.pair(&alice, &bob).await;
+
+    // never reading means never answering pings, like a connection that died
+    // without closing
+    match receive(&mut socket).await {
+        ServerMessage::PartnerLeft => {}
+        other => panic!("Expected partner left, got {other:?}"),
+    }
+    drop(silent);
+}
+
+#[tokio::test]
+async fn test_sweeper_evicts_ghosts() {
+    let state = test_state().await;
+    // the socket of the ghost is gone but its handler never cleaned up
+    let (ghost_sender, ghost_receiver) = tokio::sync::mpsc::unbounded_channel();
+    drop(ghost_receiver);
+    let (partner_sender, mut partner_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (ghost_id, partner_id, gone_id) =
+        (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let mut ghost = User::new(ghost_sender);
+    ghost.set_partner_id(partner_id);
+    let mut partner = User::new(partner_sender);
+    partner.set_partner_id(ghost_id);
+    state.all_users.insert(ghost_id, ghost).ok();
+    state.all_users.insert(partner_id, partner).ok();
+    state
+        .queues
+        .update_async(&Uuid::nil(), |_, queue| queue.extend([ghost_id, gone_id]))
+        .await;
+
+    heartbeat::sweep(&state).await;
+
+    assert!(!state.all_users.contains(&ghost_id));
+    assert!(state.all_users.contains(&partner_id));
+    let queue = state
+        .queues
+        .read(&Uuid::nil(), |_, queue| queue.clone())
+        .unwrap();
+    assert!(queue.is_empty());
+    match partner_receiver.try_recv().unwrap() {
+        axum::extract::ws::Message::Text(text) => assert!(matches!(
+            serde_json::from_str(&text).unwrap(),
+            ServerMessage::PartnerLeft
+        )),
+        other => panic!("Expected partner left, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_private_lobby() {
+    let server = TestServer::start().await;
+    let host = server.signup().await;
+    let guest = server.signup().await;
+    let stranger = server.signup().await;
+
+    let (mut host_socket, verify_time) = server.connect().await;
+    send(
+        &mut host_socket,
+        serde_json::json!({
+            "type": "create-lobby",
+            "pub_key": host.pub_key,
+            "signature": host.sign(&verify_time),
+            "password": "hunter2",
+        }),
+    )
+    .await;
+    let code = match receive(&mut host_socket).await {
+        ServerMessage::LobbyCreated { code, .. } => code,
+        other => panic!("Expected lobby-created, got {other:?}"),
+    };
+    let info: LobbyInfo = server.get(&format!("/lobby/{}", code.to_lowercase())).await;
+    assert!(info.has_password && !info.guest_joined);
+    assert_eq!(info.host_key, host.pub_key);
+
+    let join = |player: &TestPlayer, verify_time: &str, password: &str| {
+        serde_json::json!({
+            "type": "join",
+            "pub_key": player.pub_key,
+            "signature": player.sign(verify_time),
+            "lobby": code,
+            "password": password,
+        })
+    };
+    let (mut stranger_socket, verify_time_stranger) = server.connect().await;
+    send(
+        &mut stranger_socket,
+        join(&stranger, &verify_time_stranger, "hunter3"),
+    )
+    .await;
+    match receive(&mut stranger_socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "WrongLobbyPassword"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+
+    send(
+        &mut host_socket,
+        serde_json::json!({ "type": "start-lobby" }),
+    )
+    .await;
+    match receive(&mut host_socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "BadRequest"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+
+    let (mut guest_socket, verify_time) = server.connect().await;
+    send(&mut guest_socket, join(&guest, &verify_time, "hunter2")).await;
+    match receive(&mut guest_socket).await {
+        ServerMessage::LobbyJoined { lobby } => assert_eq!(lobby.code, code),
+        other => panic!("Expected lobby-joined, got {other:?}"),
+    }
+    match receive(&mut host_socket).await {
+        ServerMessage::LobbyGuestJoined { guest_key } => {
+            assert_eq!(guest_key, guest.pub_key)
+        }
+        other => panic!("Expected lobby-guest-joined, got {other:?}"),
+    }
+
+    // the stranger is still connected and can try again
+    send(
+        &mut stranger_socket,
+        join(&stranger, &verify_time_stranger, "hunter2"),
+    )
+    .await;
+    match receive(&mut stranger_socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "LobbyFull"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+
+    send(
+        &mut host_socket,
+        serde_json::json!({ "type": "start-lobby" }),
+    )
+    .await;
+    let host_pairing = wait_for_pairing(&mut host_socket).await;
+    let guest_pairing = wait_for_pairing(&mut guest_socket).await;
+    assert_eq!(host_pairing.partner_key, guest.pub_key);
+    assert_eq!(guest_pairing.partner_key, host.pub_key);
+
+    let response = server
+        .http
+        .get(server.url(&format!("/lobby/{code}")))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+/// Next move the server relayed from the partner
+async fn receive_move(socket: &mut Socket) -> HistoryItem {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, socket.next())
+            .await
+            .expect("Timed out waiting for the server")
+            .expect("Socket closed")
+            .unwrap();
+        if let Message::Binary(data) = message {
+            return bincode::deserialize(&data).unwrap();
+        }
+    }
+}
+
+/// Plays a full game with random moves sent through the server relay, returns
+/// the games of both players
+async fn play_relayed_game(
+    (initiator, initiator_pairing, initiator_socket): (
+        &TestPlayer,
+        &Pairing,
+        &mut Socket,
+    ),
+    (other, other_pairing, other_socket): (&TestPlayer, &Pairing, &mut Socket),
+) -> [Game; 2] {
+    let mut players = [
+        new_game(initiator, other, initiator_pairing),
+        new_game(other, initiator, other_pairing),
+    ];
+    relay_moves(
+        &mut players,
+        [initiator_socket, other_socket],
+        initiator_pairing.seed,
+        usize::MAX,
+    )
+    .await;
+    players
+}
+
+/// Relays up to `count` moves between the players, picking up at whoever's
+/// turn it is
+async fn relay_moves(
+    players: &mut [Game; 2],
+    sockets: [&mut Socket; 2],
+    seed: u64,
+    count: usize,
+) {
+    let mut ai = Ai::with_seed(Strategy::Random, seed);
+    let mut turn = players[0].history().len();
+    let last = turn.saturating_add(count);
+    while !players[0].is_completed() && turn < last {
+        let (current, next) = (turn % 2, (turn + 1) % 2);
+        let x = ai
+            .choose_column(&players[current].get_board_data())
+            .unwrap();
+        let item = players[current].place(x).unwrap();
+        sockets[current]
+            .send(Message::Binary(bincode::serialize(&item).unwrap()))
+            .await
+            .unwrap();
+        // the relay only takes moves in order
+        let relayed = receive_move(sockets[next]).await;
+        players[next].add_opponent_move(relayed).unwrap();
+        turn += 1;
+    }
+}
+
+#[tokio::test]
+async fn test_bot_game_is_stored() {
+    let server = TestServer::serve(test_state_with(Some(Duration::ZERO)).await).await;
+    let alice = server.signup().await;
+    let mut socket = server.join(&alice).await;
+    let pairing = wait_for_pairing(&mut socket).await;
+    let mut game = Game::new(
+        Keys::Sign {
+            my_keys: alice.signing_key.clone(),
+            other_keys: verifying_key_from_string(&pairing.partner_key).unwrap(),
+        },
+        pairing.rules,
+        ServerGameInfo::new(pairing.seed, pairing.initiator).with_time(pairing.time),
+    )
+    .unwrap();
+    let mut ai = Ai::with_seed(Strategy::Random, 0);
+    while !game.is_completed() {
+        match ai.choose_column(&game.get_board_data()) {
+            Some(x) => {
+                let item = game.place(x).unwrap();
+                socket
+                    .send(Message::Binary(bincode::serialize(&item).unwrap()))
+                    .await
+                    .unwrap();
+            }
+            None => game
+                .add_opponent_move(receive_move(&mut socket).await)
+                .unwrap(),
+        }
+    }
+
+    // nobody submits the game, the bot stores it on its own
+    let path = format!("/matches?player={}", urlencode(&alice.pub_key));
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        let matches: MatchList = server.get(&path).await;
+        if matches.total == 1 {
+            assert_eq!(matches.entries[0].opponent_key, pairing.partner_key);
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "Bot game not stored"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_leaving_bot_game_is_a_loss() {
+    let server = TestServer::serve(test_state_with(Some(Duration::ZERO)).await).await;
+    let alice = server.signup().await;
+    let mut socket = server.join(&alice).await;
+    let pairing = wait_for_pairing(&mut socket).await;
+    let mut game = Game::new(
+        Keys::Sign {
+            my_keys: alice.signing_key.clone(),
+            other_keys: verifying_key_from_string(&pairing.partner_key).unwrap(),
+        },
+        pairing.rules,
+        ServerGameInfo::new(pairing.seed, pairing.initiator).with_time(pairing.time),
+    )
+    .unwrap();
+    let item = game.place(0).unwrap();
+    socket
+        .send(Message::Binary(bincode::serialize(&item).unwrap()))
+        .await
+        .unwrap();
+    receive_move(&mut socket).await;
+    drop(socket);
+
+    let path = format!("/matches?player={}", urlencode(&alice.pub_key));
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        let matches: MatchList = server.get(&path).await;
+        if matches.total == 1 {
+            assert_eq!(matches.entries[0].result, MatchResult::Loss);
+            assert!(matches.entries[0].forfeit);
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "Abandoned bot game not stored"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_rejected_relayed_move() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((mut first_socket, first), (mut second_socket, second)) =
+        server.pair(&alice, &bob).await;
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+
+    // signed by someone else
+    let mallory = server.signup().await;
+    let item = new_game(&mallory, other, &first).place(0).unwrap();
+    first_socket
+        .send(Message::Binary(bincode::serialize(&item).unwrap()))
+        .await
+        .unwrap();
+    match receive(&mut first_socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "InvalidMoveSignature"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+
+    // the match goes on as if the move was never sent
+    let players = play_relayed_game(
+        (initiator, &first, &mut first_socket),
+        (other, &second, &mut second_socket),
+    )
+    .await;
+    assert!(players[1].is_completed());
+    let path = format!("/matches?player={}", urlencode(&initiator.pub_key));
+    let mut matches: MatchList = server.get(&path).await;
+    for _ in 0..50 {
+        if matches.total > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        matches = server.get(&path).await;
+    }
+    assert_eq!(matches.total, 1);
+}
+
+#[tokio::test]
+async fn test_startup_drops_live_moves() {
+    let store: SharedStore = Arc::new(SqliteStore::open(":memory:").unwrap());
+    store.run_migrations().await.unwrap();
+    let match_id = Uuid::new_v4();
+    for seq in 1..=2 {
+        store.push_live_move(match_id, seq, vec![1]).await.unwrap();
+    }
+    assert_eq!(store.clear_all_live_moves().await.unwrap(), 2);
+    assert!(store.live_moves(match_id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_sqlite_migrations_verified() {
+    let path = std::env::temp_dir().join(format!("knuckle-{}.sqlite", Uuid::new_v4()));
+    let path = path.to_str().unwrap();
+    {
+        let store: SharedStore = Arc::new(SqliteStore::open(path).unwrap());
+        assert_eq!(
+            store.pending_migrations().await.unwrap().len(),
+            SQLITE_MIGRATIONS.len()
+        );
+        store.run_migrations().await.unwrap();
+        assert!(store.pending_migrations().await.unwrap().is_empty());
+    }
+    rusqlite::Connection::open(path)
+        .unwrap()
+        .execute(
+            "UPDATE schema_migrations SET checksum = x'00' WHERE version = 1",
+            [],
+        )
+        .unwrap();
+    let store: SharedStore = Arc::new(SqliteStore::open(path).unwrap());
+    assert!(store.pending_migrations().await.is_err());
+    assert!(store.run_migrations().await.is_err());
+    drop(store);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_spectate_relayed_game() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((mut first_socket, first), (mut second_socket, second)) =
+        server.pair(&alice, &bob).await;
+
+    let (mut spectator, _) = server.connect().await;
+    // nothing is relayed yet, it might as well be played over WebRTC
+    send(
+        &mut spectator,
+        serde_json::json!({ "type": "list-live-matches" }),
+    )
+    .await;
+    match receive(&mut spectator).await {
+        ServerMessage::LiveMatches { matches } => assert!(matches.is_empty()),
+        other => panic!("Expected live-matches, got {other:?}"),
+    }
+
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let mut players = [
+        new_game(initiator, other, &first),
+        new_game(other, initiator, &second),
+    ];
+    let seed = first.seed;
+    relay_moves(
+        &mut players,
+        [&mut first_socket, &mut second_socket],
+        seed,
+        1,
+    )
+    .await;
+
+    send(
+        &mut spectator,
+        serde_json::json!({ "type": "list-live-matches" }),
+    )
+    .await;
+    let live = match receive(&mut spectator).await {
+        ServerMessage::LiveMatches { mut matches } => matches.pop().unwrap(),
+        other => panic!("Expected live-matches, got {other:?}"),
+    };
+    assert_eq!(live.initiator_key, first.public_key);
+    assert_eq!(live.moves, 1);
+    send(
+        &mut spectator,
+        serde_json::json!({ "type": "spectate", "match_id": live.match_id }),
+    )
+    .await;
+    relay_moves(
+        &mut players,
+        [&mut first_socket, &mut second_socket],
+        seed,
+        usize::MAX,
+    )
+    .await;
+
+    let mut boards = Vec::new();
+    loop {
+        match receive(&mut spectator).await {
+            ServerMessage::SpectatorBoard { board, .. } => boards.push(board),
+            ServerMessage::SpectatorEnded { .. } => break,
+            other => panic!("Expected spectator-board, got {other:?}"),
+        }
+    }
+    // the empty board was already out of the delay when spectating started
+    assert_eq!(boards.len(), players[0].history().len());
+    let last = serde_json::to_value(boards.last().unwrap()).unwrap();
+    let expected = serde_json::to_value(players[0].get_board_data()).unwrap();
+    assert_eq!(last["points"], expected["points"]);
+    assert_eq!(last["is_completed"], true);
+}
+
+#[tokio::test]
+async fn test_hidden_dice_relayed_game() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let rules = RuleSet {
+        hidden_dice: true,
+        ..Default::default()
+    };
+
+    // the dice come from the seed contributions, so committing is required
+    let (mut socket, verify_time) = server.connect().await;
+    send(
+        &mut socket,
+        serde_json::json!({
+            "type": "join",
+            "pub_key": alice.pub_key,
+            "signature": alice.sign(&verify_time),
+            "rules": rules,
+        }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::Error { name, .. } => assert_eq!(name, "BadRequest"),
+        other => panic!("Expected error, got {other:?}"),
+    }
+    drop(socket);
+
+    let ((mut first_socket, first), (mut second_socket, second)) =
+        server.pair_committed(&alice, &bob, rules).await;
+    assert_eq!(first.rules, rules);
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let players = play_relayed_game(
+        (initiator, &first, &mut first_socket),
+        (other, &second, &mut second_socket),
+    )
+    .await;
+    assert!(players[0].is_completed());
+
+    // the relay checked every link and saved the finished game
+    let mut saved = false;
+    for _ in 0..50 {
+        let leader_board: LeaderBoard = server.get("/leaderboard").await;
+        if leader_board
+            .entries
+            .iter()
+            .all(|entry| entry.total_games == 1)
+        {
+            saved = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(saved);
+}
+
+#[tokio::test]
+async fn test_rematch_swaps_initiator() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let ((mut first_socket, first), (mut second_socket, second)) =
+        server.pair(&alice, &bob).await;
+    let (initiator, other) = match first.public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    let body = play_game((initiator, &first), (other, &second));
+    let response = server.submit_game(&body).await;
+    assert!(response.status().is_success(), "{response:?}");
+
+    send(&mut first_socket, serde_json::json!({ "type": "rematch" })).await;
+    match receive(&mut second_socket).await {
+        ServerMessage::RematchRequested => {}
+        other => panic!("Expected rematch-requested, got {other:?}"),
+    }
+    send(&mut second_socket, serde_json::json!({ "type": "rematch" })).await;
+    let first_rematch = wait_for_pairing(&mut first_socket).await;
+    let second_rematch = wait_for_pairing(&mut second_socket).await;
+    assert!(!first_rematch.initiator);
+    assert!(second_rematch.initiator);
+    assert_ne!(first_rematch.seed, first.seed);
+
+    let body = play_game((other, &second_rematch), (initiator, &first_rematch));
+    let response = server.submit_game(&body).await;
+    assert!(response.status().is_success(), "{response:?}");
+
+    let matches: MatchList = server
+        .get(&format!("/matches?player={}", urlencode(&alice.pub_key)))
+        .await;
+    assert_eq!(matches.total, 2);
+    let mut series = Vec::new();
+    for entry in &matches.entries {
+        let details: MatchDetails =
+            server.get(&format!("/matches/{}", entry.match_id)).await;
+        series.push(details.series_id);
+    }
+    assert_eq!(series[0], series[1]);
+    assert!(matches
+        .entries
+        .iter()
+        .any(|entry| entry.match_id == series[0]));
+}
+
+#[tokio::test]
+async fn test_claim_timeout_in_relayed_game() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let rules = RuleSet {
+        turn_limit_secs: Some(5),
+        ..Default::default()
+    };
+    let mut sockets = Vec::new();
+    for player in [&alice, &bob] {
+        let (mut socket, verify_time) = server.connect().await;
+        send(
+            &mut socket,
+            serde_json::json!({
+                "type": "join",
+                "pub_key": player.pub_key,
+                "signature": player.sign(&verify_time),
+                "queue": "00000000-0000-0000-0000-00000000beef",
+                "rules": rules,
+            }),
+        )
+        .await;
+        sockets.push(socket);
+    }
+    let mut pairings = Vec::new();
+    for socket in &mut sockets {
+        pairings.push(wait_for_pairing(socket).await);
+    }
+    if !pairings[0].initiator {
+        sockets.swap(0, 1);
+        pairings.swap(0, 1);
+    }
+    let (initiator, other) = match pairings[0].public_key == alice.pub_key {
+        true => (&alice, &bob),
+        false => (&bob, &alice),
+    };
+    assert_eq!(pairings[0].rules, rules);
+
+    let mut game = Game::new(
+        Keys::Sign {
+            my_keys: initiator.signing_key.clone(),
+            other_keys: other.signing_key.verifying_key(),
+        },
+        rules,
+        ServerGameInfo::new(pairings[0].seed, true).with_time(pairings[0].time),
+    )
+    .unwrap();
+    let item = game.place(0).unwrap();
+    sockets[0]
+        .send(Message::Binary(bincode::serialize(&item).unwrap()))
+        .await
+        .unwrap();
+    receive_move(&mut sockets[1]).await;
+    assert!(game.claim_timeout().is_err());
+
+    // the other player lets their turn run out
+    tokio::time::sleep(Duration::from_millis(5_500)).await;
+    let claim = game.claim_timeout().unwrap();
+    sockets[0]
+        .send(Message::Binary(bincode::serialize(&claim).unwrap()))
+        .await
+        .unwrap();
+    assert!(receive_move(&mut sockets[1]).await.is_timeout_claim());
+
+    let path = format!("/matches?player={}", urlencode(&initiator.pub_key));
+    let mut matches: MatchList = server.get(&path).await;
+    for _ in 0..50 {
+        if matches.total > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        matches = server.get(&path).await;
+    }
+    assert_eq!(matches.total, 1);
+    assert_eq!(matches.entries[0].result, MatchResult::Win);
+    assert!(matches.entries[0].forfeit);
+
+    // without the relay the server can't tell the turn ran out
+    let response = server
+        .submit_game(&GameBody {
+            seed: pairings[0].seed,
+            time: pairings[0].time,
+            your_key: pairings[0].public_key.clone(),
+            opponent_key: pairings[0].partner_key.clone(),
+            starting: true,
+            rules,
+            signature: pairings[0].signature.clone(),
+            moves: game.history().to_vec(),
+            seed_reveal: None,
+        })
+        .await;
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+/// Connects and waits for the next game of the tournament
+async fn join_tournament(
+    server: &TestServer,
+    player: &TestPlayer,
+    tournament_id: &str,
+) -> Socket {
+    let (mut socket, verify_time) = server.connect().await;
+    send(
+        &mut socket,
+        serde_json::json!({
+            "type": "join",
+            "pub_key": player.pub_key,
+            "signature": player.sign(&verify_time),
+            "tournament": tournament_id,
+        }),
+    )
+    .await;
+    match receive(&mut socket).await {
+        ServerMessage::TournamentWaiting { .. } => socket,
+        other => panic!("Expected tournament-waiting, got {other:?}"),
+    }
+}
+
+/// Plays the games the server pairs both players for until their series is
+/// decided, returns the bracket afterwards
+async fn play_series(
+    server: &TestServer,
+    tournament_id: &str,
+    (first, first_socket): (&TestPlayer, &mut Socket),
+    (second, second_socket): (&TestPlayer, &mut Socket),
+) -> TournamentInfo {
+    let mut last_initiator: Option<String> = None;
+    loop {
+        let first_pairing = wait_for_pairing(first_socket).await;
+        let second_pairing = wait_for_pairing(second_socket).await;
+        let ((initiator, initiator_pairing), (other, other_pairing)) =
+            match first_pairing.initiator {
+                true => ((first, &first_pairing), (second, &second_pairing)),
+                false => ((second, &second_pairing), (first, &first_pairing)),
+            };
+        // the players take turns starting
+        assert_ne!(last_initiator.as_ref(), Some(&initiator.pub_key));
+        last_initiator = Some(initiator.pub_key.clone());
+
+        let body = play_game((initiator, initiator_pairing), (other, other_pairing));
+        let response = server.submit_game(&body).await;
+        assert!(response.status().is_success(), "{response:?}");
+
+        let info: TournamentInfo =
+            server.get(&format!("/tournaments/{tournament_id}")).await;
+        let series = info
+            .rounds
+            .iter()
+            .flatten()
+            .find(|series| {
+                let players = [series.player1.as_ref(), series.player2.as_ref()];
+                players.contains(&Some(&first.pub_key))
+                    && players.contains(&Some(&second.pub_key))
+            })
+            .filter(|series| series.winner.is_some());
+        if series.is_some() {
+            return info;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_single_elimination_tournament() {
+    let server = TestServer::start().await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+    let carol = server.signup().await;
+
+    let name = "Friday cup";
+    let tournament_id = Uuid::new_v4().to_string();
+    let new_tournament = NewTournament {
+        tournament_id: tournament_id.clone(),
+        name: name.to_owned(),
+        format: TournamentFormat::SingleElimination,
+        best_of: 3,
+        rules: RuleSet::default(),
+        organizer_key: alice.pub_key.clone(),
+        signature: alice.sign(&format!("create:{tournament_id}:{name}")),
+    };
+    let info: TournamentInfo = server.post("/tournaments", &new_tournament).await;
+    let id = info.tournament_id;
+    assert_eq!(id, tournament_id);
+    // the same signed request can not open a second tournament
+    let replayed = server
+        .http
+        .post(server.url("/tournaments"))
+        .json(&new_tournament)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(replayed.status(), reqwest::StatusCode::CONFLICT);
+    let tournaments: Vec<TournamentInfo> = server.get("/tournaments").await;
+    assert_eq!(tournaments.len(), 1);
+    for player in [&alice, &bob, &carol] {
+        let _: TournamentInfo = server
+            .post(
+                &format!("/tournaments/{id}/register"),
+                &TournamentAction {
+                    pub_key: player.pub_key.clone(),
+                    signature: player.sign(&format!("register:{id}")),
+                },
+            )
+            .await;
+    }
+    let info: TournamentInfo = server
+        .post(
+            &format!("/tournaments/{id}/start"),
+            &TournamentAction {
+                pub_key: alice.pub_key.clone(),
+                signature: alice.sign(&format!("start:{id}")),
+            },
+        )
+        .await;
+    assert_eq!(info.status, TournamentStatus::Running);
+    // the top seed gets the bye of the four player bracket
+    assert_eq!(info.rounds[0].len(), 2);
+    assert_eq!(info.rounds[0][0].winner.as_ref(), Some(&alice.pub_key));
+
+    let mut alice_socket = join_tournament(&server, &alice, &id).await;
+    let mut bob_socket = join_tournament(&server, &bob, &id).await;
+    let mut carol_socket = join_tournament(&server, &carol, &id).await;
+    let info = play_series(
+        &server,
+        &id,
+        (&bob, &mut bob_socket),
+        (&carol, &mut carol_socket),
+    )
+    .await;
+    let semi_final = &info.rounds[0][1];
+    let series_id = semi_final.series_id.clone().unwrap();
+    let finalist = match semi_final.winner.as_ref() == Some(&bob.pub_key) {
+        true => (&bob, &mut bob_socket),
+        false => (&carol, &mut carol_socket),
+    };
+    assert_eq!(info.rounds.len(), 2);
+
+    let info = play_series(&server, &id, (&alice, &mut alice_socket), finalist).await;
+    assert_eq!(info.status, TournamentStatus::Finished);
+    assert_eq!(info.winner, info.rounds[1][0].winner);
+    assert_eq!(info.standings[0].pub_key, info.winner.unwrap());
+
+    let matches: MatchList = server
+        .get(&format!(
+            "/matches?player={}&opponent={}",
+            urlencode(&bob.pub_key),
+            urlencode(&carol.pub_key)
+        ))
+        .await;
+    assert!(matches.total >= 2);
+    for entry in &matches.entries {
+        let details: MatchDetails =
+            server.get(&format!("/matches/{}", entry.match_id)).await;
+        assert_eq!(details.series_id, series_id);
+    }
+}
+
+#[tokio::test]
+async fn test_tournament_survives_abandoned_games_and_restarts() {
+    let state = test_state()
+        .await
+        .with_tournament_match_timeout(Duration::from_millis(500));
+    let store = state.store.clone();
+    let server = TestServer::serve(state).await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+
+    let name = "Sunday cup";
+    let tournament_id = Uuid::new_v4().to_string();
+    let info: TournamentInfo = server
+        .post(
+            "/tournaments",
+            &NewTournament {
+                tournament_id: tournament_id.clone(),
+                name: name.to_owned(),
+                format: TournamentFormat::RoundRobin,
+                best_of: 1,
+                rules: RuleSet::default(),
+                organizer_key: alice.pub_key.clone(),
+                signature: alice.sign(&format!("create:{tournament_id}:{name}")),
+            },
+        )
+        .await;
+    let id = info.tournament_id;
+    for player in [&alice, &bob] {
+        let _: TournamentInfo = server
+            .post(
+                &format!("/tournaments/{id}/register"),
+                &TournamentAction {
+                    pub_key: player.pub_key.clone(),
+                    signature: player.sign(&format!("register:{id}")),
+                },
+            )
+            .await;
+    }
+    let _: TournamentInfo = server
+        .post(
+            &format!("/tournaments/{id}/start"),
+            &TournamentAction {
+                pub_key: alice.pub_key.clone(),
+                signature: alice.sign(&format!("start:{id}")),
+            },
+        )
+        .await;
+
+    // both players leave their game without submitting it
+    let mut alice_socket = join_tournament(&server, &alice, &id).await;
+    let mut bob_socket = join_tournament(&server, &bob, &id).await;
+    wait_for_pairing(&mut alice_socket).await;
+    wait_for_pairing(&mut bob_socket).await;
+    let info: TournamentInfo = server.get(&format!("/tournaments/{id}")).await;
+    assert!(info.rounds[0][0].playing);
+    drop((alice_socket, bob_socket));
+
+    let path = format!("/tournaments/{id}");
+    let mut info: TournamentInfo = server.get(&path).await;
+    for _ in 0..50 {
+        if !info.rounds[0][0].playing {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        info = server.get(&path).await;
+    }
+    assert!(!info.rounds[0][0].playing);
+    assert!(info.rounds[0][0].winner.is_none());
+
+    // the bracket is read back from the store and played on
+    let restarted = state_on(store, None);
+    tournament::load(&restarted).await.unwrap();
+    let server = TestServer::serve(restarted).await;
+    let reloaded: TournamentInfo = server.get(&path).await;
+    assert_eq!(reloaded.status, TournamentStatus::Running);
+    assert_eq!(reloaded.players, info.players);
+    let mut alice_socket = join_tournament(&server, &alice, &id).await;
+    let mut bob_socket = join_tournament(&server, &bob, &id).await;
+    let info = play_series(
+        &server,
+        &id,
+        (&alice, &mut alice_socket),
+        (&bob, &mut bob_socket),
+    )
+    .await;
+    assert_eq!(info.status, TournamentStatus::Finished);
+}
+
+#[tokio::test]
+async fn test_abandoned_relayed_tournament_game_is_a_forfeit() {
+    let mut state = test_state().await;
+    state.reconnect_grace = Duration::from_millis(200);
+    let server = TestServer::serve(state).await;
+    let alice = server.signup().await;
+    let bob = server.signup().await;
+
+    let name = "Monday cup";
+    let tournament_id = Uuid::new_v4().to_string();
+    let info: TournamentInfo = server
+        .post(
+            "/tournaments",
+            &NewTournament {
+                tournament_id: tournament_id.clone(),
+                name: name.to_owned(),
+                format: TournamentFormat::RoundRobin,
+                best_of: 1,
+                rules: RuleSet::default(),
+                organizer_key: alice.pub_key.clone(),
+                signature: alice.sign(&format!("create:{tournament_id}:{name}")),
+            },
+        )
+        .await;
+    let id = info.tournament_id;
+    for player in [&alice, &bob] {
+        let _: TournamentInfo = server
+            .post(
+                &format!("/tournaments/{id}/register"),
+                &TournamentAction {
+                    pub_key: player.pub_key.clone(),
+                    signature: player.sign(&format!("register:{id}")),
+                },
+            )
+            .await;
+    }
+    let _: TournamentInfo = server
+        .post(
+            &format!("/tournaments/{id}/start"),
+            &TournamentAction {
+                pub_key: alice.pub_key.clone(),
+                signature: alice.sign(&format!("start:{id}")),
+            },
+        )
+        .await;
+
+    let mut alice_socket = join_tournament(&server, &alice, &id).await;
+    let mut bob_socket = join_tournament(&server, &bob, &id).await;
+    let alice_pairing = wait_for_pairing(&mut alice_socket).await;
+    let bob_pairing = wait_for_pairing(&mut bob_socket).await;
+    let mut players = [
+        new_game(&alice, &bob, &alice_pairing),
+        new_game(&bob, &alice, &bob_pairing),
+    ];
+    let seed = alice_pairing.seed;
+    match alice_pairing.initiator {
+        true => {
+            relay_moves(&mut players, [&mut alice_socket, &mut bob_socket], seed, 1).await
+        }
+        false => {
+            players.swap(0, 1);
+            relay_moves(&mut players, [&mut bob_socket, &mut alice_socket], seed, 1).await
+        }
+    }
+    // alice never comes back
+    drop(alice_socket);
+    match receive(&mut bob_socket).await {
+        ServerMessage::PartnerDisconnected { .. } => {}
+        other => panic!("Expected partner-disconnected, got {other:?}"),
+    }
+    match receive(&mut bob_socket).await {
+        ServerMessage::PartnerLeft => {}
+        other => panic!("Expected partner-left, got {other:?}"),
+    }
+
+    let info: TournamentInfo = server.get(&format!("/tournaments/{id}")).await;
+    assert_eq!(info.rounds[0][0].winner.as_ref(), Some(&bob.pub_key));
+    assert_eq!(info.status, TournamentStatus::Finished);
+    let matches: MatchList = server
+        .get(&format!("/matches?player={}", urlencode(&alice.pub_key)))
+        .await;
+    assert_eq!(matches.total, 1);
+    assert_eq!(matches.entries[0].result, MatchResult::Loss);
+    assert!(matches.entries[0].forfeit);
+}
+
+fn urlencode(value: &str) -> String {
+    value.replace('+', "%2B").replace('/', "%2F")
+}
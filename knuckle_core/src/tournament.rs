@@ -0,0 +1,698 @@
+//! Brackets of best-of-N series. Registered players that wait for their
+//! tournament on the websocket are paired by the server, results come in
+//! through `save_game`. Every change is written to the store so brackets
+//! survive a restart
+
+use std::{
+    collections::HashMap as StdHashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use lib_knuckle::{
+    api_interfaces::{
+        BracketSeries, NewTournament, TournamentFormat, TournamentInfo,
+        TournamentStanding, TournamentStatus,
+    },
+    protocol::ServerMessage,
+    rules::RuleSet,
+};
+use scc::HashMap;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{matchmaker::start_match, spectate, AppState, UserCreateError};
+
+/// How often waiting players are paired for their next game
+const TICK: Duration = Duration::from_secs(1);
+const MAX_BEST_OF: u8 = 9;
+const MAX_PLAYERS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Series {
+    players: [Option<String>; 2],
+    wins: [u8; 2],
+    winner: Option<usize>,
+    /// First match of the series, the later ones are stored with its id
+    series_id: Option<Uuid>,
+    /// Match being played, nil while it is being started
+    playing: Option<Uuid>,
+    /// When `playing` was started, in milliseconds since the epoch
+    playing_since: Option<u64>,
+    /// Games played including ties, the players take turns starting
+    games: u8,
+}
+
+impl Series {
+    fn new(players: [Option<String>; 2]) -> Self {
+        // byes go through without playing
+        let winner = match &players {
+            [Some(_), None] => Some(0),
+            [None, Some(_)] => Some(1),
+            _ => None,
+        };
+        Self {
+            players,
+            wins: [0; 2],
+            winner,
+            series_id: None,
+            playing: None,
+            playing_since: None,
+            games: 0,
+        }
+    }
+
+    fn winner_key(&self) -> Option<&String> {
+        self.winner.and_then(|index| self.players[index].as_ref())
+    }
+
+    fn info(&self) -> BracketSeries {
+        BracketSeries {
+            player1: self.players[0].clone(),
+            player2: self.players[1].clone(),
+            wins1: self.wins[0],
+            wins2: self.wins[1],
+            winner: self.winner_key().cloned(),
+            series_id: self.series_id.map(|id| id.to_string()),
+            playing: self.playing.is_some(),
+        }
+    }
+}
+
+/// Next game to start for a series whose players are both waiting
+struct Pending {
+    tournament_id: Uuid,
+    round: usize,
+    index: usize,
+    initiator: Uuid,
+    other: Uuid,
+    series_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tournament {
+    pub name: String,
+    pub format: TournamentFormat,
+    pub best_of: u8,
+    pub rules: RuleSet,
+    pub organizer_key: String,
+    /// Public keys in registration order, which is also the seeding
+    pub players: Vec<String>,
+    pub status: TournamentStatus,
+    rounds: Vec<Vec<Series>>,
+}
+
+/// Tournaments by their id
+pub type Tournaments = Arc<HashMap<Uuid, Tournament>>;
+
+impl Tournament {
+    fn wins_needed(&self) -> u8 {
+        self.best_of / 2 + 1
+    }
+
+    fn register(&mut self, pub_key: String) -> Result<(), UserCreateError> {
+        if self.status != TournamentStatus::Registering {
+            return Err(UserCreateError::BadRequest(
+                "Registration is closed".to_owned(),
+            ));
+        }
+        if self.players.contains(&pub_key) {
+            return Err(UserCreateError::BadRequest("Already registered".to_owned()));
+        }
+        if self.players.len() >= MAX_PLAYERS {
+            return Err(UserCreateError::BadRequest("Tournament is full".to_owned()));
+        }
+        self.players.push(pub_key);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), UserCreateError> {
+        if self.status != TournamentStatus::Registering {
+            return Err(UserCreateError::BadRequest("Already started".to_owned()));
+        }
+        if self.players.len() < 2 {
+            return Err(UserCreateError::BadRequest(
+                "Needs at least two players".to_owned(),
+            ));
+        }
+        self.rounds = match self.format {
+            TournamentFormat::SingleElimination => {
+                vec![first_bracket_round(&self.players)]
+            }
+            TournamentFormat::RoundRobin => round_robin(&self.players),
+        };
+        self.status = TournamentStatus::Running;
+        self.advance();
+        Ok(())
+    }
+
+    /// Round that is being played, the earlier ones are all decided
+    fn current_round(&self) -> Option<usize> {
+        self.rounds
+            .iter()
+            .position(|round| round.iter().any(|series| series.winner.is_none()))
+    }
+
+    /// Counts the result of a match of this tournament, ties are replayed.
+    /// Returns false when the match is not part of it
+    fn record(&mut self, match_id: Uuid, winner_key: Option<&str>) -> bool {
+        let wins_needed = self.wins_needed();
+        let Some(series) = self
+            .rounds
+            .iter_mut()
+            .flatten()
+            .find(|series| series.playing == Some(match_id))
+        else {
+            return false;
+        };
+        series.playing = None;
+        series.playing_since = None;
+        series.games += 1;
+        if let Some(index) = series
+            .players
+            .iter()
+            .position(|key| key.is_some() && key.as_deref() == winner_key)
+        {
+            series.wins[index] += 1;
+            if series.wins[index] >= wins_needed {
+                series.winner = Some(index);
+            }
+        }
+        self.advance();
+        true
+    }
+
+    /// Frees series whose match was started `timeout` ago and is not relayed
+    /// anymore, the game is played again. Returns whether any was freed
+    fn expire_stale(&mut self, now: u64, timeout: Duration, live: &[Uuid]) -> bool {
+        let mut expired = false;
+        for series in self.rounds.iter_mut().flatten() {
+            let stale = match (series.playing, series.playing_since) {
+                (Some(match_id), Some(since)) => {
+                    !live.contains(&match_id)
+                        && now.saturating_sub(since) >= timeout.as_millis() as u64
+                }
+                _ => false,
+            };
+            if stale {
+                series.playing = None;
+                series.playing_since = None;
+                expired = true;
+            }
+        }
+        expired
+    }
+
+    /// Adds the next single elimination round once the current one is
+    /// decided and finishes the tournament after the last one
+    fn advance(&mut self) {
+        while self.status == TournamentStatus::Running && self.current_round().is_none() {
+            let winners = self
+                .rounds
+                .last()
+                .map(|round| {
+                    round
+                        .iter()
+                        .map(|series| series.winner_key().cloned())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            match self.format {
+                TournamentFormat::SingleElimination if winners.len() > 1 => {
+                    self.rounds.push(
+                        winners
+                            .chunks(2)
+                            .map(|pair| Series::new([pair[0].clone(), pair[1].clone()]))
+                            .collect(),
+                    );
+                }
+                _ => self.status = TournamentStatus::Finished,
+            }
+        }
+    }
+
+    fn standings(&self) -> Vec<TournamentStanding> {
+        let mut standings = self
+            .players
+            .iter()
+            .map(|pub_key| TournamentStanding {
+                pub_key: pub_key.clone(),
+                series_wins: 0,
+                game_wins: 0,
+                game_losses: 0,
+            })
+            .collect::<Vec<_>>();
+        for series in self.rounds.iter().flatten() {
+            for (index, key) in series.players.iter().enumerate() {
+                let Some(standing) = standings
+                    .iter_mut()
+                    .find(|standing| Some(&standing.pub_key) == key.as_ref())
+                else {
+                    continue;
+                };
+                standing.game_wins += series.wins[index] as u32;
+                standing.game_losses += series.wins[1 - index] as u32;
+                // byes do not count as a win
+                if series.winner == Some(index) && series.players[1 - index].is_some() {
+                    standing.series_wins += 1;
+                }
+            }
+        }
+        // a stable sort keeps the seeding as the tie breaker
+        standings.sort_by_key(|standing| {
+            (
+                std::cmp::Reverse(standing.series_wins),
+                std::cmp::Reverse(
+                    standing.game_wins as i64 - standing.game_losses as i64,
+                ),
+            )
+        });
+        standings
+    }
+
+    fn winner(&self) -> Option<String> {
+        if self.status != TournamentStatus::Finished {
+            return None;
+        }
+        match self.format {
+            TournamentFormat::SingleElimination => self
+                .rounds
+                .last()
+                .and_then(|round| round.first())
+                .and_then(|series| series.winner_key().cloned()),
+            TournamentFormat::RoundRobin => self
+                .standings()
+                .into_iter()
+                .next()
+                .map(|standing| standing.pub_key),
+        }
+    }
+
+    pub fn info(&self, tournament_id: Uuid) -> TournamentInfo {
+        TournamentInfo {
+            tournament_id: tournament_id.to_string(),
+            name: self.name.clone(),
+            format: self.format,
+            best_of: self.best_of,
+            rules: self.rules,
+            status: self.status,
+            organizer_key: self.organizer_key.clone(),
+            players: self.players.clone(),
+            rounds: self
+                .rounds
+                .iter()
+                .map(|round| round.iter().map(Series::info).collect())
+                .collect(),
+            standings: self.standings(),
+            winner: self.winner(),
+        }
+    }
+}
+
+/// Seeds in bracket order so the best seeds only meet in the later rounds,
+/// `[0, 3, 1, 2]` for four players
+fn bracket_order(size: usize) -> Vec<usize> {
+    let mut order = vec![0];
+    while order.len() < size {
+        let len = order.len() * 2;
+        order = order
+            .iter()
+            .flat_map(|&seed| [seed, len - 1 - seed])
+            .collect();
+    }
+    order
+}
+
+/// The top seeds get the byes when the players do not fill the bracket
+fn first_bracket_round(players: &[String]) -> Vec<Series> {
+    bracket_order(players.len().next_power_of_two())
+        .chunks(2)
+        .map(|pair| {
+            Series::new([players.get(pair[0]).cloned(), players.get(pair[1]).cloned()])
+        })
+        .collect()
+}
+
+/// Every pairing once with the circle method, each player plays at most once
+/// per round
+fn round_robin(players: &[String]) -> Vec<Vec<Series>> {
+    let mut circle = players.iter().cloned().map(Some).collect::<Vec<_>>();
+    if circle.len() % 2 == 1 {
+        circle.push(None);
+    }
+    let len = circle.len();
+    let mut rounds = Vec::with_capacity(len - 1);
+    for _ in 0..len - 1 {
+        rounds.push(
+            (0..len / 2)
+                .map(|i| [circle[i].clone(), circle[len - 1 - i].clone()])
+                // sitting out a round is not a series
+                .filter(|pair| pair.iter().all(Option::is_some))
+                .map(Series::new)
+                .collect(),
+        );
+        circle[1..].rotate_right(1);
+    }
+    rounds
+}
+
+/// Opens registration for a tournament the organizer already signed
+pub async fn create(
+    state: &AppState,
+    tournament_id: Uuid,
+    body: NewTournament,
+) -> Result<TournamentInfo, UserCreateError> {
+    let name = body.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(UserCreateError::BadRequest(
+            "Name has to be 1 to 64 bytes".to_owned(),
+        ));
+    }
+    if body.best_of.is_multiple_of(2) || body.best_of > MAX_BEST_OF {
+        return Err(UserCreateError::BadRequest(format!(
+            "best_of has to be odd and at most {MAX_BEST_OF}"
+        )));
+    }
+    body.rules.validate()?;
+    let tournament = Tournament {
+        name: name.to_owned(),
+        format: body.format,
+        best_of: body.best_of,
+        rules: body.rules,
+        organizer_key: body.organizer_key,
+        players: Vec::new(),
+        status: TournamentStatus::Registering,
+        rounds: Vec::new(),
+    };
+    let info = tournament.info(tournament_id);
+    // a replayed request can not open the same tournament again
+    state
+        .tournaments
+        .insert_async(tournament_id, tournament)
+        .await
+        .map_err(|_| UserCreateError::TournamentAlreadyExists)?;
+    persist(state, tournament_id).await?;
+    Ok(info)
+}
+
+/// Writes the tournament to the store so it survives a restart
+async fn persist(state: &AppState, tournament_id: Uuid) -> Result<(), UserCreateError> {
+    let json = state
+        .tournaments
+        .read_async(&tournament_id, |_, tournament| {
+            serde_json::to_string(tournament)
+        })
+        .await
+        .ok_or(UserCreateError::TournamentNotFound)??;
+    state.store.save_tournament(tournament_id, json).await
+}
+
+/// Background tasks have nobody to report a failed write to
+async fn persist_or_log(state: &AppState, tournament_id: Uuid) {
+    if let Err(e) = persist(state, tournament_id).await {
+        tracing::error!("Failed storing tournament {tournament_id}: {e:?}");
+    }
+}
+
+/// Reads the stored tournaments back on startup, returns how many there were
+pub async fn load(state: &AppState) -> Result<usize, UserCreateError> {
+    let stored = state.store.tournaments().await?;
+    for (tournament_id, json) in &stored {
+        let mut tournament: Tournament = serde_json::from_str(json)?;
+        // matches that were still being started never reached the players
+        for series in tournament.rounds.iter_mut().flatten() {
+            if series.playing_since.is_none() {
+                series.playing = None;
+            }
+        }
+        state
+            .tournaments
+            .upsert_async(*tournament_id, tournament)
+            .await;
+    }
+    Ok(stored.len())
+}
+
+pub async fn info(
+    state: &AppState,
+    tournament_id: Uuid,
+) -> Result<TournamentInfo, UserCreateError> {
+    state
+        .tournaments
+        .read_async(&tournament_id, |_, tournament| {
+            tournament.info(tournament_id)
+        })
+        .await
+        .ok_or(UserCreateError::TournamentNotFound)
+}
+
+pub async fn list(state: &AppState) -> Vec<TournamentInfo> {
+    let mut tournaments = Vec::new();
+    state
+        .tournaments
+        .scan_async(|id, tournament| tournaments.push(tournament.info(*id)))
+        .await;
+    tournaments
+}
+
+/// Adds a player whose key was already verified
+pub async fn register(
+    state: &AppState,
+    tournament_id: Uuid,
+    pub_key: String,
+) -> Result<TournamentInfo, UserCreateError> {
+    let info = state
+        .tournaments
+        .update_async(&tournament_id, |_, tournament| {
+            tournament
+                .register(pub_key)
+                .map(|()| tournament.info(tournament_id))
+        })
+        .await
+        .ok_or(UserCreateError::TournamentNotFound)??;
+    persist(state, tournament_id).await?;
+    Ok(info)
+}
+
+/// Closes registration and seeds the first round, only the organizer can
+pub async fn start(
+    state: &AppState,
+    tournament_id: Uuid,
+    pub_key: &str,
+) -> Result<TournamentInfo, UserCreateError> {
+    let info = state
+        .tournaments
+        .update_async(&tournament_id, |_, tournament| {
+            if tournament.organizer_key != pub_key {
+                return Err(UserCreateError::NotTournamentOrganizer);
+            }
+            tournament.start()?;
+            Ok(tournament.info(tournament_id))
+        })
+        .await
+        .ok_or(UserCreateError::TournamentNotFound)??;
+    persist(state, tournament_id).await?;
+    Ok(info)
+}
+
+/// Marks a verified user as waiting for their next game in the tournament
+pub async fn wait(
+    state: &AppState,
+    tournament_id: Uuid,
+    user_id: Uuid,
+    pub_key: &str,
+) -> Result<(), UserCreateError> {
+    let (registered, rules) = state
+        .tournaments
+        .read_async(&tournament_id, |_, tournament| {
+            (
+                tournament.status != TournamentStatus::Finished
+                    && tournament.players.iter().any(|key| key == pub_key),
+                tournament.rules,
+            )
+        })
+        .await
+        .ok_or(UserCreateError::TournamentNotFound)?;
+    if !registered {
+        return Err(UserCreateError::BadRequest(
+            "Not registered for this tournament".to_owned(),
+        ));
+    }
+    state
+        .all_users
+        .update_async(&user_id, |_, user| {
+            user.set_rules(rules).set_tournament(tournament_id);
+        })
+        .await;
+    state.send_to(
+        user_id,
+        ServerMessage::TournamentWaiting {
+            tournament_id: tournament_id.to_string(),
+        },
+    )
+}
+
+/// Tournaments that are being played
+async fn running(state: &AppState) -> Vec<Uuid> {
+    let mut tournament_ids = Vec::new();
+    state
+        .tournaments
+        .scan_async(|id, tournament| {
+            if tournament.status == TournamentStatus::Running {
+                tournament_ids.push(*id);
+            }
+        })
+        .await;
+    tournament_ids
+}
+
+/// Counts a stored match towards its series, matches outside of tournaments
+/// are ignored
+pub async fn record_result(state: &AppState, match_id: Uuid, winner_key: Option<&str>) {
+    for tournament_id in running(state).await {
+        let recorded = state
+            .tournaments
+            .update_async(&tournament_id, |_, tournament| {
+                tournament.record(match_id, winner_key)
+            })
+            .await
+            .unwrap_or(false);
+        if recorded {
+            persist_or_log(state, tournament_id).await;
+            return;
+        }
+    }
+}
+
+/// Claims the series of the tournament whose players are both waiting, they
+/// count as playing until the match is started
+fn take_pending(
+    tournament_id: Uuid,
+    tournament: &mut Tournament,
+    waiting: &StdHashMap<(Uuid, String), Uuid>,
+) -> Vec<Pending> {
+    let Some(round) = tournament.current_round() else {
+        return Vec::new();
+    };
+    let mut pending = Vec::new();
+    for (index, series) in tournament.rounds[round].iter_mut().enumerate() {
+        if series.winner.is_some() || series.playing.is_some() {
+            continue;
+        }
+        let users = series
+            .players
+            .clone()
+            .map(|key| key.and_then(|key| waiting.get(&(tournament_id, key)).copied()));
+        if let [Some(first), Some(second)] = users {
+            let (initiator, other) = match series.games % 2 {
+                0 => (first, second),
+                _ => (second, first),
+            };
+            series.playing = Some(Uuid::nil());
+            pending.push(Pending {
+                tournament_id,
+                round,
+                index,
+                initiator,
+                other,
+                series_id: series.series_id,
+            });
+        }
+    }
+    pending
+}
+
+/// Matches the relay still has, those are never stale
+async fn live_match_ids(state: &AppState) -> Vec<Uuid> {
+    let mut match_ids = Vec::new();
+    for relay in spectate::relays(state).await {
+        let relay = relay.lock().await;
+        if !relay.is_completed() {
+            match_ids.push(relay.match_id());
+        }
+    }
+    match_ids
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Background task that starts the games of every running tournament and
+/// frees series whose game was abandoned
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        let tournament_ids = running(&state).await;
+        if tournament_ids.is_empty() {
+            continue;
+        }
+
+        let mut waiting = StdHashMap::new();
+        state
+            .all_users
+            .scan_async(|user_id, user| {
+                if let (Some(tournament_id), Some(pub_key), true) =
+                    (user.tournament, &user.pub_key, user.can_start())
+                {
+                    waiting.insert((tournament_id, pub_key.clone()), *user_id);
+                }
+            })
+            .await;
+
+        let live = live_match_ids(&state).await;
+        let now = now_millis();
+        let mut pending = Vec::new();
+        for tournament_id in tournament_ids {
+            let expired = state
+                .tournaments
+                .update_async(&tournament_id, |_, tournament| {
+                    let expired = tournament.expire_stale(
+                        now,
+                        state.tournament_match_timeout,
+                        &live,
+                    );
+                    pending.extend(take_pending(tournament_id, tournament, &waiting));
+                    expired
+                })
+                .await
+                .unwrap_or(false);
+            if expired {
+                persist_or_log(&state, tournament_id).await;
+            }
+        }
+        for pending in pending {
+            let started = start_match(
+                &state,
+                None,
+                pending.initiator,
+                pending.other,
+                pending.series_id,
+            )
+            .await;
+            let match_id = match started {
+                Ok(match_id) => match_id,
+                Err(e) => {
+                    tracing::error!("Failed starting tournament match: {e:?}");
+                    None
+                }
+            };
+            state
+                .tournaments
+                .update_async(&pending.tournament_id, |_, tournament| {
+                    let series = &mut tournament.rounds[pending.round][pending.index];
+                    series.playing = match_id;
+                    series.playing_since = match_id.map(|_| now_millis());
+                    if series.series_id.is_none() {
+                        series.series_id = match_id;
+                    }
+                })
+                .await;
+            persist_or_log(&state, pending.tournament_id).await;
+        }
+    }
+}
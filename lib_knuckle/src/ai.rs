@@ -0,0 +1,345 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::BoardData,
+    rules::{Cancellation, RuleSet},
+    utils::knucklebones_points::calculate_knucklebones_points,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum Strategy {
+    /// Any column that still has space
+    Random,
+    /// The column with the best point difference right after placing
+    Greedy,
+    /// Searches `depth` moves ahead, averaging over every possible roll. Deep
+    /// searches on big boards are cut short
+    Expectimax { depth: u8 },
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Expectimax { depth: 3 }
+    }
+}
+
+pub struct Ai {
+    strategy: Strategy,
+    rng: StdRng,
+}
+
+impl Ai {
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn with_seed(strategy: Strategy, seed: u64) -> Self {
+        Self {
+            strategy,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// Picks the column to place the next die in, `None` when it is not our
+    /// turn or the game is over
+    pub fn choose_column(&mut self, board: &BoardData) -> Option<u16> {
+        if !board.your_turn || board.is_completed {
+            return None;
+        }
+        let sim = Simulation {
+            me: board.decks.me.clone(),
+            other: board.decks.other.clone(),
+            rules: &board.rules,
+        };
+        let die = board.next_dice as u32;
+        let columns = sim.open_columns(true);
+
+        let scored = match self.strategy {
+            Strategy::Random => return columns.choose(&mut self.rng).map(|&x| x as u16),
+            Strategy::Greedy => columns
+                .iter()
+                .map(|&x| {
+                    let mut next = sim.clone();
+                    next.place(true, x, die);
+                    (x, next.evaluate())
+                })
+                .collect::<Vec<_>>(),
+            Strategy::Expectimax { depth } => {
+                let depth = search_depth(depth, &board.rules);
+                columns
+                    .iter()
+                    .map(|&x| {
+                        let mut next = sim.clone();
+                        next.place(true, x, die);
+                        (x, next.expectimax(depth.saturating_sub(1), false))
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let best = scored
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let best_columns = scored
+            .iter()
+            .filter(|(_, score)| (best - score).abs() < f64::EPSILON)
+            .map(|(x, _)| *x)
+            .collect::<Vec<_>>();
+        best_columns.choose(&mut self.rng).map(|&x| x as u16)
+    }
+}
+
+/// Positions an expectimax search may look at, deeper searches are cut short so
+/// callers can't ask for one that never finishes
+const MAX_LEAVES: u64 = 200_000;
+
+/// The requested depth, lowered until the search stays within [`MAX_LEAVES`]
+fn search_depth(requested: u8, rules: &RuleSet) -> u8 {
+    // every level after the first tries each face in each column
+    let branching = rules.die_faces as u64 * rules.columns as u64;
+    let mut depth = requested.min(1);
+    let mut leaves = rules.columns as u64;
+    while depth < requested && leaves * branching <= MAX_LEAVES {
+        leaves *= branching;
+        depth += 1;
+    }
+    depth
+}
+
+/// Bonus for finishing ahead, keeps the search from trading a won game for a
+/// few points
+const WIN_SCORE: f64 = 1000.0;
+
+#[derive(Clone)]
+struct Simulation<'a> {
+    me: Vec<u32>,
+    other: Vec<u32>,
+    rules: &'a RuleSet,
+}
+
+impl Simulation<'_> {
+    fn deck(&self, mine: bool) -> &[u32] {
+        if mine {
+            &self.me
+        } else {
+            &self.other
+        }
+    }
+
+    fn column(&self, x: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.rules.rows).map(move |row| row * self.rules.columns + x)
+    }
+
+    fn open_columns(&self, mine: bool) -> Vec<usize> {
+        let deck = self.deck(mine);
+        (0..self.rules.columns)
+            .filter(|&x| self.column(x).any(|idx| deck[idx] == 0))
+            .collect()
+    }
+
+    // Positions inside a column do not matter for points or for the game
+    // ending so there is no need to float the dice like the real game does
+    fn place(&mut self, mine: bool, x: usize, die: u32) {
+        let indexes = self.column(x).collect::<Vec<_>>();
+        let (deck, other) = if mine {
+            (&mut self.me, &mut self.other)
+        } else {
+            (&mut self.other, &mut self.me)
+        };
+        if let Some(&idx) = indexes.iter().find(|&&idx| deck[idx] == 0) {
+            deck[idx] = die;
+        }
+        if self.rules.cancellation == Cancellation::SameColumn {
+            for idx in indexes {
+                if other[idx] == die {
+                    other[idx] = 0;
+                }
+            }
+        }
+    }
+
+    fn is_completed(&self) -> bool {
+        self.me.iter().all(|c| *c != 0) || self.other.iter().all(|c| *c != 0)
+    }
+
+    fn points(&self, mine: bool) -> u32 {
+        calculate_knucklebones_points(
+            self.deck(mine),
+            self.rules.columns,
+            self.rules.die_faces,
+            self.rules.scoring,
+        )
+        .iter()
+        .sum()
+    }
+
+    fn evaluate(&self) -> f64 {
+        let diff = self.points(true) as f64 - self.points(false) as f64;
+        if self.is_completed() {
+            diff + WIN_SCORE * diff.signum()
+        } else {
+            diff
+        }
+    }
+
+    // Chance node, the next die is unknown so every face is equally likely
+    fn expectimax(&self, depth: u8, mine: bool) -> f64 {
+        if depth == 0 || self.is_completed() {
+            return self.evaluate();
+        }
+        let faces = self.rules.die_faces as u32;
+        let columns = self.open_columns(mine);
+        let total: f64 = (1..=faces)
+            .map(|die| {
+                let scores = columns.iter().map(|&x| {
+                    let mut next = self.clone();
+                    next.place(mine, x, die);
+                    next.expectimax(depth - 1, !mine)
+                });
+                if mine {
+                    scores.fold(f64::NEG_INFINITY, f64::max)
+                } else {
+                    scores.fold(f64::INFINITY, f64::min)
+                }
+            })
+            .sum();
+        total / faces as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::{
+        game::{Game, ServerGameInfo},
+        keys::Keys,
+    };
+
+    fn create_game(
+        my_keys: &SigningKey,
+        other_keys: &SigningKey,
+        starting: bool,
+    ) -> Game {
+        Game::new(
+            Keys::Sign {
+                my_keys: my_keys.clone(),
+                other_keys: other_keys.verifying_key(),
+            },
+            RuleSet::default(),
+            ServerGameInfo::new(0, starting),
+        )
+        .unwrap()
+    }
+
+    // Plays a full game between two strategies and returns the final board of
+    // the starting player
+    fn play(first: Strategy, second: Strategy, seed: u64) -> BoardData {
+        let mut csprng = OsRng;
+        let first_keys = SigningKey::generate(&mut csprng);
+        let second_keys = SigningKey::generate(&mut csprng);
+        let mut players = [
+            (
+                create_game(&first_keys, &second_keys, true),
+                Ai::with_seed(first, seed),
+            ),
+            (
+                create_game(&second_keys, &first_keys, false),
+                Ai::with_seed(second, seed),
+            ),
+        ];
+
+        let mut turn = 0;
+        while !players[0].0.get_board_data().is_completed {
+            let (game, ai) = &mut players[turn % 2];
+            let x = ai.choose_column(&game.get_board_data()).unwrap();
+            let item = game.place(x).unwrap();
+            players[(turn + 1) % 2].0.add_opponent_move(item).unwrap();
+            turn += 1;
+        }
+        players[0].0.get_board_data()
+    }
+
+    #[test]
+    fn test_not_your_turn() {
+        let mut csprng = OsRng;
+        let my_keys = SigningKey::generate(&mut csprng);
+        let other_keys = SigningKey::generate(&mut csprng);
+        let game = create_game(&my_keys, &other_keys, false);
+        let mut ai = Ai::with_seed(Strategy::Greedy, 0);
+        assert_eq!(ai.choose_column(&game.get_board_data()), None);
+    }
+
+    #[test]
+    fn test_full_games() {
+        for strategy in [
+            Strategy::Random,
+            Strategy::Greedy,
+            Strategy::Expectimax { depth: 2 },
+        ] {
+            let board = play(strategy, Strategy::Random, 1);
+            assert!(board.is_completed);
+        }
+    }
+
+    #[test]
+    fn test_greedy_cancels() {
+        let mut csprng = OsRng;
+        let my_keys = SigningKey::generate(&mut csprng);
+        let other_keys = SigningKey::generate(&mut csprng);
+        let mut board = create_game(&my_keys, &other_keys, true).get_board_data();
+        #[rustfmt::skip]
+        let other = vec![
+            0, 6, 0,
+            0, 6, 0,
+            0, 0, 0];
+        board.decks.other = other;
+        board.next_dice = 6;
+        for seed in 0..10 {
+            let mut ai = Ai::with_seed(Strategy::Greedy, seed);
+            assert_eq!(ai.choose_column(&board), Some(1));
+        }
+    }
+
+    #[test]
+    fn test_search_depth() {
+        let rules = RuleSet::default();
+        assert_eq!(search_depth(0, &rules), 0);
+        assert_eq!(search_depth(3, &rules), 3);
+        assert_eq!(search_depth(u8::MAX, &rules), 4);
+        let big = RuleSet {
+            rows: RuleSet::MAX_SIDE,
+            columns: RuleSet::MAX_SIDE,
+            die_faces: RuleSet::MAX_DIE_FACES,
+            ..rules
+        };
+        assert_eq!(search_depth(u8::MAX, &big), 2);
+
+        let mut csprng = OsRng;
+        let my_keys = SigningKey::generate(&mut csprng);
+        let other_keys = SigningKey::generate(&mut csprng);
+        let board = create_game(&my_keys, &other_keys, true).get_board_data();
+        let mut ai = Ai::with_seed(Strategy::Expectimax { depth: u8::MAX }, 0);
+        assert!(ai.choose_column(&board).is_some());
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 // TODO: possibly split up crate into game and utils for interop
-use crate::game::HistoryItem;
+use crate::{game::HistoryItem, rules::RuleSet, seed::SeedReveal};
 
 #[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(
@@ -22,8 +22,14 @@ pub struct GameBody {
     pub opponent_key: String,
     // decides wether his key will go first in check
     pub starting: bool,
+    // required for signature, older clients only played the default rules
+    #[serde(default)]
+    pub rules: RuleSet,
     pub signature: String,
     pub moves: Vec<HistoryItem>,
+    // required for signature when the seed came from a commit-reveal
+    #[serde(default)]
+    pub seed_reveal: Option<SeedReveal>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -36,7 +42,11 @@ pub struct GameBody {
     tsify(into_wasm_abi, from_wasm_abi)
 )]
 pub struct LeaderBoard {
+    // every ranked player, also those without games in the window, ignoring
+    // pagination
     pub total: u32,
+    pub page: u32,
+    pub limit: u32,
     pub entries: Vec<LeaderBoardEntry>,
 }
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -49,12 +59,58 @@ pub struct LeaderBoard {
     tsify(into_wasm_abi, from_wasm_abi)
 )]
 pub struct LeaderBoardEntry {
+    pub rank: u32,
     pub name: String,
     pub total_points: u32,
     pub total_games: u32,
     pub total_wins: u32,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum LeaderBoardWindow {
+    Daily,
+    Weekly,
+    Monthly,
+    #[default]
+    AllTime,
+}
+
+impl LeaderBoardWindow {
+    /// Number of days counted, today included, `None` for all time
+    pub fn days(&self) -> Option<u64> {
+        match self {
+            LeaderBoardWindow::Daily => Some(1),
+            LeaderBoardWindow::Weekly => Some(7),
+            LeaderBoardWindow::Monthly => Some(30),
+            LeaderBoardWindow::AllTime => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct LeaderBoardRank {
+    pub total: u32,
+    // none for unknown players and bots
+    pub entry: Option<LeaderBoardEntry>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[cfg_attr(
     any(test, target_arch = "wasm32", feature = "wasm"),
@@ -69,3 +125,349 @@ pub struct UserUpdate {
     pub pub_key: String,
     pub signature: String,
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum MatchResult {
+    Win,
+    Loss,
+    Tie,
+}
+
+impl MatchResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchResult::Win => "win",
+            MatchResult::Loss => "loss",
+            MatchResult::Tie => "tie",
+        }
+    }
+}
+
+/// One finished match from the point of view of the requested player
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct MatchSummary {
+    pub match_id: String,
+    pub opponent_name: String,
+    pub opponent_key: String,
+    pub result: MatchResult,
+    pub forfeit: bool,
+    pub points: u32,
+    pub opponent_points: u32,
+    pub bot_game: bool,
+    // unix millis
+    pub started_at: u64,
+    // unix millis
+    pub completed_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct MatchList {
+    // matches that pass the filters, ignoring pagination
+    pub total: u32,
+    pub entries: Vec<MatchSummary>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct MatchPlayer {
+    pub name: String,
+    pub public_key: String,
+    pub points: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct MatchMove {
+    pub seq: u32,
+    // public key of the player that placed the die
+    pub player: String,
+    pub x: u16,
+    pub number: u8,
+    // unix millis
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct MatchDetails {
+    pub match_id: String,
+    pub player1: MatchPlayer,
+    pub player2: MatchPlayer,
+    // public key of the winner, none on a tie
+    pub winner: Option<String>,
+    // "win", "tie" or "forfeit"
+    pub result: String,
+    pub bot_game: bool,
+    pub rules: RuleSet,
+    pub started_at: u64,
+    pub completed_at: u64,
+    // id of the first match, rematches and tournament series share it
+    pub series_id: String,
+    pub moves: Vec<MatchMove>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct RatingLeaderBoard {
+    pub total: u32,
+    pub entries: Vec<RatingLeaderBoardEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct RatingLeaderBoardEntry {
+    pub name: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub rated_games: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct LobbyInfo {
+    pub code: String,
+    pub host_key: String,
+    pub has_password: bool,
+    pub rules: RuleSet,
+    pub guest_joined: bool,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct IceServers {
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct LiveMatch {
+    pub match_id: String,
+    // boards sent to spectators are from this players point of view
+    pub initiator_key: String,
+    pub other_key: String,
+    pub rules: RuleSet,
+    pub moves: u32,
+    // the server only sees the moves of relayed games
+    pub relayed: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum TournamentFormat {
+    // losers are out, the bracket is filled up with byes
+    SingleElimination,
+    // everyone plays everyone once
+    RoundRobin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum TournamentStatus {
+    Registering,
+    Running,
+    Finished,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct NewTournament {
+    // picked by the organizer, a signature can only create it once
+    pub tournament_id: String,
+    pub name: String,
+    pub format: TournamentFormat,
+    // odd number of games, a series is won with more than half of them
+    pub best_of: u8,
+    #[serde(default)]
+    pub rules: RuleSet,
+    pub organizer_key: String,
+    // signature of `create:<tournament_id>:<name>`
+    pub signature: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct TournamentAction {
+    pub pub_key: String,
+    // signature of "register:<tournament_id>" or "start:<tournament_id>"
+    pub signature: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct BracketSeries {
+    // none is a bye
+    pub player1: Option<String>,
+    pub player2: Option<String>,
+    pub wins1: u8,
+    pub wins2: u8,
+    pub winner: Option<String>,
+    // shared by the matches of the series, none before the first one started
+    pub series_id: Option<String>,
+    pub playing: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct TournamentStanding {
+    pub pub_key: String,
+    pub series_wins: u32,
+    pub game_wins: u32,
+    pub game_losses: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct TournamentInfo {
+    pub tournament_id: String,
+    pub name: String,
+    pub format: TournamentFormat,
+    pub best_of: u8,
+    pub rules: RuleSet,
+    pub status: TournamentStatus,
+    pub organizer_key: String,
+    // in registration order, which is also the seeding
+    pub players: Vec<String>,
+    // series of every round, single elimination rounds are added as the
+    // previous one finishes
+    pub rounds: Vec<Vec<BracketSeries>>,
+    // best first
+    pub standings: Vec<TournamentStanding>,
+    pub winner: Option<String>,
+}
@@ -1,20 +1,34 @@
-use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use rand::{
+    rngs::{OsRng, StdRng},
+    RngCore, SeedableRng,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{error::GameError, seed::decode_contribution};
 
 pub struct Dice {
     next_dice: u8,
+    faces: u8,
     rng: StdRng,
 }
 
 impl Dice {
-    pub fn new(seed: u64) -> Self {
+    pub fn new(seed: u64, faces: u8) -> Self {
         let mut rng = StdRng::seed_from_u64(seed);
-        let next_dice = (rng.next_u32() % 6) as u8 + 1;
-        Dice { next_dice, rng }
+        let next_dice = (rng.next_u32() % faces as u32) as u8 + 1;
+        Dice {
+            next_dice,
+            faces,
+            rng,
+        }
     }
 
     pub fn roll(&mut self) -> usize {
         let num = self.next_dice;
-        self.next_dice = (self.rng.next_u32() % 6) as u8 + 1;
+        self.next_dice = (self.rng.next_u32() % self.faces as u32) as u8 + 1;
         num as usize
     }
     pub fn peek(&self) -> usize {
@@ -27,13 +41,100 @@ impl Dice {
     }
 }
 
+/// Hash chain a player reveals one link of with every move of a hidden dice
+/// game. Each link hashes to the one before it, the first one is the anchor the
+/// player uses as their seed contribution
+#[derive(Clone)]
+pub struct DiceChain {
+    links: Vec<[u8; 32]>,
+}
+
+impl DiceChain {
+    /// Links after the anchor, more moves than any board takes
+    pub const LEN: usize = 1024;
+
+    pub fn new() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self::from_bytes(secret)
+    }
+
+    fn from_bytes(secret: [u8; 32]) -> Self {
+        let mut links = Vec::with_capacity(Self::LEN + 1);
+        links.push(secret);
+        for _ in 0..Self::LEN {
+            links.push(Sha256::digest(links[links.len() - 1]).into());
+        }
+        links.reverse();
+        Self { links }
+    }
+
+    /// Rebuilds the chain from [`DiceChain::secret`], e.g. after a reconnect
+    pub fn from_secret(secret: &str) -> Result<Self, GameError> {
+        let bytes = decode_contribution(secret)?;
+        Ok(Self::from_bytes(bytes.try_into().expect("checked length")))
+    }
+
+    /// The last link, everything else is derived from it
+    pub fn secret(&self) -> String {
+        STANDARD_NO_PAD.encode(self.links[Self::LEN])
+    }
+
+    /// The seed contribution, only its commitment is sent when joining
+    pub fn anchor(&self) -> String {
+        STANDARD_NO_PAD.encode(self.links[0])
+    }
+
+    /// Link revealed with the `n`th move, counting from 1
+    pub(crate) fn link(&self, n: usize) -> Option<&[u8; 32]> {
+        self.links.get(n).filter(|_| n > 0)
+    }
+}
+
+impl fmt::Debug for DiceChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiceChain")
+            .field("anchor", &self.anchor())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for DiceChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `link` is the next link of the chain that revealed `previous` last
+pub(crate) fn follows(link: &[u8], previous: &[u8]) -> bool {
+    Sha256::digest(link).as_slice() == previous
+}
+
+/// Die of a hidden dice move. It depends on the signature of the move before,
+/// which the mover can not influence, and on the link the mover reveals, which
+/// the opponent can not know in advance
+pub(crate) fn hidden_roll(
+    seed: u64,
+    previous_signature: &[u8],
+    link: &[u8],
+    faces: u8,
+) -> u8 {
+    let mut hasher = Sha256::new();
+    hasher.update(b"knuckle-hidden-die");
+    hasher.update(seed.to_le_bytes());
+    hasher.update(previous_signature);
+    hasher.update(link);
+    let hash = hasher.finalize();
+    (u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]) % faces as u32) as u8 + 1
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_dice() {
-        let mut dice = Dice::new(0);
+        let mut dice = Dice::new(0, 6);
         dice.set_next(1);
         assert_eq!(dice.peek(), 1);
         assert_eq!(dice.roll(), 1);
@@ -41,4 +142,31 @@ mod tests {
         assert_eq!(dice.peek(), 2);
         assert_eq!(dice.roll(), 2);
     }
+
+    #[test]
+    fn test_dice_chain() {
+        let chain = DiceChain::new();
+        let anchor = STANDARD_NO_PAD.decode(chain.anchor()).unwrap();
+        assert!(chain.link(0).is_none());
+        assert!(follows(chain.link(1).unwrap(), &anchor));
+        assert!(follows(chain.link(2).unwrap(), chain.link(1).unwrap()));
+        assert!(!follows(chain.link(3).unwrap(), chain.link(1).unwrap()));
+        assert!(chain.link(DiceChain::LEN + 1).is_none());
+
+        let restored = DiceChain::from_secret(&chain.secret()).unwrap();
+        assert_eq!(restored.anchor(), chain.anchor());
+    }
+
+    #[test]
+    fn test_dice_faces() {
+        let mut dice = Dice::new(0, 8);
+        for _ in 0..100 {
+            assert!((1..=8).contains(&dice.roll()));
+        }
+        let chain = DiceChain::new();
+        for n in 1..=100 {
+            let roll = hidden_roll(0, &[n as u8], chain.link(n).unwrap(), 8);
+            assert!((1..=8).contains(&roll));
+        }
+    }
 }
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum GameError {
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Malformed signature, expected 64 bytes, got {len}")]
+    MalformedSignature { len: usize },
+    #[error("Cannot sign moves without a signing key")]
+    MissingSigningKey,
+    #[error("Invalid sequence, expected {expected}, got {got}")]
+    OutOfOrderSeq { expected: u32, got: u32 },
+    #[error("Invalid time, expected more than {last}, got {got}")]
+    TimeWentBackwards { last: u64, got: u64 },
+    #[error("Move is dated {got}, ahead of the clock at {now}")]
+    MoveFromFuture { now: u64, got: u64 },
+    #[error("Move took {took}ms, the turn limit is {limit}ms")]
+    TurnTimeExceeded { limit: u64, took: u64 },
+    #[error("Opponent still has time, waited {waited}ms of {limit}ms")]
+    TimeoutNotReached { limit: u64, waited: u64 },
+    #[error("Collision deck at {x},{y} already has a {value}, seq {seq}")]
+    ColumnFull {
+        x: usize,
+        y: usize,
+        value: u32,
+        seq: u32,
+    },
+    #[error("Column {x} is out of range, the board has {columns} columns")]
+    ColumnOutOfRange { x: usize, columns: usize },
+    #[error("Game is already completed")]
+    GameCompleted,
+    #[error("Invalid rule set: {reason}")]
+    InvalidRuleSet { reason: String },
+    #[error("Malformed public key")]
+    MalformedKey,
+    #[error("Unsupported match record version {version}")]
+    UnsupportedRecordVersion { version: u16 },
+    #[error("Malformed seed contribution, expected 32 bytes, got {len}")]
+    MalformedContribution { len: usize },
+    #[error("Seed reveal does not match the commitments")]
+    SeedRevealMismatch,
+    #[error("Hidden dice need the seed reveal and a dice chain")]
+    MissingDiceChain,
+    #[error("Dice chain link does not follow the previous one")]
+    InvalidDiceLink,
+    #[error("Could not decode move: {reason}")]
+    Decode { reason: String },
+}
+
+#[derive(Error, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum ProtocolError {
+    #[error("Unknown message type {kind}")]
+    UnknownMessage { kind: String },
+    #[error("Malformed message: {reason}")]
+    MalformedMessage { reason: String },
+    #[error("Unsupported protocol version {version}, need at least {min}")]
+    UnsupportedVersion { version: u32, min: u32 },
+}
@@ -5,12 +5,21 @@ use uuid::Uuid;
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::{
-    dice::Dice,
+    dice::{follows, hidden_roll, Dice, DiceChain},
+    error::GameError,
     keys::Keys,
+    rules::{Cancellation, RuleSet},
+    seed::{decode_contribution, SeedReveal},
     shift_columns::{shift_column_values, FloatDirection},
     utils::{knucklebones_points::calculate_knucklebones_points, now_impl::now},
 };
 
+/// How far the clocks of both players and the server may be apart, timed games
+/// reject moves dated further ahead of the local clock
+pub const CLOCK_SKEW_MS: u64 = 5_000;
+/// Column of a timeout claim, one below the forfeit
+const TIMEOUT_CLAIM: u16 = u16::MAX - 1;
+
 #[cfg_attr(any(test, target_arch = "wasm32", feature = "wasm"), wasm_bindgen)]
 pub struct Game {
     history: Vec<HistoryItem>,
@@ -19,9 +28,19 @@ pub struct Game {
     seq: u32,
     dice: Dice,
     deck_size: (usize, usize),
+    rules: RuleSet,
     info: ServerGameInfo,
     verify: bool,
     keys: Keys,
+    /// Time of the previous move, or of the match start when known
+    last_time: u64,
+    /// Own dice chain, needed to place in hidden dice games
+    chain: Option<DiceChain>,
+    /// Last link the first and the second player revealed, starting with their
+    /// anchors, only known in hidden dice games
+    links: Option<[Vec<u8>; 2]>,
+    /// Links the first and the second player revealed so far
+    revealed: [usize; 2],
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -34,12 +53,22 @@ pub struct HistoryForSql {
 }
 
 impl Game {
-    pub fn new(keys: Keys, deck_size: (usize, usize), info: ServerGameInfo) -> Self {
+    pub fn new(
+        keys: Keys,
+        rules: RuleSet,
+        info: ServerGameInfo,
+    ) -> Result<Self, GameError> {
+        rules.validate()?;
+        let deck_size = rules.deck_size();
         let deck = Self::create_deck(deck_size);
         let other_deck = Self::create_deck(deck_size);
-        let dice = Dice::new(info.seed);
+        let dice = Dice::new(info.seed, rules.die_faces);
 
-        Game {
+        Ok(Game {
+            last_time: info.started_at,
+            chain: None,
+            links: info.anchors.clone(),
+            revealed: [0, 0],
             history: Vec::new(),
             deck,
             other_deck,
@@ -47,42 +76,36 @@ impl Game {
             dice,
             keys,
             deck_size,
+            rules,
             info,
             verify: true,
-        }
+        })
     }
 
     pub fn validate_entire_game(
         keys: Keys,
         uuids: (Uuid, Uuid),
-        deck_size: (usize, usize),
+        rules: RuleSet,
         info: ServerGameInfo,
         history: Vec<HistoryItem>,
-    ) -> Result<(BoardData, Vec<HistoryForSql>), String> {
-        let mut game = Game::new(keys, deck_size, info);
+    ) -> Result<(BoardData, Vec<HistoryForSql>), GameError> {
+        let mut game = Game::new(keys, rules, info)?;
         let mut seq = 0;
-        let last_time = 0;
         let mut sql_history = Vec::new();
         for item in history {
-            if last_time > item.now {
-                return Err(format!(
-                    "Invalid time, expected more than {}, got {}",
-                    last_time, item.now
-                ));
-            }
             seq += 1;
             if seq != item.seq {
-                return Err(format!(
-                    "Invalid sequence, expected {}, got {}",
-                    seq, item.seq
-                ));
+                return Err(GameError::OutOfOrderSeq {
+                    expected: seq,
+                    got: item.seq,
+                });
             }
 
             sql_history.push(HistoryForSql {
                 seq: item.seq,
                 now: item.now,
                 x: item.x,
-                number: game.dice.peek() as u8,
+                number: game.upcoming_roll(item.link.as_deref()),
                 player: if !game.my_turn() { uuids.0 } else { uuids.1 },
             });
 
@@ -90,11 +113,13 @@ impl Game {
         }
         let data = game.get_board_data();
         if data.winner.win_by_forfeit {
+            // forfeits are signed by the loser, timeout claims by the winner
+            let signed_by_winner = game.history.last().unwrap().is_timeout_claim();
             let last_item = sql_history.last_mut().unwrap();
-            if data.winner.winner {
-                last_item.player = uuids.1;
-            } else {
+            if data.winner.winner == signed_by_winner {
                 last_item.player = uuids.0;
+            } else {
+                last_item.player = uuids.1;
             }
         }
         Ok((game.get_board_data(), sql_history))
@@ -104,6 +129,69 @@ impl Game {
         self.verify = false;
     }
 
+    /// Hidden dice games need the chain whose anchor was this players seed
+    /// contribution
+    pub fn set_dice_chain(&mut self, chain: DiceChain) {
+        self.chain = Some(chain);
+    }
+
+    /// For games created before the seed was revealed, same as
+    /// [`ServerGameInfo::with_reveal`]
+    pub fn set_seed_reveal(&mut self, reveal: &SeedReveal) {
+        self.info.anchors = anchors(reveal);
+        self.links = self.info.anchors.clone();
+    }
+
+    /// Index of the player making move `seq`, 0 for the one that starts
+    fn mover(seq: u32) -> usize {
+        ((seq + 1) % 2) as usize
+    }
+
+    /// Own link for the next placement
+    fn my_link(&self) -> Option<&[u8]> {
+        let me = !self.info.starting as usize;
+        let chain = self.chain.as_ref()?;
+        chain
+            .link(self.revealed[me] + 1)
+            .map(|link| link.as_slice())
+    }
+
+    /// Die the next move rolls. With hidden dice it needs the link the mover
+    /// reveals, so only the player on turn knows it in advance
+    fn upcoming_roll(&self, link: Option<&[u8]>) -> u8 {
+        if !self.rules.hidden_dice {
+            return self.dice.peek() as u8;
+        }
+        let previous = self
+            .history
+            .last()
+            .map(|item| item.signature.as_slice())
+            .unwrap_or_default();
+        link.map_or(0, |link| {
+            hidden_roll(self.info.seed, previous, link, self.rules.die_faces)
+        })
+    }
+
+    /// Checks the link revealed with the current move against the last one of
+    /// the same player and rolls the die from it
+    fn reveal_roll(&mut self, item: &HistoryItem) -> Result<u8, GameError> {
+        let mover = Self::mover(self.seq);
+        let link = item.link.as_deref().ok_or(GameError::InvalidDiceLink)?;
+        let links = self.links.as_mut().ok_or(GameError::MissingDiceChain)?;
+        if !follows(link, &links[mover]) {
+            return Err(GameError::InvalidDiceLink);
+        }
+        // the current move is already in the history
+        let previous = match self.history.len().checked_sub(2) {
+            Some(index) => self.history[index].signature.as_slice(),
+            None => &[],
+        };
+        let roll = hidden_roll(self.info.seed, previous, link, self.rules.die_faces);
+        links[mover] = link.to_vec();
+        self.revealed[mover] += 1;
+        Ok(roll)
+    }
+
     pub(crate) fn encode_history_item(item: &HistoryItem) -> Vec<u8> {
         format!("{}:{}:{}", item.seq, item.now, item.x).into_bytes()
     }
@@ -112,56 +200,105 @@ impl Game {
         vec![0; desk_size.0 * desk_size.1]
     }
 
-    pub fn add_opponent_move(&mut self, data: HistoryItem) -> Result<(), String> {
+    /// Rejected moves leave the game as it was
+    pub fn add_opponent_move(&mut self, data: HistoryItem) -> Result<(), GameError> {
         self.seq += 1;
         self.history.push(data.clone());
-        self.play_move(data)
+        self.play_move(data).inspect_err(|_| {
+            self.seq -= 1;
+            self.history.pop();
+        })
     }
 
-    pub fn place(&mut self, x: u16) -> Result<HistoryItem, String> {
+    pub fn place(&mut self, x: u16) -> Result<HistoryItem, GameError> {
         let signed_item = self.create_history_for_placing(x)?;
-
-        self.seq += 1;
-        self.history.push(signed_item.clone());
-        self.play_move(signed_item.clone())?;
+        self.add_opponent_move(signed_item.clone())?;
         Ok(signed_item)
     }
 
-    pub fn test_place(&mut self, x: u16) -> Result<(), String> {
+    pub fn test_place(&mut self, x: u16) -> Result<(), GameError> {
         let signed_item = self.create_history_for_placing(x)?;
         self.seq += 1;
-        let result = self.validate_move(&signed_item);
+        let result = self
+            .check_time(&signed_item)
+            .and_then(|_| self.validate_move(&signed_item));
         self.seq -= 1;
         result.map(|_| ())
     }
 
-    fn create_history_for_placing(&mut self, x: u16) -> Result<HistoryItem, String> {
+    fn create_history_for_placing(&mut self, x: u16) -> Result<HistoryItem, GameError> {
         let now = now();
+        let link = match self.rules.hidden_dice && x < TIMEOUT_CLAIM {
+            true => Some(self.my_link().ok_or(GameError::MissingDiceChain)?.to_vec()),
+            false => None,
+        };
 
         let data = HistoryItem {
             seq: self.seq + 1,
             now,
             x,
             signature: vec![],
+            link,
         };
 
         let to_sign = Game::encode_history_item(&data);
-        let key = self.keys.my_sign().unwrap();
+        let key = self.keys.my_sign().ok_or(GameError::MissingSigningKey)?;
         let signature = key.sign(&to_sign);
         let mut signed_item = data.clone();
         signed_item.signature = signature.to_bytes().to_vec();
         Ok(signed_item)
     }
 
+    /// Time left for the player on turn, `None` in untimed games
+    pub fn time_left(&self) -> Option<u64> {
+        let limit = self.rules.turn_limit_ms()?;
+        Some(limit.saturating_sub(now().saturating_sub(self.last_time)))
+    }
+
+    /// Timestamps only go forward, give or take the clock skew between both
+    /// players. In timed games every move has to be made within the limit and
+    /// a timeout can only be claimed after it
+    fn check_time(&self, item: &HistoryItem) -> Result<(), GameError> {
+        if item.now + CLOCK_SKEW_MS < self.last_time {
+            return Err(GameError::TimeWentBackwards {
+                last: self.last_time,
+                got: item.now,
+            });
+        }
+        let Some(limit) = self.rules.turn_limit_ms() else {
+            return Ok(());
+        };
+        let local = now();
+        if item.now > local + CLOCK_SKEW_MS {
+            return Err(GameError::MoveFromFuture {
+                now: local,
+                got: item.now,
+            });
+        }
+        let took = item.now.saturating_sub(self.last_time);
+        if item.is_timeout_claim() {
+            // nothing to measure from without the match start
+            if self.last_time == 0 || took <= limit {
+                return Err(GameError::TimeoutNotReached {
+                    limit,
+                    waited: took,
+                });
+            }
+        } else if !item.is_forfeit() && self.last_time != 0 && took > limit {
+            return Err(GameError::TurnTimeExceeded { limit, took });
+        }
+        Ok(())
+    }
+
     fn my_turn(&self) -> bool {
         let player = self.seq % 2;
         let me_first = self.info.starting;
         (me_first && player == 1) || (!me_first && player == 0)
     }
 
-    fn validate_move(&self, item: &HistoryItem) -> Result<(usize, usize), String> {
+    fn validate_move(&self, item: &HistoryItem) -> Result<(usize, usize), GameError> {
         if self.is_completed() {
-            return Err("Game is already completed".to_string());
+            return Err(GameError::GameCompleted);
         }
 
         let (public_key, deck) = if self.my_turn() {
@@ -172,16 +309,21 @@ impl Game {
 
         if self.verify {
             let to_verify = Game::encode_history_item(item);
-            let signature =
-                Signature::from_bytes(&item.signature.clone().try_into().unwrap());
+            let signature = item.signature()?;
 
             public_key
                 .verify(&to_verify, &signature)
-                .map_err(|_| "Invalid signature".to_string())?;
+                .map_err(|_| GameError::InvalidSignature)?;
         }
 
         let mut item_y = 0;
         let item_x = item.x as usize;
+        if item_x >= self.deck_size.1 {
+            return Err(GameError::ColumnOutOfRange {
+                x: item_x,
+                columns: self.deck_size.1,
+            });
+        }
         for i in 0..self.deck_size.0 {
             if deck[item_x + i * self.deck_size.1] == 0 {
                 item_y = i;
@@ -191,25 +333,36 @@ impl Game {
 
         let pos = item_x + item_y * self.deck_size.1;
         if deck[pos] != 0 {
-            return Err(format!(
-                "Collision deck at {},{} already has a {}, seq {}",
-                item_x, item_y, deck[pos], self.seq
-            ));
+            return Err(GameError::ColumnFull {
+                x: item_x,
+                y: item_y,
+                value: deck[pos],
+                seq: self.seq,
+            });
         }
 
         Ok((item_x, pos))
     }
-    pub fn forfeit(&mut self) -> HistoryItem {
-        let signed_item = self.create_history_for_placing(u16::MAX).unwrap();
+    /// Ends a timed game the opponent did not move in, the signed and dated
+    /// claim proves it to the server
+    pub fn claim_timeout(&mut self) -> Result<HistoryItem, GameError> {
+        let signed_item = self.create_history_for_placing(TIMEOUT_CLAIM)?;
+        self.add_opponent_move(signed_item.clone())?;
+        Ok(signed_item)
+    }
+
+    pub fn forfeit(&mut self) -> Result<HistoryItem, GameError> {
+        if self.is_completed() {
+            return Err(GameError::GameCompleted);
+        }
+        let signed_item = self.create_history_for_placing(u16::MAX)?;
         self.seq += 1;
         self.history.push(signed_item.clone());
-        signed_item
+        Ok(signed_item)
     }
 
-    fn is_valid_signature(&self, item: &HistoryItem) -> Result<(), String> {
-        let signature = item.signature.clone();
-        let signature = signature.try_into().unwrap();
-        let signature = Signature::from_bytes(&signature);
+    fn is_valid_signature(&self, item: &HistoryItem) -> Result<(), GameError> {
+        let signature = item.signature()?;
         let key = self.keys.my_verify();
         let encoded = Self::encode_history_item(item);
         match key.verify_strict(&encoded, &signature) {
@@ -217,20 +370,48 @@ impl Game {
             Err(_) => {
                 match self.keys.other_verify().verify_strict(&encoded, &signature) {
                     Ok(_) => Ok(()),
-                    Err(_) => Err("Invalid signature".to_string()),
+                    Err(_) => Err(GameError::InvalidSignature),
                 }
             }
         }
     }
 
-    fn play_move(&mut self, item: HistoryItem) -> Result<(), String> {
+    fn play_move(&mut self, item: HistoryItem) -> Result<(), GameError> {
+        self.check_time(&item)?;
         if item.is_forfeit() {
             if self.verify {
                 self.is_valid_signature(&item)?
             }
+            self.last_time = item.now;
+            return Ok(());
+        }
+        if item.is_timeout_claim() {
+            let previous = self.history.len().checked_sub(2).map(|i| &self.history[i]);
+            if self.decks_full()
+                || previous
+                    .is_some_and(|last| last.is_forfeit() || last.is_timeout_claim())
+            {
+                return Err(GameError::GameCompleted);
+            }
+            // only the player waiting for the move can claim it
+            let waiting = match self.my_turn() {
+                true => self.keys.other_verify(),
+                false => self.keys.my_verify(),
+            };
+            if self.verify {
+                waiting
+                    .verify_strict(&Self::encode_history_item(&item), &item.signature()?)
+                    .map_err(|_| GameError::InvalidSignature)?;
+            }
+            self.last_time = item.now;
             return Ok(());
         }
         let (item_x, pos) = self.validate_move(&item)?;
+        let num = match self.rules.hidden_dice {
+            true => self.reveal_roll(&item)? as u32,
+            false => self.dice.roll() as u32,
+        };
+        self.last_time = item.now;
 
         let (deck, other_deck) = if self.my_turn() {
             (&mut self.deck, &mut self.other_deck)
@@ -238,15 +419,16 @@ impl Game {
             (&mut self.other_deck, &mut self.deck)
         };
 
-        let num = self.dice.roll() as u32;
         deck[pos] = num;
 
-        let width = self.deck_size.1;
-        let col_idx = item_x;
-        for row_idx in 0..other_deck.len() / width {
-            let idx = row_idx * width + col_idx;
-            if other_deck[idx] == num {
-                other_deck[idx] = 0;
+        if self.rules.cancellation == Cancellation::SameColumn {
+            let width = self.deck_size.1;
+            let col_idx = item_x;
+            for row_idx in 0..other_deck.len() / width {
+                let idx = row_idx * width + col_idx;
+                if other_deck[idx] == num {
+                    other_deck[idx] = 0;
+                }
             }
         }
 
@@ -255,25 +437,41 @@ impl Game {
         Ok(())
     }
 
-    fn is_completed(&self) -> bool {
+    fn calculate_points(&self, deck: &[u32]) -> Vec<u32> {
+        calculate_knucklebones_points(
+            deck,
+            self.deck_size.1,
+            self.rules.die_faces,
+            self.rules.scoring,
+        )
+    }
+
+    pub fn is_completed(&self) -> bool {
         match self.history.last() {
-            Some(item) if item.is_forfeit() => return true,
+            Some(item) if item.is_forfeit() || item.is_timeout_claim() => return true,
             _ => {}
         }
+        self.decks_full()
+    }
+
+    fn decks_full(&self) -> bool {
         self.deck.iter().all(|c| *c != 0) || self.other_deck.iter().all(|c| *c != 0)
     }
 
+    pub fn history(&self) -> &[HistoryItem] {
+        &self.history
+    }
+
     pub fn get_board_data(&self) -> BoardData {
         let player = self.seq % 2;
         let me_first = self.info.starting;
-        let me_points = calculate_knucklebones_points(&self.deck, self.deck_size.0);
-        let other_points =
-            calculate_knucklebones_points(&self.other_deck, self.deck_size.0);
+        let me_points = self.calculate_points(&self.deck);
+        let other_points = self.calculate_points(&self.other_deck);
         let your_turn = !((me_first && player == 1) || (!me_first && player == 0));
         BoardData {
             points: Points {
-                me: calculate_knucklebones_points(&self.deck, self.deck_size.0),
-                other: calculate_knucklebones_points(&self.other_deck, self.deck_size.0),
+                me: me_points.clone(),
+                other: other_points.clone(),
             },
             decks: Decks {
                 me: self.deck.clone(),
@@ -282,21 +480,21 @@ impl Game {
             history: self.history.clone(),
             seq: self.seq,
             deck_size: self.deck_size,
-            next_dice: self.dice.peek() as u8,
+            rules: self.rules,
+            next_dice: self.upcoming_roll(self.my_link().filter(|_| your_turn)),
             your_turn,
             is_completed: self.is_completed(),
             winner: match self.history.last() {
-                Some(item) if item.is_forfeit() => {
+                // a timeout counts as a forfeit of the player that ran out
+                Some(item) if item.is_forfeit() || item.is_timeout_claim() => {
                     let to_verify = Game::encode_history_item(item);
-                    let signature = Signature::from_bytes(
-                        &item.signature.clone().try_into().unwrap(),
-                    );
-
-                    let is_from_me = self.keys.my_verify().verify(&to_verify, &signature);
+                    let is_from_me = item.signature().is_ok_and(|signature| {
+                        self.keys.my_verify().verify(&to_verify, &signature).is_ok()
+                    });
                     GameEnd {
                         win_by_tie: false,
                         win_by_forfeit: true,
-                        winner: is_from_me.is_err(),
+                        winner: is_from_me == item.is_timeout_claim(),
                     }
                 }
                 _ => match (me_points, other_points) {
@@ -347,16 +545,23 @@ pub struct GameEnd {
 )]
 pub struct BoardData {
     pub points: Points,
-    decks: Decks,
+    pub(crate) decks: Decks,
     history: Vec<HistoryItem>,
     seq: u32,
     deck_size: (usize, usize),
-    next_dice: u8,
-    your_turn: bool,
-    is_completed: bool,
+    pub(crate) rules: RuleSet,
+    pub(crate) next_dice: u8,
+    pub(crate) your_turn: bool,
+    pub(crate) is_completed: bool,
     pub winner: GameEnd,
 }
 
+impl BoardData {
+    pub fn is_completed(&self) -> bool {
+        self.is_completed
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[cfg_attr(
     any(test, target_arch = "wasm32", feature = "wasm"),
@@ -381,8 +586,8 @@ pub struct Points {
     tsify(into_wasm_abi, from_wasm_abi)
 )]
 pub struct Decks {
-    me: Vec<u32>,
-    other: Vec<u32>,
+    pub(crate) me: Vec<u32>,
+    pub(crate) other: Vec<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -399,32 +604,85 @@ pub struct HistoryItem {
     now: u64,
     x: u16,
     pub(crate) signature: Vec<u8>,
+    /// Dice chain link revealed with a placement in hidden dice games, not
+    /// signed since it has to hash to the previous link anyway
+    #[serde(default)]
+    pub(crate) link: Option<Vec<u8>>,
 }
 
 impl HistoryItem {
     pub fn is_forfeit(&self) -> bool {
         self.x == u16::MAX
     }
+
+    pub fn is_timeout_claim(&self) -> bool {
+        self.x == TIMEOUT_CLAIM
+    }
+
+    /// Unix millis the move was signed at
+    pub fn time(&self) -> u64 {
+        self.now
+    }
+
+    pub(crate) fn signature(&self) -> Result<Signature, GameError> {
+        let bytes: [u8; 64] = self.signature.as_slice().try_into().map_err(|_| {
+            GameError::MalformedSignature {
+                len: self.signature.len(),
+            }
+        })?;
+        Ok(Signature::from_bytes(&bytes))
+    }
 }
 
 #[derive(Debug)]
 pub struct ServerGameInfo {
     pub(crate) seed: u64,
     pub(crate) starting: bool,
+    /// Unix millis the match was signed at, 0 when unknown
+    pub(crate) started_at: u64,
+    /// Dice chain anchors of the first and the second player
+    pub(crate) anchors: Option<[Vec<u8>; 2]>,
 }
 
 impl ServerGameInfo {
     pub fn new(seed: u64, starting: bool) -> Self {
-        Self { seed, starting }
+        Self {
+            seed,
+            starting,
+            started_at: 0,
+            anchors: None,
+        }
+    }
+
+    /// The first move of a timed game is measured from the signed match
+    /// time, in seconds like `GameBody::time`
+    pub fn with_time(mut self, time: u64) -> Self {
+        self.started_at = time * 1000;
+        self
+    }
+
+    /// Hidden dice games start the dice chains at the revealed seed
+    /// contributions
+    pub fn with_reveal(mut self, reveal: Option<&SeedReveal>) -> Self {
+        self.anchors = reveal.and_then(anchors);
+        self
     }
 }
 
+fn anchors(reveal: &SeedReveal) -> Option<[Vec<u8>; 2]> {
+    Some([
+        decode_contribution(&reveal.first).ok()?,
+        decode_contribution(&reveal.second).ok()?,
+    ])
+}
+
 #[cfg(test)]
 mod tests {
     use ed25519_dalek::SigningKey;
     use rand_core::OsRng;
 
     use super::*;
+    use crate::rules::Scoring;
 
     impl Game {
         fn mock_move(&mut self, number: u8, x: u16) {
@@ -434,6 +692,7 @@ mod tests {
                 now: 0,
                 x,
                 signature: vec![],
+                link: None,
             })
             .unwrap();
             self.seq += 1;
@@ -445,6 +704,7 @@ mod tests {
                 now: 0,
                 x,
                 signature: vec![],
+                link: None,
             })
             .unwrap();
             self.seq += 1;
@@ -483,18 +743,15 @@ mod tests {
         let mut csprng = OsRng;
         let my_keys = SigningKey::generate(&mut csprng);
         let other_keys = SigningKey::generate(&mut csprng);
-        let deck_size = (3, 3);
         Game::new(
             Keys::Sign {
                 my_keys,
                 other_keys: other_keys.verifying_key(),
             },
-            deck_size,
-            ServerGameInfo {
-                seed,
-                starting: true,
-            },
+            RuleSet::default(),
+            ServerGameInfo::new(seed, true),
         )
+        .unwrap()
     }
 
     #[test]
@@ -514,19 +771,16 @@ mod tests {
         let mut csprng = OsRng;
         let my_keys = SigningKey::generate(&mut csprng);
         let other_keys = SigningKey::generate(&mut csprng);
-        let deck_size = (3, 3);
-        let info = ServerGameInfo {
-            seed: 0,
-            starting: true,
-        };
+        let info = ServerGameInfo::new(0, true);
         let mut game = Game::new(
             Keys::Sign {
                 my_keys: my_keys.clone(),
                 other_keys: other_keys.verifying_key(),
             },
-            deck_size,
+            RuleSet::default(),
             info,
-        );
+        )
+        .unwrap();
         let info = game.get_board_data();
         assert_eq!(info.next_dice, 2);
         assert_eq!(info.deck_size, (3, 3));
@@ -534,19 +788,16 @@ mod tests {
         assert_eq!(info.history.len(), 0);
         let mv = game.place(2).unwrap();
         let item = {
-            let deck_size = (3, 3);
-            let info = ServerGameInfo {
-                seed: 0,
-                starting: false,
-            };
+            let info = ServerGameInfo::new(0, false);
             let mut game = Game::new(
                 Keys::Sign {
                     my_keys: other_keys,
                     other_keys: my_keys.verifying_key(),
                 },
-                deck_size,
+                RuleSet::default(),
                 info,
-            );
+            )
+            .unwrap();
             game.add_opponent_move(mv).unwrap();
             game.place(1).unwrap()
         };
@@ -689,12 +940,13 @@ mod tests {
     fn test_forfeit() {
         let mut game = create_test_game(0);
         game.disable_verify();
-        let item = game.forfeit();
+        let item = game.forfeit().unwrap();
         assert_eq!(game.history.len(), 1);
         assert!(game.history[0].is_forfeit());
         // assert!(!game.get_board_data().winner);
         assert!(game.get_board_data().is_completed);
         assert!(!game.get_board_data().your_turn);
+        assert_eq!(game.forfeit().err(), Some(GameError::GameCompleted));
         let mut game2 = create_test_game(0);
         game2.info.starting = false;
         game2.disable_verify();
@@ -702,4 +954,277 @@ mod tests {
         // assert!(game2.get_board_data().winner);
         assert!(game2.get_board_data().is_completed);
     }
+
+    #[test]
+    fn test_custom_rules() {
+        let mut game = create_test_game(0);
+        game.rules = RuleSet {
+            rows: 4,
+            columns: 4,
+            die_faces: 8,
+            scoring: Scoring::Sum,
+            cancellation: Cancellation::None,
+            turn_limit_secs: None,
+            hidden_dice: false,
+        };
+        game.deck_size = game.rules.deck_size();
+        game.deck = Game::create_deck(game.deck_size);
+        game.other_deck = Game::create_deck(game.deck_size);
+        game.disable_verify();
+
+        game.mock_move(8, 3);
+        game.mock_move(8, 3);
+        let info = game.get_board_data();
+        // nothing got cancelled
+        assert_eq!(info.decks.me[15], 8);
+        assert_eq!(info.decks.other[3], 8);
+        assert_eq!(info.points.me, vec![0, 0, 0, 8]);
+        assert_eq!(info.points.other, vec![0, 0, 0, 8]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_rules() {
+        let mut csprng = OsRng;
+        let keys = SigningKey::generate(&mut csprng);
+        let game = Game::new(
+            Keys::Sign {
+                my_keys: keys.clone(),
+                other_keys: keys.verifying_key(),
+            },
+            RuleSet {
+                die_faces: 0,
+                ..RuleSet::default()
+            },
+            ServerGameInfo::new(0, true),
+        );
+        assert!(matches!(game, Err(GameError::InvalidRuleSet { .. })));
+    }
+
+    #[test]
+    fn test_turn_limit() {
+        let mut game = create_test_game(0);
+        game.rules.turn_limit_secs = Some(5);
+        game.disable_verify();
+        let start = now() - 20_000;
+        game.last_time = start;
+        let item = |seq, now, x| HistoryItem {
+            seq,
+            now,
+            x,
+            signature: vec![],
+            link: None,
+        };
+
+        game.seq += 1;
+        assert_eq!(game.play_move(item(1, start + 4_000, 0)), Ok(()));
+        game.seq += 1;
+        assert_eq!(
+            game.play_move(item(2, start + 10_000, 0)),
+            Err(GameError::TurnTimeExceeded {
+                limit: 5_000,
+                took: 6_000
+            })
+        );
+        assert_eq!(
+            game.play_move(item(2, start + 8_000, TIMEOUT_CLAIM)),
+            Err(GameError::TimeoutNotReached {
+                limit: 5_000,
+                waited: 4_000
+            })
+        );
+        assert!(matches!(
+            game.play_move(item(2, now() + 2 * CLOCK_SKEW_MS, 0)),
+            Err(GameError::MoveFromFuture { .. })
+        ));
+        assert_eq!(
+            game.play_move(item(2, start - CLOCK_SKEW_MS, 0)),
+            Err(GameError::TimeWentBackwards {
+                last: start + 4_000,
+                got: start - CLOCK_SKEW_MS
+            })
+        );
+        assert_eq!(game.play_move(item(2, start + 9_000, 1)), Ok(()));
+    }
+
+    #[test]
+    fn test_hidden_dice() {
+        let keys = [
+            SigningKey::generate(&mut OsRng),
+            SigningKey::generate(&mut OsRng),
+        ];
+        let chains = [DiceChain::new(), DiceChain::new()];
+        let reveal = SeedReveal {
+            server: crate::seed::new_contribution(),
+            first: chains[0].anchor(),
+            second: chains[1].anchor(),
+        };
+        let rules = RuleSet {
+            hidden_dice: true,
+            ..Default::default()
+        };
+        let seed = reveal.seed().unwrap();
+        let info =
+            |starting| ServerGameInfo::new(seed, starting).with_reveal(Some(&reveal));
+        let verify_only = || Keys::VerifyOnly {
+            my_keys: keys[0].verifying_key(),
+            other_keys: keys[1].verifying_key(),
+        };
+        let mut players = [0, 1].map(|i| {
+            let mut game = Game::new(
+                Keys::Sign {
+                    my_keys: keys[i].clone(),
+                    other_keys: keys[1 - i].verifying_key(),
+                },
+                rules,
+                info(i == 0),
+            )
+            .unwrap();
+            game.set_dice_chain(chains[i].clone());
+            game
+        });
+
+        // only the player on turn knows the next roll
+        assert_ne!(players[0].get_board_data().next_dice, 0);
+        assert_eq!(players[1].get_board_data().next_dice, 0);
+
+        let first = players[0].place(0).unwrap();
+        let mut observer = Game::new(verify_only(), rules, info(true)).unwrap();
+        let skipped = HistoryItem {
+            link: chains[0].link(2).map(|link| link.to_vec()),
+            ..first.clone()
+        };
+        assert_eq!(
+            observer.add_opponent_move(skipped),
+            Err(GameError::InvalidDiceLink)
+        );
+        players[1].add_opponent_move(first).unwrap();
+        assert_eq!(players[0].get_board_data().next_dice, 0);
+
+        let mut ai = crate::ai::Ai::with_seed(crate::ai::Strategy::Random, 7);
+        let mut rolls = 1;
+        let mut expected = Vec::new();
+        let mut turn = 1;
+        while !players[0].is_completed() {
+            let board = players[turn].get_board_data();
+            expected.push(board.next_dice);
+            let item = players[turn]
+                .place(ai.choose_column(&board).unwrap())
+                .unwrap();
+            players[1 - turn].add_opponent_move(item).unwrap();
+            turn = 1 - turn;
+            rolls += 1;
+        }
+
+        let (board, sql_history) = Game::validate_entire_game(
+            verify_only(),
+            (Uuid::nil(), Uuid::new_v4()),
+            rules,
+            info(true),
+            players[0].history().to_vec(),
+        )
+        .unwrap();
+        assert_eq!(sql_history.len(), rolls);
+        let numbers = sql_history[1..].iter().map(|item| item.number);
+        assert!(numbers.eq(expected));
+        assert_eq!(board.points.me, players[0].get_board_data().points.me);
+
+        // without the reveal the links can not be checked
+        assert_eq!(
+            Game::validate_entire_game(
+                verify_only(),
+                (Uuid::nil(), Uuid::new_v4()),
+                rules,
+                ServerGameInfo::new(seed, true),
+                players[0].history().to_vec(),
+            )
+            .map(|_| ()),
+            Err(GameError::MissingDiceChain)
+        );
+    }
+
+    #[test]
+    fn test_claim_timeout() {
+        let mut csprng = OsRng;
+        let my_keys = SigningKey::generate(&mut csprng);
+        let other_keys = SigningKey::generate(&mut csprng);
+        let rules = RuleSet {
+            turn_limit_secs: Some(5),
+            ..Default::default()
+        };
+        let started = now() / 1000;
+        let mut game = Game::new(
+            Keys::Sign {
+                my_keys: my_keys.clone(),
+                other_keys: other_keys.verifying_key(),
+            },
+            rules,
+            ServerGameInfo::new(0, true).with_time(started),
+        )
+        .unwrap();
+        let mut other = Game::new(
+            Keys::Sign {
+                my_keys: other_keys,
+                other_keys: my_keys.verifying_key(),
+            },
+            rules,
+            ServerGameInfo::new(0, false).with_time(started),
+        )
+        .unwrap();
+        other.add_opponent_move(game.place(0).unwrap()).unwrap();
+        assert!(matches!(
+            game.claim_timeout(),
+            Err(GameError::TimeoutNotReached { .. })
+        ));
+        assert_eq!(game.history().len(), 1);
+
+        game.last_time -= 10_000;
+        other.last_time -= 10_000;
+        // the player on turn can not claim their own timeout
+        assert_eq!(
+            other.claim_timeout().map(|_| ()),
+            Err(GameError::InvalidSignature)
+        );
+        assert_eq!(other.history().len(), 1);
+        let claim = game.claim_timeout().unwrap();
+        assert!(game.is_completed());
+        assert!(game.get_board_data().winner.winner);
+        other.add_opponent_move(claim).unwrap();
+        let end = other.get_board_data().winner;
+        assert!(end.win_by_forfeit);
+        assert!(!end.winner);
+        assert_eq!(other.test_place(1), Err(GameError::GameCompleted));
+    }
+
+    #[test]
+    fn test_move_errors() {
+        let mut game = create_test_game(0);
+        assert_eq!(
+            game.test_place(3),
+            Err(GameError::ColumnOutOfRange { x: 3, columns: 3 })
+        );
+
+        let mut item = game.place(0).unwrap();
+        item.signature.pop();
+        let mut game2 = create_test_game(0);
+        game2.info.starting = false;
+        assert_eq!(
+            game2.add_opponent_move(item),
+            Err(GameError::MalformedSignature { len: 63 })
+        );
+
+        let mut game3 = create_test_game(0);
+        game3.info.starting = false;
+        let item = game3.forfeit().unwrap();
+        let mut game4 = create_test_game(0);
+        game4.info.starting = false;
+        assert_eq!(
+            game4.add_opponent_move(item),
+            Err(GameError::InvalidSignature)
+        );
+        // rejected moves are not kept
+        assert_eq!(game4.get_board_data().seq, 0);
+        assert!(game4.history().is_empty());
+        assert!(game4.place(3).is_err());
+        assert!(game4.history().is_empty());
+    }
 }
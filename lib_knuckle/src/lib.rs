@@ -6,11 +6,18 @@ mod wasm;
 #[cfg(any(test, target_arch = "wasm32", feature = "wasm"))]
 pub use wasm::*;
 
+pub mod ai;
+pub mod error;
 pub mod game;
 mod utils;
 
-mod dice;
+pub mod dice;
 pub mod keys;
+pub mod protocol;
+pub mod rating;
+pub mod record;
+pub mod rules;
+pub mod seed;
 
 pub use utils::signing_helpers::*;
 
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    api_interfaces::{IceServers, LiveMatch, LobbyInfo},
+    error::ProtocolError,
+    game::{BoardData, HistoryItem},
+    rules::RuleSet,
+    seed::{SeedCommitments, SeedReveal},
+};
+
+/// Websocket protocol version this build speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest version the server still talks to, clients that never say hello are
+/// treated as this one
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The version both sides use after a client said hello with `version`
+pub fn negotiate(version: u32) -> Result<u32, ProtocolError> {
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(ProtocolError::UnsupportedVersion {
+            version,
+            min: MIN_PROTOCOL_VERSION,
+        });
+    }
+    Ok(version.min(PROTOCOL_VERSION))
+}
+
+/// Messages clients send to the server as json text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum ClientMessage {
+    #[serde(rename = "hello")]
+    Hello { version: u32 },
+    #[serde(rename = "join")]
+    Join {
+        pub_key: String,
+        // signature of the verify time
+        signature: String,
+        // the public queue when unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        queue: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tournament: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        lobby: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rules: Option<RuleSet>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commitment: Option<String>,
+    },
+    #[serde(rename = "create-lobby")]
+    CreateLobby {
+        pub_key: String,
+        signature: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rules: Option<RuleSet>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commitment: Option<String>,
+    },
+    #[serde(rename = "start-lobby")]
+    StartLobby,
+    #[serde(rename = "commit")]
+    Commit { commitment: String },
+    #[serde(rename = "reveal")]
+    Reveal { contribution: String },
+    #[serde(rename = "rematch")]
+    Rematch {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commitment: Option<String>,
+    },
+    #[serde(rename = "decline-rematch")]
+    DeclineRematch,
+    #[serde(rename = "list-live-matches")]
+    ListLiveMatches,
+    #[serde(rename = "spectate")]
+    Spectate { match_id: String },
+    #[serde(rename = "stop-spectating")]
+    StopSpectating,
+    #[serde(rename = "reconnect")]
+    Reconnect { pub_key: String, signature: String },
+    // WebRTC signalling as simple-peer emits it, the server forwards these
+    // to the partner as is
+    #[serde(rename = "ice-candidate")]
+    IceCandidate { candidate: IceCandidate },
+    #[serde(rename = "offer")]
+    Offer { sdp: String },
+    #[serde(rename = "answer")]
+    Answer { sdp: String },
+    #[serde(rename = "candidate")]
+    Candidate { candidate: IceCandidate },
+    /// WebRTC failed, moves go through the server from now on
+    #[serde(rename = "relay")]
+    Relay,
+}
+
+impl ClientMessage {
+    /// Parses a text frame, telling unknown message types apart from known
+    /// ones with missing or broken fields
+    pub fn parse(text: &str) -> Result<Self, ProtocolError> {
+        let malformed = |e: serde_json::Error| ProtocolError::MalformedMessage {
+            reason: e.to_string(),
+        };
+        let value: Value = serde_json::from_str(text).map_err(malformed)?;
+        let kind =
+            value["type"]
+                .as_str()
+                .ok_or_else(|| ProtocolError::MalformedMessage {
+                    reason: "Missing message type".to_owned(),
+                })?;
+        // serde names the tag it could not match, other unknown variants
+        // belong to a field
+        let unknown = format!("unknown variant `{kind}`");
+        let kind = kind.to_owned();
+        serde_json::from_value(value).map_err(|e| {
+            match e.to_string().starts_with(&unknown) {
+                true => ProtocolError::UnknownMessage { kind },
+                false => malformed(e),
+            }
+        })
+    }
+}
+
+/// ICE candidate of a WebRTC signal, named like the browsers
+/// `RTCIceCandidateInit`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct IceCandidate {
+    pub candidate: String,
+    #[serde(default)]
+    pub sdp_m_line_index: Option<u16>,
+    #[serde(default)]
+    pub sdp_mid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username_fragment: Option<String>,
+}
+
+/// Messages the server sends to clients as json text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum ServerMessage {
+    #[serde(rename = "verify")]
+    Verify { verify_time: String },
+    /// Answers a hello with the version both sides use from now on
+    #[serde(rename = "hello")]
+    Hello { version: u32 },
+    /// A message was rejected, the connection stays open
+    #[serde(rename = "error")]
+    Error { reason: String, name: String },
+    #[serde(rename = "paired")]
+    Paired {
+        public_key: String,
+        partner_key: String,
+        initiator: bool,
+        seed: u64,
+        signature: String,
+        time: u64,
+        ice_servers: IceServers,
+        rules: RuleSet,
+        bot: bool,
+        /// Set when the seed comes from a commit-reveal, `seed` is only known
+        /// once both players revealed
+        commitments: Option<SeedCommitments>,
+    },
+    #[serde(rename = "seed-revealed")]
+    SeedRevealed { seed: u64, reveal: SeedReveal },
+    #[serde(rename = "partner-left")]
+    PartnerLeft,
+    #[serde(rename = "relay")]
+    Relay,
+    #[serde(rename = "partner-disconnected")]
+    PartnerDisconnected { grace_secs: u64 },
+    #[serde(rename = "partner-reconnected")]
+    PartnerReconnected,
+    #[serde(rename = "resume")]
+    Resume {
+        public_key: String,
+        partner_key: String,
+        initiator: bool,
+        seed: u64,
+        signature: String,
+        time: u64,
+        rules: RuleSet,
+        moves: Vec<HistoryItem>,
+        seed_reveal: Option<SeedReveal>,
+    },
+    /// The last message before the server closes the connection
+    #[serde(rename = "disconnected")]
+    Disconnected { reason: String, name: String },
+    #[serde(rename = "lobby-created")]
+    LobbyCreated { code: String, expires_in_secs: u64 },
+    #[serde(rename = "lobby-joined")]
+    LobbyJoined { lobby: LobbyInfo },
+    #[serde(rename = "lobby-guest-joined")]
+    LobbyGuestJoined { guest_key: String },
+    #[serde(rename = "lobby-guest-left")]
+    LobbyGuestLeft,
+    #[serde(rename = "lobby-closed")]
+    LobbyClosed { reason: String },
+    #[serde(rename = "tournament-waiting")]
+    TournamentWaiting { tournament_id: String },
+    #[serde(rename = "rematch-requested")]
+    RematchRequested,
+    #[serde(rename = "rematch-declined")]
+    RematchDeclined,
+    #[serde(rename = "live-matches")]
+    LiveMatches { matches: Vec<LiveMatch> },
+    #[serde(rename = "spectator-board")]
+    SpectatorBoard { match_id: String, board: BoardData },
+    #[serde(rename = "spectator-ended")]
+    SpectatorEnded { match_id: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate() -> IceCandidate {
+        IceCandidate {
+            candidate: "candidate:1 1 udp 2122260223 10.0.0.1 5000 typ host".to_owned(),
+            sdp_m_line_index: Some(0),
+            sdp_mid: Some("0".to_owned()),
+            username_fragment: None,
+        }
+    }
+
+    #[test]
+    fn test_every_client_message_parses() {
+        let messages = [
+            ClientMessage::Hello { version: 1 },
+            ClientMessage::Join {
+                pub_key: "key".to_owned(),
+                signature: "signature".to_owned(),
+                queue: None,
+                tournament: None,
+                lobby: Some("ABCD".to_owned()),
+                password: None,
+                rules: Some(RuleSet::default()),
+                commitment: None,
+            },
+            ClientMessage::CreateLobby {
+                pub_key: "key".to_owned(),
+                signature: "signature".to_owned(),
+                password: Some("secret".to_owned()),
+                rules: None,
+                commitment: None,
+            },
+            ClientMessage::StartLobby,
+            ClientMessage::Commit {
+                commitment: "commitment".to_owned(),
+            },
+            ClientMessage::Reveal {
+                contribution: "contribution".to_owned(),
+            },
+            ClientMessage::Rematch { commitment: None },
+            ClientMessage::DeclineRematch,
+            ClientMessage::ListLiveMatches,
+            ClientMessage::Spectate {
+                match_id: "match".to_owned(),
+            },
+            ClientMessage::StopSpectating,
+            ClientMessage::Reconnect {
+                pub_key: "key".to_owned(),
+                signature: "signature".to_owned(),
+            },
+            ClientMessage::IceCandidate {
+                candidate: candidate(),
+            },
+            ClientMessage::Offer {
+                sdp: "v=0".to_owned(),
+            },
+            ClientMessage::Answer {
+                sdp: "v=0".to_owned(),
+            },
+            ClientMessage::Candidate {
+                candidate: candidate(),
+            },
+            ClientMessage::Relay,
+        ];
+        for message in messages {
+            let text = serde_json::to_string(&message).unwrap();
+            assert_eq!(ClientMessage::parse(&text), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            ClientMessage::parse(r#"{"type":"dance"}"#),
+            Err(ProtocolError::UnknownMessage {
+                kind: "dance".to_owned()
+            })
+        );
+        // a bad variant inside a known message is not an unknown message
+        let mut lobby = serde_json::to_value(ClientMessage::CreateLobby {
+            pub_key: "key".to_owned(),
+            signature: "signature".to_owned(),
+            password: None,
+            rules: Some(RuleSet::default()),
+            commitment: None,
+        })
+        .unwrap();
+        lobby["rules"]["scoring"] = "dance".into();
+        assert!(matches!(
+            ClientMessage::parse(&lobby.to_string()),
+            Err(ProtocolError::MalformedMessage { .. })
+        ));
+        assert!(matches!(
+            ClientMessage::parse(r#"{"type":"offer"}"#),
+            Err(ProtocolError::MalformedMessage { .. })
+        ));
+        assert!(matches!(
+            ClientMessage::parse(r#"{"type":"join"}"#),
+            Err(ProtocolError::MalformedMessage { .. })
+        ));
+        assert!(matches!(
+            ClientMessage::parse(r#"{"pub_key":"key"}"#),
+            Err(ProtocolError::MalformedMessage { .. })
+        ));
+        assert!(matches!(
+            ClientMessage::parse("not json"),
+            Err(ProtocolError::MalformedMessage { .. })
+        ));
+        // older clients send the optional fields as null
+        assert_eq!(
+            ClientMessage::parse(r#"{"type":"rematch","commitment":null}"#),
+            Ok(ClientMessage::Rematch { commitment: None })
+        );
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1), Ok(PROTOCOL_VERSION));
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION), Ok(MIN_PROTOCOL_VERSION));
+        assert_eq!(
+            negotiate(0),
+            Err(ProtocolError::UnsupportedVersion {
+                version: 0,
+                min: MIN_PROTOCOL_VERSION
+            })
+        );
+    }
+}
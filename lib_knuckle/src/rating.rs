@@ -0,0 +1,248 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Converts between the Glicko scale and the internal Glicko-2 scale
+const SCALE: f64 = 173.7178;
+/// System constant, smaller values keep the volatility from jumping around
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000001;
+/// Share of the usual rating gain a player gets for a forfeited game, the
+/// game was never played out so it says less about their skill
+const FORFEIT_WEIGHT: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: 1500.0,
+            deviation: 350.0,
+            volatility: 0.06,
+        }
+    }
+}
+
+/// Result of a match from the point of view of one player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Loss,
+    Tie,
+    WinByForfeit,
+    LossByForfeit,
+}
+
+impl Outcome {
+    fn score(&self) -> f64 {
+        match self {
+            Outcome::Win | Outcome::WinByForfeit => 1.0,
+            Outcome::Loss | Outcome::LossByForfeit => 0.0,
+            Outcome::Tie => 0.5,
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Outcome::Win => Outcome::Loss,
+            Outcome::Loss => Outcome::Win,
+            Outcome::Tie => Outcome::Tie,
+            Outcome::WinByForfeit => Outcome::LossByForfeit,
+            Outcome::LossByForfeit => Outcome::WinByForfeit,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+    1.0 / (1.0 + (-g(phi_j) * (mu - mu_j)).exp())
+}
+
+impl Rating {
+    /// Glicko-2 update for one rating period with the given results, each
+    /// result is the opponent and a score of 1, 0.5 or 0
+    pub fn update(&self, results: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - 1500.0) / SCALE;
+        let phi = self.deviation / SCALE;
+        if results.is_empty() {
+            let phi = (phi * phi + self.volatility * self.volatility).sqrt();
+            return Rating {
+                deviation: phi * SCALE,
+                ..*self
+            };
+        }
+
+        let scaled = results
+            .iter()
+            .map(|(opponent, score)| {
+                let mu_j = (opponent.rating - 1500.0) / SCALE;
+                let phi_j = opponent.deviation / SCALE;
+                (g(phi_j), expected(mu, mu_j, phi_j), *score)
+            })
+            .collect::<Vec<_>>();
+        let v = 1.0
+            / scaled
+                .iter()
+                .map(|(g, e, _)| g * g * e * (1.0 - e))
+                .sum::<f64>();
+        let improvement = scaled.iter().map(|(g, e, s)| g * (s - e)).sum::<f64>();
+        let delta = v * improvement;
+
+        let sigma = self.new_volatility(phi, v, delta);
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+
+        Rating {
+            rating: mu * SCALE + 1500.0,
+            deviation: phi * SCALE,
+            volatility: sigma,
+        }
+    }
+
+    // Illinois algorithm from step 5 of the Glicko-2 paper
+    fn new_volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d)
+                - (x - a) / (TAU * TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        (big_a / 2.0).exp()
+    }
+
+    /// Rates a single match, returns the new ratings of the player and of the
+    /// opponent
+    pub fn rate_match(
+        player: Rating,
+        opponent: Rating,
+        outcome: Outcome,
+    ) -> (Rating, Rating) {
+        let rate = |me: Rating, other: Rating, outcome: Outcome| {
+            let next = me.update(&[(other, outcome.score())]);
+            match outcome {
+                Outcome::WinByForfeit => Rating {
+                    rating: me.rating + (next.rating - me.rating) * FORFEIT_WEIGHT,
+                    ..next
+                },
+                _ => next,
+            }
+        };
+        (
+            rate(player, opponent, outcome),
+            rate(opponent, player, outcome.opposite()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            volatility: 0.06,
+        }
+    }
+
+    // Example from the Glicko-2 paper by Mark Glickman
+    #[test]
+    fn test_paper_example() {
+        let player = rating(1500.0, 200.0);
+        let next = player.update(&[
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ]);
+        assert!((next.rating - 1464.06).abs() < 0.01);
+        assert!((next.deviation - 151.52).abs() < 0.01);
+        assert!((next.volatility - 0.05999).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_rate_match() {
+        let (winner, loser) =
+            Rating::rate_match(Rating::default(), Rating::default(), Outcome::Win);
+        assert!(winner.rating > 1500.0);
+        assert!(loser.rating < 1500.0);
+        assert!((winner.rating - 1500.0 - (1500.0 - loser.rating)).abs() < 0.001);
+
+        let (a, b) =
+            Rating::rate_match(Rating::default(), Rating::default(), Outcome::Tie);
+        assert!((a.rating - 1500.0).abs() < 0.001);
+        assert!((b.rating - 1500.0).abs() < 0.001);
+        assert!(a.deviation < 350.0);
+
+        // an upset tie still moves both players
+        let (low, high) =
+            Rating::rate_match(rating(1300.0, 80.0), rating(1700.0, 80.0), Outcome::Tie);
+        assert!(low.rating > 1300.0);
+        assert!(high.rating < 1700.0);
+    }
+
+    #[test]
+    fn test_forfeit() {
+        let (winner, _) =
+            Rating::rate_match(Rating::default(), Rating::default(), Outcome::Win);
+        let (forfeit_winner, forfeit_loser) = Rating::rate_match(
+            Rating::default(),
+            Rating::default(),
+            Outcome::WinByForfeit,
+        );
+        assert!(forfeit_winner.rating > 1500.0);
+        assert!(forfeit_winner.rating < winner.rating);
+        assert!(forfeit_loser.rating < 1500.0);
+
+        let (loser, winner) = Rating::rate_match(
+            Rating::default(),
+            Rating::default(),
+            Outcome::LossByForfeit,
+        );
+        assert_eq!(winner.rating, forfeit_winner.rating);
+        assert_eq!(loser.rating, forfeit_loser.rating);
+    }
+}
@@ -0,0 +1,359 @@
+use ed25519_dalek::{Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api_interfaces::GameBody,
+    error::GameError,
+    game::{BoardData, Game, HistoryItem, ServerGameInfo},
+    keys::Keys,
+    rules::RuleSet,
+    seed::{signed_seed, SeedReveal},
+    signature_from_string, verifying_key_from_string,
+};
+
+/// Self contained copy of a finished match, enough to verify and replay it
+/// without the database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct MatchRecord {
+    pub version: u16,
+    pub seed: u64,
+    pub time: u64,
+    /// Public key of the player that moved first
+    pub first_key: String,
+    pub second_key: String,
+    /// Server signature over the match parameters
+    pub signature: String,
+    pub rules: RuleSet,
+    pub moves: Vec<HistoryItem>,
+    /// Contributions the seed was derived from, none when the server picked it
+    #[serde(default)]
+    pub seed_reveal: Option<SeedReveal>,
+}
+
+impl MatchRecord {
+    /// Version 2 added the turn limit to the rules, version 3 the seed reveal
+    /// and version 4 the dice chain links of hidden dice moves
+    pub const VERSION: u16 = 4;
+
+    pub fn new(
+        seed: u64,
+        time: u64,
+        (first_key, second_key): (String, String),
+        signature: String,
+        rules: RuleSet,
+        moves: Vec<HistoryItem>,
+    ) -> Self {
+        Self {
+            version: Self::VERSION,
+            seed,
+            time,
+            first_key,
+            second_key,
+            signature,
+            rules,
+            moves,
+            seed_reveal: None,
+        }
+    }
+
+    /// The match parameters as signed by the server
+    pub fn signed_message(&self) -> Result<String, GameError> {
+        Ok(format!(
+            "{}:{}:{}:{}:{}",
+            signed_seed(self.seed, self.seed_reveal.as_ref())?,
+            self.time,
+            self.first_key,
+            self.second_key,
+            self.rules.encode()
+        ))
+    }
+
+    fn player_keys(&self) -> Result<(VerifyingKey, VerifyingKey), GameError> {
+        match (
+            verifying_key_from_string(&self.first_key),
+            verifying_key_from_string(&self.second_key),
+        ) {
+            (Some(first), Some(second)) => Ok((first, second)),
+            _ => Err(GameError::MalformedKey),
+        }
+    }
+
+    /// Checks the server signature and every move, returns the final board
+    /// from the first players point of view
+    pub fn verify(&self, server_key: &VerifyingKey) -> Result<BoardData, GameError> {
+        let signature =
+            signature_from_string(&self.signature).ok_or(GameError::InvalidSignature)?;
+        server_key
+            .verify(self.signed_message()?.as_bytes(), &signature)
+            .map_err(|_| GameError::InvalidSignature)?;
+        let mut replay = Game::replay(self)?;
+        for board in replay.by_ref() {
+            board?;
+        }
+        Ok(replay.board())
+    }
+
+    pub fn to_json(&self) -> Result<String, GameError> {
+        serde_json::to_string(self).map_err(|e| GameError::Decode {
+            reason: e.to_string(),
+        })
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, GameError> {
+        let record: Self = serde_json::from_str(data).map_err(|e| GameError::Decode {
+            reason: e.to_string(),
+        })?;
+        record.check_version()
+    }
+
+    /// Compact form, the version comes first so newer layouts can be told
+    /// apart before decoding the rest. Only meant for transfer, bincode has no
+    /// field names to fall back on so older layouts are rejected, archives
+    /// should keep the json form
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GameError> {
+        bincode::serialize(self).map_err(|e| GameError::Decode {
+            reason: e.to_string(),
+        })
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, GameError> {
+        let version: u16 = bincode::deserialize(data).map_err(|e| GameError::Decode {
+            reason: e.to_string(),
+        })?;
+        if version != Self::VERSION {
+            return Err(GameError::UnsupportedRecordVersion { version });
+        }
+        bincode::deserialize(data).map_err(|e| GameError::Decode {
+            reason: e.to_string(),
+        })
+    }
+
+    /// Older json records still parse, their rules default to untimed and
+    /// their seed to server picked
+    fn check_version(self) -> Result<Self, GameError> {
+        match self.version {
+            1..=Self::VERSION => Ok(self),
+            version => Err(GameError::UnsupportedRecordVersion { version }),
+        }
+    }
+}
+
+impl From<GameBody> for MatchRecord {
+    fn from(body: GameBody) -> Self {
+        let keys = match body.starting {
+            true => (body.your_key, body.opponent_key),
+            false => (body.opponent_key, body.your_key),
+        };
+        Self {
+            seed_reveal: body.seed_reveal,
+            ..Self::new(
+                body.seed,
+                body.time,
+                keys,
+                body.signature,
+                body.rules,
+                body.moves,
+            )
+        }
+    }
+}
+
+/// Steps through a recorded match one move at a time, yielding the board after
+/// every move
+pub struct Replay {
+    game: Game,
+    moves: std::vec::IntoIter<HistoryItem>,
+    failed: bool,
+}
+
+impl Replay {
+    /// The board after the last replayed move
+    pub fn board(&self) -> BoardData {
+        self.game.get_board_data()
+    }
+}
+
+impl Iterator for Replay {
+    type Item = Result<BoardData, GameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let item = self.moves.next()?;
+        match self.game.add_opponent_move(item) {
+            Ok(()) => Some(Ok(self.game.get_board_data())),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Game {
+    /// Replays a match from the first players point of view, moves are
+    /// verified as they are stepped through
+    pub fn replay(record: &MatchRecord) -> Result<Replay, GameError> {
+        let (my_keys, other_keys) = record.player_keys()?;
+        let game = Game::new(
+            Keys::VerifyOnly {
+                my_keys,
+                other_keys,
+            },
+            record.rules,
+            ServerGameInfo::new(record.seed, true)
+                .with_time(record.time)
+                .with_reveal(record.seed_reveal.as_ref()),
+        )?;
+        Ok(Replay {
+            game,
+            moves: record.moves.clone().into_iter(),
+            failed: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{prelude::BASE64_STANDARD_NO_PAD, Engine};
+    use ed25519_dalek::{Signer, SigningKey};
+    use rand_core::OsRng;
+
+    use super::*;
+    use crate::ai::{Ai, Strategy};
+
+    fn key_string(key: &SigningKey) -> String {
+        BASE64_STANDARD_NO_PAD.encode(key.verifying_key().to_bytes())
+    }
+
+    // Plays a full game between two random players and records it
+    fn record_game(server: &SigningKey) -> MatchRecord {
+        let mut csprng = OsRng;
+        let first_keys = SigningKey::generate(&mut csprng);
+        let second_keys = SigningKey::generate(&mut csprng);
+        let rules = RuleSet::default();
+        let mut players = [
+            Game::new(
+                Keys::Sign {
+                    my_keys: first_keys.clone(),
+                    other_keys: second_keys.verifying_key(),
+                },
+                rules,
+                ServerGameInfo::new(7, true),
+            )
+            .unwrap(),
+            Game::new(
+                Keys::Sign {
+                    my_keys: second_keys.clone(),
+                    other_keys: first_keys.verifying_key(),
+                },
+                rules,
+                ServerGameInfo::new(7, false),
+            )
+            .unwrap(),
+        ];
+        let mut ai = Ai::with_seed(Strategy::Random, 7);
+        let mut turn = 0;
+        while !players[0].is_completed() {
+            let x = ai
+                .choose_column(&players[turn % 2].get_board_data())
+                .unwrap();
+            let item = players[turn % 2].place(x).unwrap();
+            players[(turn + 1) % 2].add_opponent_move(item).unwrap();
+            turn += 1;
+        }
+
+        let mut record = MatchRecord::new(
+            7,
+            1000,
+            (key_string(&first_keys), key_string(&second_keys)),
+            String::new(),
+            rules,
+            players[0].history().to_vec(),
+        );
+        record.signature = BASE64_STANDARD_NO_PAD.encode(
+            server
+                .sign(record.signed_message().unwrap().as_bytes())
+                .to_bytes(),
+        );
+        record
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let server = SigningKey::generate(&mut OsRng);
+        let record = record_game(&server);
+        let json = record.to_json().unwrap();
+        assert_eq!(MatchRecord::from_json(&json).unwrap(), record);
+        let bytes = record.to_bytes().unwrap();
+        assert_eq!(MatchRecord::from_bytes(&bytes).unwrap(), record);
+        assert!(bytes.len() < json.len());
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let server = SigningKey::generate(&mut OsRng);
+        let record = MatchRecord {
+            version: 99,
+            ..record_game(&server)
+        };
+        assert_eq!(
+            MatchRecord::from_bytes(&record.to_bytes().unwrap()),
+            Err(GameError::UnsupportedRecordVersion { version: 99 })
+        );
+        assert_eq!(
+            MatchRecord::from_json(&record.to_json().unwrap()),
+            Err(GameError::UnsupportedRecordVersion { version: 99 })
+        );
+    }
+
+    #[test]
+    fn test_old_version_only_from_json() {
+        let server = SigningKey::generate(&mut OsRng);
+        let record = MatchRecord {
+            version: 3,
+            ..record_game(&server)
+        };
+        assert_eq!(
+            MatchRecord::from_bytes(&record.to_bytes().unwrap()),
+            Err(GameError::UnsupportedRecordVersion { version: 3 })
+        );
+        assert_eq!(
+            MatchRecord::from_json(&record.to_json().unwrap()).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn test_replay() {
+        let server = SigningKey::generate(&mut OsRng);
+        let record = record_game(&server);
+        let boards = Game::replay(&record)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(boards.len(), record.moves.len());
+        assert!(boards.last().unwrap().is_completed);
+        assert!(boards[..boards.len() - 1].iter().all(|b| !b.is_completed));
+        assert!(record.verify(&server.verifying_key()).is_ok());
+
+        let other_server = SigningKey::generate(&mut OsRng);
+        assert_eq!(
+            record.verify(&other_server.verifying_key()).err(),
+            Some(GameError::InvalidSignature)
+        );
+
+        let mut tampered = record.clone();
+        tampered.moves.swap(0, 1);
+        assert!(tampered.verify(&server.verifying_key()).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::GameError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum Scoring {
+    /// Matching dice in a column multiply, `n` dice showing `face` score
+    /// `face * n * n`
+    #[default]
+    Knucklebones,
+    /// Every die is worth its face value
+    Sum,
+}
+
+impl Scoring {
+    pub(crate) fn score(&self, face: u32, count: u32) -> u32 {
+        match self {
+            Scoring::Knucklebones => face * count * count,
+            Scoring::Sum => face * count,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Scoring::Knucklebones => "knucklebones",
+            Scoring::Sum => "sum",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "knucklebones" => Some(Scoring::Knucklebones),
+            "sum" => Some(Scoring::Sum),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub enum Cancellation {
+    /// Placing a die removes every matching die in the opponents column
+    #[default]
+    SameColumn,
+    /// Dice are never removed, casual mode
+    None,
+}
+
+impl Cancellation {
+    fn name(&self) -> &'static str {
+        match self {
+            Cancellation::SameColumn => "same_column",
+            Cancellation::None => "none",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "same_column" => Some(Cancellation::SameColumn),
+            "none" => Some(Cancellation::None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct RuleSet {
+    pub rows: usize,
+    pub columns: usize,
+    pub die_faces: u8,
+    pub scoring: Scoring,
+    pub cancellation: Cancellation,
+    /// Seconds a player has for each move, a player that runs out loses once
+    /// the opponent claims the timeout. Untimed when unset
+    #[serde(default)]
+    pub turn_limit_secs: Option<u32>,
+    /// Every die is derived from the previous move and a hash chain of the
+    /// player rolling it, so nobody learns a roll in advance. Needs both
+    /// players to commit to a seed contribution
+    #[serde(default)]
+    pub hidden_dice: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self {
+            rows: 3,
+            columns: 3,
+            die_faces: 6,
+            scoring: Scoring::Knucklebones,
+            cancellation: Cancellation::SameColumn,
+            turn_limit_secs: None,
+            hidden_dice: false,
+        }
+    }
+}
+
+impl RuleSet {
+    pub const MAX_SIDE: usize = 8;
+    pub const MAX_DIE_FACES: u8 = 20;
+    pub const TURN_LIMIT_SECS: std::ops::RangeInclusive<u32> = 5..=3600;
+
+    pub fn deck_size(&self) -> (usize, usize) {
+        (self.rows, self.columns)
+    }
+
+    pub fn turn_limit_ms(&self) -> Option<u64> {
+        self.turn_limit_secs.map(|secs| secs as u64 * 1000)
+    }
+
+    pub fn validate(&self) -> Result<(), GameError> {
+        let reason = if !(1..=Self::MAX_SIDE).contains(&self.rows) {
+            format!("rows must be between 1 and {}", Self::MAX_SIDE)
+        } else if !(1..=Self::MAX_SIDE).contains(&self.columns) {
+            format!("columns must be between 1 and {}", Self::MAX_SIDE)
+        } else if !(2..=Self::MAX_DIE_FACES).contains(&self.die_faces) {
+            format!("die faces must be between 2 and {}", Self::MAX_DIE_FACES)
+        } else if self
+            .turn_limit_secs
+            .is_some_and(|secs| !Self::TURN_LIMIT_SECS.contains(&secs))
+        {
+            format!(
+                "turn limit must be between {} and {} seconds",
+                Self::TURN_LIMIT_SECS.start(),
+                Self::TURN_LIMIT_SECS.end()
+            )
+        } else {
+            return Ok(());
+        };
+        Err(GameError::InvalidRuleSet { reason })
+    }
+
+    /// Stable string form of the rule set, this is part of the match
+    /// parameters signed by the server so changing it breaks old signatures.
+    /// The turn limit and hidden dice are only appended when set
+    pub fn encode(&self) -> String {
+        let mut encoded = format!(
+            "{}x{}d{}:{}:{}",
+            self.rows,
+            self.columns,
+            self.die_faces,
+            self.scoring.name(),
+            self.cancellation.name()
+        );
+        if let Some(secs) = self.turn_limit_secs {
+            encoded.push_str(&format!(":t{secs}"));
+        }
+        if self.hidden_dice {
+            encoded.push_str(":hidden");
+        }
+        encoded
+    }
+
+    /// Parses the output of [`RuleSet::encode`]
+    pub fn decode(data: &str) -> Result<Self, GameError> {
+        let invalid = || GameError::InvalidRuleSet {
+            reason: format!("can not parse {data:?}"),
+        };
+        let mut parts = data.split(':');
+        let (size, scoring, cancellation) =
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(size), Some(scoring), Some(cancellation)) => {
+                    (size, scoring, cancellation)
+                }
+                _ => return Err(invalid()),
+            };
+        // optional parts keep the order of `encode`
+        let mut optional = parts.peekable();
+        let turn_limit = optional.next_if(|part| part.starts_with('t'));
+        let hidden_dice = optional.next_if_eq(&"hidden").is_some();
+        if optional.next().is_some() {
+            return Err(invalid());
+        }
+        let (rows, rest) = size.split_once('x').ok_or_else(invalid)?;
+        let (columns, die_faces) = rest.split_once('d').ok_or_else(invalid)?;
+        let rules = Self {
+            rows: rows.parse().map_err(|_| invalid())?,
+            columns: columns.parse().map_err(|_| invalid())?,
+            die_faces: die_faces.parse().map_err(|_| invalid())?,
+            scoring: Scoring::from_name(scoring).ok_or_else(invalid)?,
+            cancellation: Cancellation::from_name(cancellation).ok_or_else(invalid)?,
+            turn_limit_secs: turn_limit
+                .map(|limit| {
+                    limit
+                        .strip_prefix('t')
+                        .and_then(|secs| secs.parse().ok())
+                        .ok_or_else(invalid)
+                })
+                .transpose()?,
+            hidden_dice,
+        };
+        rules.validate()?;
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            RuleSet::default().encode(),
+            "3x3d6:knucklebones:same_column"
+        );
+        let rules = RuleSet {
+            rows: 4,
+            columns: 4,
+            die_faces: 8,
+            scoring: Scoring::Sum,
+            cancellation: Cancellation::None,
+            turn_limit_secs: None,
+            hidden_dice: false,
+        };
+        assert_eq!(rules.encode(), "4x4d8:sum:none");
+        assert_eq!(RuleSet::decode(&rules.encode()), Ok(rules));
+        assert_eq!(
+            RuleSet::decode(&RuleSet::default().encode()),
+            Ok(RuleSet::default())
+        );
+        assert!(RuleSet::decode("3x3d6:knucklebones").is_err());
+        assert!(RuleSet::decode("3x3:knucklebones:none").is_err());
+        assert!(RuleSet::decode("3x3d1:knucklebones:none").is_err());
+
+        let timed = RuleSet {
+            turn_limit_secs: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(timed.encode(), "3x3d6:knucklebones:same_column:t30");
+        assert_eq!(RuleSet::decode(&timed.encode()), Ok(timed));
+        assert!(RuleSet::decode("3x3d6:knucklebones:same_column:30").is_err());
+        assert!(RuleSet::decode("3x3d6:knucklebones:same_column:t1").is_err());
+
+        let hidden = RuleSet {
+            hidden_dice: true,
+            ..timed
+        };
+        assert_eq!(hidden.encode(), "3x3d6:knucklebones:same_column:t30:hidden");
+        assert_eq!(RuleSet::decode(&hidden.encode()), Ok(hidden));
+        let hidden = RuleSet {
+            turn_limit_secs: None,
+            ..hidden
+        };
+        assert_eq!(RuleSet::decode(&hidden.encode()), Ok(hidden));
+        assert!(RuleSet::decode("3x3d6:knucklebones:same_column:hidden:t30").is_err());
+        assert!(RuleSet::decode("3x3d6:knucklebones:same_column:hidden:hidden").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(RuleSet::default().validate().is_ok());
+        let rules = RuleSet {
+            columns: 0,
+            ..Default::default()
+        };
+        assert!(rules.validate().is_err());
+        let rules = RuleSet {
+            die_faces: 1,
+            ..Default::default()
+        };
+        assert!(rules.validate().is_err());
+    }
+}
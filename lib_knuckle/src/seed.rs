@@ -0,0 +1,168 @@
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::GameError;
+
+/// Bytes of entropy every party contributes to the dice seed
+pub const CONTRIBUTION_LEN: usize = 32;
+
+/// Random contribution, base64 encoded like the keys
+pub fn new_contribution() -> String {
+    let mut contribution = [0u8; CONTRIBUTION_LEN];
+    OsRng.fill_bytes(&mut contribution);
+    STANDARD_NO_PAD.encode(contribution)
+}
+
+pub(crate) fn decode_contribution(contribution: &str) -> Result<Vec<u8>, GameError> {
+    let bytes = STANDARD_NO_PAD
+        .decode(contribution)
+        .map_err(|e| GameError::Decode {
+            reason: e.to_string(),
+        })?;
+    match bytes.len() {
+        CONTRIBUTION_LEN => Ok(bytes),
+        len => Err(GameError::MalformedContribution { len }),
+    }
+}
+
+/// Sha256 of the contribution, sent before anyone learns the other
+/// contributions
+pub fn commitment(contribution: &str) -> Result<String, GameError> {
+    Ok(STANDARD_NO_PAD.encode(Sha256::digest(decode_contribution(contribution)?)))
+}
+
+/// Checks that a commitment has the length of a sha256 hash
+pub fn validate_commitment(commitment: &str) -> Result<(), GameError> {
+    decode_contribution(commitment).map(|_| ())
+}
+
+/// Commitments of the server and both players, `first` belongs to the player
+/// that moves first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct SeedCommitments {
+    pub server: String,
+    pub first: String,
+    pub second: String,
+}
+
+impl SeedCommitments {
+    /// Takes the place of the seed in the server signed match parameters
+    pub fn encode(&self) -> String {
+        format!("{}.{}.{}", self.server, self.first, self.second)
+    }
+}
+
+/// The revealed contributions, in the same order as the commitments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    derive(tsify::Tsify)
+)]
+#[cfg_attr(
+    any(test, target_arch = "wasm32", feature = "wasm"),
+    tsify(into_wasm_abi, from_wasm_abi)
+)]
+pub struct SeedReveal {
+    pub server: String,
+    pub first: String,
+    pub second: String,
+}
+
+impl SeedReveal {
+    pub fn commitments(&self) -> Result<SeedCommitments, GameError> {
+        Ok(SeedCommitments {
+            server: commitment(&self.server)?,
+            first: commitment(&self.first)?,
+            second: commitment(&self.second)?,
+        })
+    }
+
+    /// Dice seed derived from all three contributions, kept to 32 bits like
+    /// the server picked seeds so it survives javascript numbers
+    pub fn seed(&self) -> Result<u64, GameError> {
+        let mut hasher = Sha256::new();
+        hasher.update(b"knuckle-dice-seed");
+        for contribution in [&self.server, &self.first, &self.second] {
+            hasher.update(decode_contribution(contribution)?);
+        }
+        let hash = hasher.finalize();
+        Ok(u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]) as u64)
+    }
+
+    /// Returns the seed when the contributions match the commitments
+    pub fn verify(&self, commitments: &SeedCommitments) -> Result<u64, GameError> {
+        if self.commitments()? != *commitments {
+            return Err(GameError::SeedRevealMismatch);
+        }
+        self.seed()
+    }
+}
+
+/// The seed as it appears in the server signed match parameters, seeds from a
+/// reveal are signed through the commitments
+pub fn signed_seed(seed: u64, reveal: Option<&SeedReveal>) -> Result<String, GameError> {
+    match reveal {
+        Some(reveal) if reveal.seed()? != seed => Err(GameError::SeedRevealMismatch),
+        Some(reveal) => Ok(reveal.commitments()?.encode()),
+        None => Ok(seed.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reveal() -> SeedReveal {
+        SeedReveal {
+            server: new_contribution(),
+            first: new_contribution(),
+            second: new_contribution(),
+        }
+    }
+
+    #[test]
+    fn test_reveal_matches_commitments() {
+        let reveal = reveal();
+        let commitments = reveal.commitments().unwrap();
+        assert_eq!(reveal.verify(&commitments), reveal.seed());
+
+        let swapped = SeedReveal {
+            first: reveal.second.clone(),
+            second: reveal.first.clone(),
+            ..reveal.clone()
+        };
+        assert_eq!(
+            swapped.verify(&commitments),
+            Err(GameError::SeedRevealMismatch)
+        );
+        assert_ne!(swapped.seed(), reveal.seed());
+
+        let seed = reveal.seed().unwrap();
+        assert_eq!(signed_seed(seed, Some(&reveal)), Ok(commitments.encode()));
+        assert_eq!(
+            signed_seed(seed + 1, Some(&reveal)),
+            Err(GameError::SeedRevealMismatch)
+        );
+        assert_eq!(signed_seed(seed, None), Ok(seed.to_string()));
+    }
+
+    #[test]
+    fn test_contribution_length() {
+        let short = STANDARD_NO_PAD.encode([0u8; 16]);
+        assert_eq!(
+            commitment(&short),
+            Err(GameError::MalformedContribution { len: 16 })
+        );
+        assert!(validate_commitment(&commitment(&new_contribution()).unwrap()).is_ok());
+    }
+}
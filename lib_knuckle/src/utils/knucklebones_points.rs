@@ -1,15 +1,12 @@
 use super::occurrences::count_occurrences;
+use crate::rules::Scoring;
 
-pub(crate) fn calculate_knucklebones_points(board: &[u32], width: usize) -> Vec<u32> {
-    let multiplication_table = [
-        [1, 4, 9],
-        [2, 8, 18],
-        [3, 12, 27],
-        [4, 16, 36],
-        [5, 20, 45],
-        [6, 24, 54],
-    ];
-
+pub(crate) fn calculate_knucklebones_points(
+    board: &[u32],
+    width: usize,
+    die_faces: u8,
+    scoring: Scoring,
+) -> Vec<u32> {
     let mut columns = vec![vec![]; width];
     for (i, &value) in board.iter().enumerate() {
         columns[i % width].push(value);
@@ -25,12 +22,10 @@ pub(crate) fn calculate_knucklebones_points(board: &[u32], width: usize) -> Vec<
             if key == 0 {
                 continue;
             }
-            if key > 6 {
+            if key > die_faces as u32 {
                 return vec![];
             }
-            total += multiplication_table[key as usize - 1]
-                .get(value as usize - 1)
-                .unwrap_or(&0);
+            total += scoring.score(key, value);
         }
 
         results.push(total);
@@ -43,25 +38,37 @@ pub(crate) fn calculate_knucklebones_points(board: &[u32], width: usize) -> Vec<
 mod tests {
     use super::*;
 
-    // Test only 3x3 since thats what we do in the game
+    fn default_points(board: &[u32], width: usize) -> Vec<u32> {
+        calculate_knucklebones_points(board, width, 6, Scoring::Knucklebones)
+    }
+
     #[test]
     fn test_knucklebones() {
-        let points = calculate_knucklebones_points(&[1, 0, 0, 0, 0, 0, 0, 0, 0], 3);
+        let points = default_points(&[1, 0, 0, 0, 0, 0, 0, 0, 0], 3);
         assert_eq!(points, vec![1, 0, 0]);
-        let points = calculate_knucklebones_points(&[6, 1, 2, 6, 0, 0, 6, 0, 0], 3);
+        let points = default_points(&[6, 1, 2, 6, 0, 0, 6, 0, 0], 3);
         assert_eq!(points, vec![54, 1, 2]);
-        let points = calculate_knucklebones_points(&[0, 0, 0, 0, 0, 0, 0, 0, 0], 3);
+        let points = default_points(&[0, 0, 0, 0, 0, 0, 0, 0, 0], 3);
         assert_eq!(points, vec![0, 0, 0]);
     }
 
-    // The comment above was a lie i want some tests for it
     #[test]
     fn test_invalid_knucklebones_inputs() {
-        let points = calculate_knucklebones_points(&[1, 1, 1, 1], 1);
-        assert_eq!(points, vec![0]);
-        let points = calculate_knucklebones_points(&[0, 0, 0, 0, 0, 0, 0], 2);
+        // four of a kind only happens on boards with more than 3 rows
+        let points = default_points(&[1, 1, 1, 1], 1);
+        assert_eq!(points, vec![16]);
+        let points = default_points(&[0, 0, 0, 0, 0, 0, 0], 2);
         assert_eq!(points, vec![0, 0]);
-        let points = calculate_knucklebones_points(&[7], 1);
+        let points = default_points(&[7], 1);
         assert_eq!(points, Vec::<u32>::new());
     }
+
+    #[test]
+    fn test_other_rules() {
+        let points = calculate_knucklebones_points(&[7, 8, 7, 0], 2, 8, Scoring::Sum);
+        assert_eq!(points, vec![14, 8]);
+        let points =
+            calculate_knucklebones_points(&[7, 8, 7, 0], 2, 8, Scoring::Knucklebones);
+        assert_eq!(points, vec![28, 8]);
+    }
 }
@@ -5,8 +5,15 @@ use uuid::Uuid;
 use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
 
 use crate::{
-    game::{Game, HistoryItem, ServerGameInfo},
+    ai::{Ai, Strategy},
+    dice::DiceChain,
+    error::GameError,
+    game::{BoardData, Game, HistoryItem, ServerGameInfo},
     keys::Keys,
+    protocol::PROTOCOL_VERSION,
+    record::MatchRecord,
+    rules::RuleSet,
+    seed::{self, SeedCommitments, SeedReveal},
     signing_key_from_string,
     utils::now_impl::now,
 };
@@ -35,11 +42,64 @@ pub fn sign_message(key: String, message: String) -> String {
     BASE64_STANDARD_NO_PAD.encode(signature)
 }
 
+/// Websocket protocol version to say hello with
+#[wasm_bindgen]
+pub fn protocol_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
 #[wasm_bindgen]
 pub fn random_uuid() -> String {
     Uuid::new_v4().to_string()
 }
 
+#[wasm_bindgen]
+pub fn ai_choose_column(board: BoardData, strategy: Strategy) -> Option<u16> {
+    Ai::new(strategy).choose_column(&board)
+}
+
+/// Fresh seed contribution, only its commitment is sent when joining
+#[wasm_bindgen]
+pub fn seed_contribution() -> String {
+    seed::new_contribution()
+}
+
+#[wasm_bindgen]
+pub fn seed_commitment(contribution: String) -> Option<String> {
+    seed::commitment(&contribution).ok()
+}
+
+/// Secret of a fresh dice chain for hidden dice games, keep it for reconnects
+#[wasm_bindgen]
+pub fn new_dice_chain() -> String {
+    DiceChain::new().secret()
+}
+
+/// The seed contribution to commit to when playing with hidden dice
+#[wasm_bindgen]
+pub fn dice_chain_anchor(secret: String) -> Option<String> {
+    DiceChain::from_secret(&secret)
+        .ok()
+        .map(|chain| chain.anchor())
+}
+
+/// The seed when the reveal matches the commitments from the pairing
+#[wasm_bindgen]
+pub fn verify_seed_reveal(
+    reveal: SeedReveal,
+    commitments: SeedCommitments,
+) -> Option<u64> {
+    reveal.verify(&commitments).ok()
+}
+
+/// Every board of a recorded match, for the replay viewer
+#[wasm_bindgen]
+pub fn replay_match(record: MatchRecord) -> JsValue {
+    let boards =
+        Game::replay(&record).and_then(|replay| replay.collect::<Result<Vec<_>, _>>());
+    serde_wasm_bindgen::to_value(&boards).unwrap()
+}
+
 #[wasm_bindgen]
 impl Game {
     #[wasm_bindgen(constructor)]
@@ -47,11 +107,11 @@ impl Game {
         my_key_pub: String,
         my_key_priv: String,
         other_key_pub: String,
-        deck_x: usize,
-        deck_y: usize,
+        rules: RuleSet,
         starting: bool,
         seed: u64,
-    ) -> Self {
+        time: u64,
+    ) -> Result<Game, JsValue> {
         #[cfg(feature = "debug")]
         console_error_panic_hook::set_once();
 
@@ -89,37 +149,42 @@ impl Game {
                 my_keys,
                 other_keys,
             },
-            (deck_x, deck_y),
-            ServerGameInfo { seed, starting },
+            rules,
+            ServerGameInfo::new(seed, starting).with_time(time),
         )
+        .map_err(|e| serde_wasm_bindgen::to_value(&e).unwrap())
     }
 
     pub fn now(&self) -> u64 {
         now()
     }
 
-    pub fn w_add_opponent_move(&mut self, data: Vec<u8>) -> Option<String> {
+    pub fn w_add_opponent_move(&mut self, data: Vec<u8>) -> Option<GameError> {
         let item = match bincode::deserialize::<HistoryItem>(&data) {
             Ok(item) => item,
-            Err(e) => return Some(e.to_string()),
+            Err(e) => {
+                return Some(GameError::Decode {
+                    reason: e.to_string(),
+                })
+            }
         };
 
-        match self.add_opponent_move(item) {
-            Err(e) => Some(e),
-            Ok(_) => None,
-        }
+        self.add_opponent_move(item).err()
+    }
+
+    /// Replays a move of either player, used to restore a resumed match
+    pub fn w_add_move(&mut self, item: HistoryItem) -> Option<GameError> {
+        self.add_opponent_move(item).err()
     }
 
-    pub fn w_test_place(&mut self, x: u16) -> Option<String> {
-        let item = self.test_place(x);
-        match item {
-            Err(e) => Some(e),
-            Ok(_) => None,
-        }
+    pub fn w_test_place(&mut self, x: u16) -> Option<GameError> {
+        self.test_place(x).err()
     }
 
-    pub fn w_place(&mut self, x: u16) -> Vec<u8> {
-        let item = self.place(x).unwrap();
+    pub fn w_place(&mut self, x: u16) -> Result<Vec<u8>, JsValue> {
+        let item = self
+            .place(x)
+            .map_err(|e| serde_wasm_bindgen::to_value(&e).unwrap())?;
 
         let wasm = bincode::serialize(&item).unwrap();
         console_log!("Sending Bytes {:?}", wasm);
@@ -128,11 +193,13 @@ impl Game {
             "Reparse result: {:?}",
             bincode::deserialize::<HistoryItem>(&wasm)
         );
-        wasm
+        Ok(wasm)
     }
 
-    pub fn w_forfeit(&mut self) -> Vec<u8> {
-        let item = self.forfeit();
+    pub fn w_forfeit(&mut self) -> Result<Vec<u8>, JsValue> {
+        let item = self
+            .forfeit()
+            .map_err(|e| serde_wasm_bindgen::to_value(&e).unwrap())?;
 
         let wasm = bincode::serialize(&item).unwrap();
         console_log!("Sending Bytes {:?}", wasm);
@@ -141,7 +208,29 @@ impl Game {
             "Reparse result: {:?}",
             bincode::deserialize::<HistoryItem>(&wasm)
         );
-        wasm
+        Ok(wasm)
+    }
+
+    /// The signed claim to send, none while the opponent still has time
+    pub fn w_claim_timeout(&mut self) -> Option<Vec<u8>> {
+        let item = self.claim_timeout().ok()?;
+        Some(bincode::serialize(&item).unwrap())
+    }
+
+    /// Milliseconds the player on turn has left, none in untimed games
+    pub fn w_time_left(&self) -> Option<u64> {
+        self.time_left()
+    }
+
+    /// Hidden dice games need the seed reveal and the dice chain whose anchor
+    /// was this players contribution, false for a malformed secret
+    pub fn w_set_dice_chain(&mut self, reveal: SeedReveal, secret: String) -> bool {
+        let Ok(chain) = DiceChain::from_secret(&secret) else {
+            return false;
+        };
+        self.set_seed_reveal(&reveal);
+        self.set_dice_chain(chain);
+        true
     }
 
     pub fn w_get_board_data(&self) -> JsValue {